@@ -1,16 +1,31 @@
 use anyhow::{Context as AnyhowContext, Result};
 
 use crate::{
-    app, buffer::Buffer, cursor::Cursor, input_handler::EventHandler, logger::Logger,
-    renderer::Renderer, state::State,
+    app,
+    buffer::Buffer,
+    buffer_list::{BufferList, OpenBuffer},
+    cursor::Cursor,
+    input_handler::{self, Command, EventHandler},
+    logger::Logger,
+    marks::Marks,
+    renderer::Renderer,
+    state::{Mode, State},
 };
-use std::io::Write;
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// How often the open file is checked for changes made by other programs.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
 
 pub struct Application<W: Write> {
     file_name: String,
     buffer: Buffer,
+    buffers: BufferList,
     app_state: State,
     cursor: Cursor,
+    marks: Marks,
     viewport: Viewport,
     renderer: Renderer<W>,
     event_handler: EventHandler,
@@ -19,17 +34,29 @@ pub struct Application<W: Write> {
 impl<W: Write> Application<W> {
     pub fn new(writer: W, file_name: &str) -> Self {
         Logger::log(format!("Create App"));
-        let buffer = Buffer::from_file(file_name);
-        let app_state = State::new();
+        let mut app_state = State::new();
+        let mut buffer = match Buffer::from_file(file_name) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                app_state.set_message(format!("{e:#}"));
+                Buffer::unreadable(file_name)
+            }
+        };
+        if let Some(prompt) = input_handler::swap_prompt(&buffer) {
+            app_state.set_prompt(prompt);
+        }
         let cursor = Cursor::new();
+        let marks = Marks::new(&mut buffer);
         let renderer = Renderer::new(writer, file_name);
         let viewport = Viewport::new();
 
         Self {
             file_name: file_name.to_string(),
             buffer,
+            buffers: BufferList::new(),
             app_state,
             cursor,
+            marks,
             viewport,
             renderer,
             event_handler: EventHandler::new(),
@@ -41,7 +68,9 @@ impl<W: Write> Application<W> {
         let mode = self.app_state.mode();
         let app_context = Context {
             cursor: &mut self.cursor,
+            marks: &mut self.marks,
             buffer: &mut self.buffer,
+            buffers: &mut self.buffers,
             app_state: &mut self.app_state,
             viewport: &mut self.viewport,
             file_name: &mut self.file_name,
@@ -55,24 +84,37 @@ impl<W: Write> Application<W> {
     }
 
     pub fn run(&mut self) -> Result<()> {
+        let mut last_disk_check = Instant::now();
         loop {
-            if crossterm::event::poll(std::time::Duration::from_millis(10)).unwrap() {
+            let mode = self.app_state.mode();
+            let cmd = if crossterm::event::poll(std::time::Duration::from_millis(10)).unwrap() {
                 let event = crossterm::event::read().unwrap();
-                let mode = self.app_state.mode();
-                let cmd_buffer = self.event_handler.get_cmd_buffer(mode);
+                self.app_state.clear_message();
+                Some(self.event_handler.handle(event, mode))
+            } else if last_disk_check.elapsed() >= DISK_CHECK_INTERVAL
+                && !matches!(mode, Mode::Prompt | Mode::Search)
+            {
+                last_disk_check = Instant::now();
+                Some(Command::CheckDisk)
+            } else {
+                None
+            };
 
+            if let Some(cmd) = cmd {
+                let cmd_buffer = self.event_handler.get_cmd_buffer(mode);
                 self.app_state.set_should_render(false);
 
                 let mut app_context = Some(Context {
                     cursor: &mut self.cursor,
+                    marks: &mut self.marks,
                     buffer: &mut self.buffer,
+                    buffers: &mut self.buffers,
                     app_state: &mut self.app_state,
                     viewport: &mut self.viewport,
                     file_name: &mut self.file_name,
                     cmd_buffer: &cmd_buffer,
                 });
 
-                let cmd = self.event_handler.handle(event, mode);
                 cmd.execute(&mut app_context);
 
                 if let Some(ref ctx) = app_context
@@ -81,6 +123,15 @@ impl<W: Write> Application<W> {
                     Logger::log(format!("Render!"));
                     self.renderer.render(ctx);
                 }
+                for sequence in self.app_state.take_terminal_output() {
+                    self.renderer.emit(&sequence);
+                }
+
+                self.buffer.flush_swap();
+                self.marks.sync(&mut self.buffer);
+                if let Some(line) = self.event_handler.take_ex_line() {
+                    self.app_state.registers_mut().set_last_command(line);
+                }
             }
 
             if self.app_state.should_terminate() {
@@ -91,7 +142,11 @@ impl<W: Write> Application<W> {
         Ok(())
     }
 
-    pub fn drop(&self) -> Result<()> {
+    pub fn drop(&mut self) -> Result<()> {
+        self.buffer.close_swap();
+        for open in self.buffers.parked_mut() {
+            open.buffer.close_swap();
+        }
         crossterm::terminal::disable_raw_mode()?;
 
         Ok(())
@@ -100,13 +155,85 @@ impl<W: Write> Application<W> {
 
 pub struct Context<'a> {
     pub cursor: &'a mut Cursor,
+    pub marks: &'a mut Marks,
     pub buffer: &'a mut Buffer,
+    pub buffers: &'a mut BufferList,
     pub app_state: &'a mut State,
     pub viewport: &'a mut Viewport,
     pub file_name: &'a mut String,
     pub cmd_buffer: &'a String,
 }
 
+impl<'a> Context<'a> {
+    /// Borrows the same state again, e.g. to run a nested command.
+    pub fn reborrow(&mut self) -> Context<'_> {
+        Context {
+            cursor: self.cursor,
+            marks: self.marks,
+            buffer: self.buffer,
+            buffers: self.buffers,
+            app_state: self.app_state,
+            viewport: self.viewport,
+            file_name: self.file_name,
+            cmd_buffer: self.cmd_buffer,
+        }
+    }
+
+    /// Shows buffer `id`, parking the current one with its cursor and scroll
+    /// position. Returns false if there is no other buffer with that number.
+    pub fn switch_buffer(&mut self, id: usize) -> bool {
+        self.buffer.end_change(self.cursor.pos());
+        self.buffers.switch(id, |open| {
+            std::mem::swap(self.buffer, &mut open.buffer);
+            std::mem::swap(self.cursor, &mut open.cursor);
+            std::mem::swap(self.marks, &mut open.marks);
+            std::mem::swap(&mut self.viewport.offset, &mut open.viewport_offset);
+            std::mem::swap(self.file_name, &mut open.file_name);
+        })
+    }
+
+    /// Opens `file_name` in a new buffer and shows it.
+    pub fn open_buffer(&mut self, file_name: &str) -> Result<()> {
+        let mut buffer = Buffer::from_file(file_name)?;
+        let marks = Marks::new(&mut buffer);
+        let id = self.buffers.add(OpenBuffer {
+            buffer,
+            cursor: Cursor::new(),
+            marks,
+            viewport_offset: 0,
+            file_name: file_name.to_string(),
+        });
+        self.switch_buffer(id);
+
+        Ok(())
+    }
+
+    /// `:ls` listing: number, `%` for the shown and `#` for the alternate
+    /// buffer, `+` if modified, the file name and the cursor line.
+    pub fn buffer_lines(&self) -> Vec<String> {
+        let alternate = self.buffers.alternate_id();
+        self.buffers
+            .iter()
+            .map(|(id, parked)| {
+                let (flag, buffer, cursor, file_name) = match parked {
+                    Some(open) => (
+                        if alternate == Some(id) { '#' } else { ' ' },
+                        &open.buffer,
+                        &open.cursor,
+                        &open.file_name,
+                    ),
+                    None => ('%', &*self.buffer, &*self.cursor, &*self.file_name),
+                };
+                format!(
+                    "{id:>3} {flag}{modified} \"{file_name}\" line {line}",
+                    modified = if buffer.is_modified() { '+' } else { ' ' },
+                    line = cursor.row() + 1,
+                )
+            })
+            .collect()
+    }
+}
+
 impl<'a> std::fmt::Display for Context<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -138,6 +265,22 @@ impl Viewport {
             offset: 0,
         }
     }
+
+    /// Scrolls `row` to the middle of the view if it is out of view.
+    pub fn center(&mut self, row: usize) {
+        if row < self.offset || row >= self.offset + self.height {
+            self.offset = row.saturating_sub(self.height / 2);
+        }
+    }
+
+    /// Scrolls the minimum amount needed to bring `row` into view.
+    pub fn follow(&mut self, row: usize) {
+        if row < self.offset {
+            self.offset = row;
+        } else if row >= self.offset + self.height {
+            self.offset = row + 1 - self.height;
+        }
+    }
 }
 
 pub mod config {
@@ -1,212 +1,1500 @@
-use anyhow::{Context as AnyhowContext, Error, Result};
-use std::{fs::File, io::BufReader, io::prelude::*};
+use crate::{
+    change_log::{ChangeLog, SubscriberId, TextChange},
+    cursor::Position,
+    diff::{self, DiffOp},
+    file_format::{FileFormat, LineEnding},
+    file_io::{self, DiskStamp},
+    hex,
+    history::{Edit, History, Step, Travel},
+    logger::Logger,
+    operator::Operator,
+    registers::{Register, RegisterKind},
+    swap_file::{self, Existing, Journal, SwapFile},
+    undo_file,
+    visual::{Selection, SelectionKind},
+};
+use anyhow::{Context as AnyhowContext, Result, bail};
+use encoding_rs::Encoding;
+use ropey::Rope;
+use std::{
+    fs,
+    ops::{Range, RangeInclusive},
+    path::Path,
+};
 
+/// Text store of the editor.
+///
+/// Lines are kept in a rope joined by `\n` (no trailing newline), so a buffer
+/// always has at least one line and edits and line lookups are O(log n).
+/// Columns are byte offsets into a line. Every mutation is recorded in the
+/// undo history. The on-disk layout (line endings, final newline, BOM) is kept
+/// aside in `format` and restored on save. Unsaved edits are journaled to a
+/// swap file so they survive a crash. The file's state on disk is remembered
+/// to notice when another program rewrites it.
 pub struct Buffer {
-    lines: Vec<String>,
+    text: Rope,
+    history: History,
+    format: FileFormat,
+    swap: Swap,
+    /// Edits applied since the swap file was last written.
+    journal: Vec<Edit>,
+    /// The file on disk as last read or written.
+    disk: DiskStamp,
+    /// Text matching `disk`, the common ancestor when merging external changes.
+    base: Rope,
+    /// History state the file on disk matches, if any. The buffer is modified
+    /// whenever it is somewhere else, so undoing back to it counts as clean.
+    saved: Option<usize>,
+    /// Saving is refused unless forced.
+    read_only: bool,
+    /// The file doesn't exist yet and is created by the first save.
+    new_file: bool,
+    /// Every change of the text, for consumers that follow it incrementally.
+    changes: ChangeLog,
+}
+
+/// Result of comparing the buffer's file with what is on disk now.
+pub enum DiskChange {
+    Unchanged,
+    /// Another program rewrote the file. `modified_here` tells whether the
+    /// buffer has changes of its own since it was read or saved.
+    Changed {
+        modified_here: bool,
+    },
+}
+
+/// Swap file state of a buffer.
+enum Swap {
+    Off,
+    Active(SwapFile),
+    /// A crashed session left edits behind; waiting for the user to decide.
+    Recoverable(Journal),
+    /// Another running editor is journaling this file.
+    InUse(u32),
 }
 
 impl Buffer {
-    pub fn from_file(file_path: &str) -> Self {
-        let f = File::open(file_path)
-            .with_context(|| format!("Error Opening File"))
-            .unwrap();
+    /// Loads `file_path`. A path that doesn't exist yet opens as an empty new
+    /// buffer, which creates the file on the first save. Files that can't be
+    /// written open read-only.
+    pub fn from_file(file_path: &str) -> Result<Self> {
+        let new_file = !Path::new(file_path).exists();
+        let (text, format, disk, history) = if new_file {
+            let text = Rope::new();
+            let disk = DiskStamp::new(file_path, Self::hash_of(&text));
+            (text, FileFormat::new(), disk, History::new())
+        } else {
+            let (text, format, disk) = Self::read_file(file_path)?;
+            let history =
+                undo_file::load(file_path, Self::hash_of(&text)).unwrap_or_else(History::new);
+            (text, format, disk, history)
+        };
+        let swap = Self::open_swap(file_path, &text);
+
+        Ok(Self {
+            saved: Some(history.seq()),
+            base: text.clone(),
+            text,
+            history,
+            format,
+            swap,
+            journal: Vec::new(),
+            disk,
+            read_only: !new_file && !file_io::is_writable(file_path),
+            new_file,
+            changes: ChangeLog::new(),
+        })
+    }
+
+    /// Empty read-only stand-in for a file that exists but couldn't be read,
+    /// so saving can't clobber it by accident.
+    pub fn unreadable(file_path: &str) -> Self {
+        let text = Rope::new();
+        Self {
+            saved: Some(0),
+            base: text.clone(),
+            disk: DiskStamp::new(file_path, Self::hash_of(&text)),
+            text,
+            history: History::new(),
+            format: FileFormat::new(),
+            swap: Swap::Off,
+            journal: Vec::new(),
+            read_only: true,
+            new_file: false,
+            changes: ChangeLog::new(),
+        }
+    }
+
+    fn open_swap(file_path: &str, text: &Rope) -> Swap {
+        match swap_file::inspect(file_path) {
+            Existing::Orphan(journal) => Swap::Recoverable(journal),
+            Existing::InUse(pid) => Swap::InUse(pid),
+            Existing::None => Self::start_swap(file_path, Self::hash_of(text)),
+        }
+    }
+
+    fn start_swap(file_path: &str, base_hash: u64) -> Swap {
+        match SwapFile::create(file_path, base_hash) {
+            Ok(swap_file) => Swap::Active(swap_file),
+            Err(e) => {
+                Logger::log(format!("[start_swap] {e:?}"));
+                Swap::Off
+            }
+        }
+    }
+
+    /// Pid of the crashed session whose edits can be recovered.
+    pub fn recoverable_swap(&self) -> Option<u32> {
+        match &self.swap {
+            Swap::Recoverable(journal) => Some(journal.pid),
+            _ => None,
+        }
+    }
+
+    /// Pid of another running editor that has this file open.
+    pub fn swap_in_use_by(&self) -> Option<u32> {
+        match self.swap {
+            Swap::InUse(pid) => Some(pid),
+            _ => None,
+        }
+    }
+
+    /// Replays the edits of a crashed session as one undoable change.
+    pub fn recover_swap(&mut self, file_path: &str, cursor: Position) -> Result<()> {
+        let Swap::Recoverable(journal) = &self.swap else {
+            bail!("No swap file to recover");
+        };
+
+        let base_hash = Self::hash_of(&self.text);
+        if journal.base_hash != base_hash {
+            bail!("{file_path} changed since the swap file was written. Can't recover");
+        }
+
+        let mut recovered = self.text.clone();
+        if !journal
+            .edits
+            .iter()
+            .all(|edit| Self::apply_checked(&mut recovered, edit))
+        {
+            bail!("Swap file is corrupt. Can't recover");
+        }
+
+        let Swap::Recoverable(journal) = std::mem::replace(&mut self.swap, Swap::Off) else {
+            unreachable!();
+        };
+        swap_file::discard(file_path);
+        self.swap = Self::start_swap(file_path, base_hash);
+
+        self.history.commit(cursor);
+        self.history.begin(cursor);
+        for edit in journal.edits {
+            self.record(&edit);
+            self.apply(&edit);
+        }
+        self.history.commit(cursor);
+        self.flush_swap();
+
+        Ok(())
+    }
+
+    /// Drops the edits of a crashed session and starts a fresh swap file.
+    pub fn discard_swap(&mut self, file_path: &str) {
+        if matches!(self.swap, Swap::Recoverable(_)) {
+            swap_file::discard(file_path);
+            self.swap = Self::start_swap(file_path, Self::hash_of(&self.text));
+        }
+    }
+
+    /// Keeps editing a file another editor has open, without a swap file.
+    pub fn ignore_swap_owner(&mut self) {
+        if matches!(self.swap, Swap::InUse(_)) {
+            self.swap = Swap::Off;
+        }
+    }
 
-        let buf_reader = BufReader::new(&f);
+    /// Unified diff between the file and what recovering the swap would give.
+    pub fn swap_diff(&self) -> Vec<String> {
+        let Swap::Recoverable(journal) = &self.swap else {
+            return Vec::new();
+        };
 
-        let mut buffer: Vec<String> = buf_reader
-            .lines()
-            .collect::<Result<Vec<_>, _>>()
-            .with_context(|| format!("Error Reading Buffer"))
-            .unwrap();
+        let mut recovered = self.text.clone();
+        if !journal
+            .edits
+            .iter()
+            .all(|edit| Self::apply_checked(&mut recovered, edit))
+        {
+            return vec![String::from("Swap file is corrupt")];
+        }
+
+        diff::unified(&Self::lines_of(&self.text), &Self::lines_of(&recovered))
+    }
 
-        if buffer.is_empty() {
-            buffer.push(String::new());
+    /// Appends the edits made since the last call to the swap file.
+    pub fn flush_swap(&mut self) {
+        if let Swap::Active(swap_file) = &mut self.swap
+            && !self.journal.is_empty()
+        {
+            if let Err(e) = swap_file.append(&self.journal) {
+                Logger::log(format!("[flush_swap] {e:?}"));
+            }
+            self.journal.clear();
         }
+    }
+
+    /// Restarts the journal from the current text, which was just saved.
+    pub fn reset_swap(&mut self) {
+        self.journal.clear();
+        let hash = Self::hash_of(&self.text);
+        if let Swap::Active(swap_file) = &mut self.swap
+            && let Err(e) = swap_file.reset(hash)
+        {
+            Logger::log(format!("[reset_swap] {e:?}"));
+        }
+    }
 
-        Self { lines: buffer }
+    /// Removes the swap file on a clean exit.
+    pub fn close_swap(&mut self) {
+        self.journal.clear();
+        if let Swap::Active(swap_file) = std::mem::replace(&mut self.swap, Swap::Off) {
+            swap_file.remove();
+        }
     }
 
-    pub fn replace(&mut self, file_path: &str) {
-        let f = File::open(file_path)
-            .with_context(|| format!("Error Opening File"))
-            .unwrap();
+    /// Writes the buffer to `file_path` in its original file format, atomically
+    /// replacing the old file. With `backup` the old contents are kept in `file~`.
+    pub fn write_to(&mut self, file_path: &str, backup: bool) -> Result<()> {
+        let mut bytes = Vec::with_capacity(self.len_bytes());
+        self.format
+            .encode(&mut bytes, (0..self.len()).map(|row| self.get(row)))?;
+
+        file_io::write_atomic(file_path, &bytes, backup)?;
+        self.disk = DiskStamp::new(file_path, undo_file::content_hash([&bytes]));
+        self.base = self.text.clone();
+        self.saved = Some(self.history.seq());
+        self.new_file = false;
 
-        let buf_reader = BufReader::new(&f);
+        Ok(())
+    }
+
+    /// Whether the buffer holds a hex dump of a binary file.
+    pub fn is_binary(&self) -> bool {
+        self.format.binary
+    }
 
-        let mut buffer: Vec<String> = buf_reader
-            .lines()
-            .collect::<Result<Vec<_>, _>>()
-            .with_context(|| format!("Error Reading Buffer"))
-            .unwrap();
+    /// Overwrites the byte of a hex dump under (`row`, `col`) with `ch`. See
+    /// `hex::overwrite`. Returns the column to move to.
+    pub fn overwrite_hex(&mut self, row: usize, col: usize, ch: char) -> Option<usize> {
+        let old = self.get(row);
+        let (new, next_col) = hex::overwrite(&old, col, ch)?;
 
-        if buffer.is_empty() {
-            buffer.push(String::new());
+        // Dump lines are ASCII and keep their length, so only changed columns are replaced.
+        for (i, (a, b)) in old.bytes().zip(new.bytes()).enumerate() {
+            if a != b {
+                self.remove_string(row, i, 1);
+                self.insert_string(row, i, &(b as char).to_string());
+            }
         }
 
-        self.lines = buffer;
+        Some(next_col)
+    }
+
+    /// Whole lines `first..=last` as a linewise register.
+    pub fn yank_lines(&self, first: usize, last: usize) -> Register {
+        Register::new(
+            RegisterKind::Linewise,
+            (first..=last).map(|row| self.get(row)).collect(),
+        )
+    }
+
+    /// Puts `register` after the cursor at `pos`, or before it for `P`.
+    /// Lines go below or above the cursor line, a block goes on the following
+    /// lines at the same column. Returns where the cursor should go.
+    pub fn put(&mut self, pos: Position, register: &Register, before: bool) -> Position {
+        let line = self.get(pos.row);
+        let col = if before {
+            pos.col
+        } else {
+            line[pos.col..]
+                .chars()
+                .next()
+                .map_or(pos.col, |ch| pos.col + ch.len_utf8())
+        };
+
+        match register.kind {
+            RegisterKind::Linewise => {
+                let row = if before { pos.row } else { pos.row + 1 };
+                for (i, line) in register.lines.iter().enumerate() {
+                    self.insert(row + i, line);
+                }
+                let line = self.get(row);
+                Position {
+                    row,
+                    col: line.len() - line.trim_start().len(),
+                }
+            }
+            RegisterKind::Charwise => {
+                let text = register.lines.join("\n");
+                self.insert_string(pos.row, col, &text);
+                let col = match register.lines.as_slice() {
+                    [only] => col + only.len() - only.chars().last().map_or(0, char::len_utf8),
+                    _ => col,
+                };
+                Position { row: pos.row, col }
+            }
+            RegisterKind::Blockwise => {
+                let width = register.lines.iter().map(|l| l.chars().count()).max();
+                for (i, piece) in register.lines.iter().enumerate() {
+                    let row = pos.row + i;
+                    if row == self.len() {
+                        self.insert(row, "");
+                    }
+
+                    let len = self.len_of(row);
+                    if len < col {
+                        self.insert_string(row, len, &" ".repeat(col - len));
+                    }
+                    // Keep the text after the block lined up.
+                    let piece = if len > col {
+                        format!("{piece:<width$}", width = width.unwrap_or(0))
+                    } else {
+                        piece.clone()
+                    };
+                    self.insert_string(row, col, &piece);
+                }
+                Position { row: pos.row, col }
+            }
+        }
+    }
+
+    /// The text of `selection`, as `y` in visual mode takes it.
+    pub fn yank_selection(&self, selection: &Selection) -> Register {
+        match selection.kind {
+            SelectionKind::Line => self.yank_lines(selection.start.row, selection.end.row),
+            SelectionKind::Char => {
+                let bytes = self.selection_bytes(selection);
+                Register::charwise(&self.slice(bytes.start, bytes.end))
+            }
+            SelectionKind::Block => Register::new(
+                RegisterKind::Blockwise,
+                selection
+                    .rows()
+                    .map(|row| {
+                        let line = self.get(row);
+                        line[selection.cols(row, &line)].to_string()
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Removes the text of `selection` and returns where the cursor should go.
+    pub fn delete_selection(&mut self, selection: &Selection) -> Position {
+        match selection.kind {
+            SelectionKind::Line => {
+                for _ in selection.rows() {
+                    self.remove(selection.start.row);
+                }
+                let row = selection.start.row.min(self.len() - 1);
+                let line = self.get(row);
+                Position {
+                    row,
+                    col: line.len() - line.trim_start().len(),
+                }
+            }
+            SelectionKind::Char => {
+                let bytes = self.selection_bytes(selection);
+                self.remove_at(bytes.start, bytes.end);
+                let (row, col) = self.offset_to_pos(bytes.start);
+                Position { row, col }
+            }
+            SelectionKind::Block => {
+                for row in selection.rows() {
+                    let cols = selection.cols(row, &self.get(row));
+                    self.remove_string(row, cols.start, cols.len());
+                }
+                let row = selection.start.row;
+                let line = self.get(row);
+                Position {
+                    row,
+                    col: selection.cols(row, &line).start,
+                }
+            }
+        }
+    }
+
+    /// Byte offsets of a characterwise selection, with the line break after
+    /// it if it goes past the end of its last line.
+    fn selection_bytes(&self, selection: &Selection) -> Range<usize> {
+        let (start, end) = (selection.start, selection.end);
+        let start_line = self.get(start.row);
+        let end_line = self.get(end.row);
+        let start = self.pos_to_offset(start.row, selection.cols(start.row, &start_line).start);
+        let end = if selection.takes_line_break(end.row, &end_line) && end.row + 1 < self.len() {
+            self.text.line_to_byte(end.row + 1)
+        } else {
+            self.pos_to_offset(end.row, selection.cols(end.row, &end_line).end)
+        };
+        start..end
+    }
+
+    /// Indents lines `first..=last` by one level of four spaces, or removes up
+    /// to that much indentation if `outdent`. Empty lines are left alone.
+    pub fn shift_lines(&mut self, first: usize, last: usize, outdent: bool) {
+        for row in first..=last {
+            let line = self.get(row);
+            if outdent {
+                let indent = line.len() - line.trim_start_matches(' ').len();
+                self.remove_string(row, 0, indent.min(4));
+            } else if !line.is_empty() {
+                self.insert_string(row, 0, "    ");
+            }
+        }
+    }
+
+    /// Changes the case of `selection` as `operator`, one of `gu`, `gU` and
+    /// `g~`, does.
+    pub fn change_case(&mut self, selection: &Selection, operator: Operator) {
+        for row in selection.rows() {
+            let line = self.get(row);
+            let cols = selection.cols(row, &line);
+            let text = &line[cols.clone()];
+            let changed = operator.change_case(text);
+            if changed != text {
+                self.remove_string(row, cols.start, cols.len());
+                self.insert_string(row, cols.start, &changed);
+            }
+        }
+    }
+
+    /// Indents lines `first..=last` for `=`: like the line above, one level
+    /// deeper after an opening bracket and one less before a closing one.
+    pub fn reindent_lines(&mut self, first: usize, last: usize) {
+        let indent_of = |line: &str| line.len() - line.trim_start().len();
+        let mut above = (0..first)
+            .rev()
+            .map(|row| self.get(row))
+            .find(|line| !line.trim().is_empty());
+
+        for row in first..=last {
+            let line = self.get(row);
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            let mut indent = above.as_deref().map_or(0, |above| {
+                let opens = above.trim_end().ends_with(['{', '(', '[']);
+                indent_of(above) + if opens { 4 } else { 0 }
+            });
+            if trimmed.starts_with(['}', ')', ']']) {
+                indent = indent.saturating_sub(4);
+            }
+            if indent != indent_of(&line) {
+                self.remove_string(row, 0, indent_of(&line));
+                self.insert_string(row, 0, &" ".repeat(indent));
+            }
+            above = Some(self.get(row));
+        }
+    }
+
+    /// Inserts `text` at byte column `col` of each of `rows`, as block `I` and
+    /// `A` repeat what was typed on the first row. Shorter lines are padded
+    /// with spaces if `pad`, otherwise skipped.
+    pub fn insert_block(&mut self, rows: RangeInclusive<usize>, col: usize, text: &str, pad: bool) {
+        for row in rows {
+            let line = self.get(row);
+            if line.len() < col {
+                if pad {
+                    self.insert_string(row, line.len(), &" ".repeat(col - line.len()));
+                } else {
+                    continue;
+                }
+            } else if !line.is_char_boundary(col) {
+                continue;
+            }
+            self.insert_string(row, col, text);
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn is_new_file(&self) -> bool {
+        self.new_file
+    }
+
+    /// Whether there are changes since the file was last read or saved.
+    pub fn is_modified(&self) -> bool {
+        self.history.has_pending() || self.saved != Some(self.history.seq())
+    }
+
+    /// Compares `file_path` on disk with the state it was last read or saved in.
+    /// Files that were only touched, or can't be read, count as unchanged.
+    pub fn check_disk(&mut self, file_path: &str) -> DiskChange {
+        if self.disk.metadata_matches(file_path) {
+            return DiskChange::Unchanged;
+        }
+        let Ok(bytes) = fs::read(file_path) else {
+            return DiskChange::Unchanged;
+        };
+
+        let hash = undo_file::content_hash([&bytes]);
+        if hash == self.disk.hash {
+            self.disk = DiskStamp::new(file_path, hash);
+            return DiskChange::Unchanged;
+        }
+
+        DiskChange::Changed {
+            modified_here: self.is_modified(),
+        }
+    }
+
+    /// Replaces the buffer with the file on disk as one undoable change.
+    pub fn reload_from_disk(&mut self, file_path: &str, cursor: Position) -> Result<()> {
+        let (text, format, disk) = Self::read_file(file_path)?;
+        self.replace_lines(&Self::lines_of(&text), cursor);
+        self.format = format;
+        self.accept_disk(text, disk);
+        self.reset_swap();
+
+        Ok(())
+    }
+
+    /// Merges the changes made on disk into the buffer's own changes, as one
+    /// undoable change. Returns how many conflicts were left marked in the text.
+    pub fn merge_with_disk(&mut self, file_path: &str, cursor: Position) -> Result<usize> {
+        let (theirs, _, disk) = Self::read_file(file_path)?;
+        let (merged, conflicts) = diff::merge3(
+            &Self::lines_of(&self.base),
+            &Self::lines_of(&self.text),
+            &Self::lines_of(&theirs),
+        );
+
+        self.replace_lines(&merged, cursor);
+        self.accept_disk(theirs, disk);
+
+        Ok(conflicts)
+    }
+
+    /// Keeps the buffer as it is, so the next save overwrites the file on disk.
+    pub fn keep_mine(&mut self, file_path: &str) -> Result<()> {
+        let (theirs, _, disk) = Self::read_file(file_path)?;
+        self.accept_disk(theirs, disk);
+
+        Ok(())
+    }
+
+    /// Preview of the file on disk against the buffer: a unified diff, or the
+    /// three-way merge with conflict markers when both sides changed.
+    pub fn disk_diff(&self, file_path: &str) -> Vec<String> {
+        let theirs = match Self::read_file(file_path) {
+            Ok((theirs, _, _)) => theirs,
+            Err(e) => return vec![format!("{e:#}")],
+        };
+
+        let (base, mine, theirs) = (
+            Self::lines_of(&self.base),
+            Self::lines_of(&self.text),
+            Self::lines_of(&theirs),
+        );
+        if mine == base {
+            diff::unified(&mine, &theirs)
+        } else {
+            diff::merge3(&base, &mine, &theirs).0
+        }
+    }
+
+    /// Remembers the file on disk as the new common ancestor.
+    fn accept_disk(&mut self, text: Rope, disk: DiskStamp) {
+        self.saved = (self.text == text).then(|| self.history.seq());
+        self.base = text;
+        self.disk = disk;
+    }
+
+    /// Turns the text into `lines` as one change of its own. Only the lines
+    /// that differ are touched, so marks on the rest stay where they are.
+    fn replace_lines(&mut self, lines: &[String], cursor: Position) {
+        // Runs of differing lines as (old rows, new rows).
+        let mut hunks = Vec::new();
+        let (mut old_row, mut new_row) = (0, 0);
+        let mut hunk_start = None;
+        for op in diff::diff_lines(&Self::lines_of(&self.text), lines) {
+            match op {
+                DiffOp::Equal { .. } => {
+                    if let Some((old_start, new_start)) = hunk_start.take() {
+                        hunks.push((old_start..old_row, new_start..new_row));
+                    }
+                    old_row += 1;
+                    new_row += 1;
+                }
+                DiffOp::Delete { .. } => {
+                    hunk_start.get_or_insert((old_row, new_row));
+                    old_row += 1;
+                }
+                DiffOp::Insert { .. } => {
+                    hunk_start.get_or_insert((old_row, new_row));
+                    new_row += 1;
+                }
+            }
+        }
+        if let Some((old_start, new_start)) = hunk_start {
+            hunks.push((old_start..old_row, new_start..new_row));
+        }
+
+        self.history.commit(cursor);
+        self.history.begin(cursor);
+        // Bottom-up, so the rows of the hunks above stay valid.
+        for (old, new) in hunks.into_iter().rev() {
+            let paired = old.len().min(new.len());
+            for i in 0..paired {
+                let row = old.start + i;
+                let len = self.line_len(row);
+                if len > 0 {
+                    self.remove_string(row, 0, len);
+                }
+                if !lines[new.start + i].is_empty() {
+                    self.insert_string(row, 0, &lines[new.start + i]);
+                }
+            }
+            for _ in paired..old.len() {
+                self.remove(old.start + paired);
+            }
+            for (i, line) in lines[new.start + paired..new.end].iter().enumerate() {
+                self.insert(old.start + paired + i, line);
+            }
+        }
+        self.history.commit(cursor);
+    }
+
+    fn lines_of(text: &Rope) -> Vec<String> {
+        text.lines()
+            .map(|line| line.to_string().trim_end_matches('\n').to_string())
+            .collect()
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.format.line_ending
+    }
+
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.format.line_ending = line_ending;
+    }
+
+    pub fn encoding_name(&self) -> String {
+        self.format.encoding_name()
+    }
+
+    /// Changes the encoding the buffer is saved in. The text itself is kept.
+    pub fn set_encoding(&mut self, encoding: &'static Encoding) {
+        self.format.encoding = encoding;
+        // A BOM only makes sense for Unicode encodings.
+        self.format.bom &= matches!(encoding.name(), "UTF-8" | "UTF-16LE" | "UTF-16BE");
+    }
+
+    /// Writes the undo history next to the current text, to be restored when
+    /// `file_path` is opened again unchanged.
+    pub fn save_undo(&self, file_path: &str) {
+        if let Err(e) = undo_file::save(file_path, Self::hash_of(&self.text), &self.history) {
+            Logger::log(format!("[save_undo] {e:?}"));
+        }
+    }
+
+    fn hash_of(text: &Rope) -> u64 {
+        undo_file::content_hash(text.chunks())
+    }
+
+    fn read_file(file_path: &str) -> Result<(Rope, FileFormat, DiskStamp)> {
+        let bytes = fs::read(file_path).with_context(|| format!("Can't open {file_path}"))?;
+        let disk = DiskStamp::new(file_path, undo_file::content_hash([&bytes]));
+        let (text, format) =
+            FileFormat::decode(&bytes).with_context(|| "Error Reading Buffer".to_string())?;
+
+        Ok((Rope::from_str(&text), format, disk))
     }
 
     pub fn len(&self) -> usize {
-        self.lines.len()
+        self.text.len_lines()
     }
 
     pub fn len_of(&self, row: usize) -> usize {
-        if row >= self.lines.len() {
+        if row >= self.len() {
             panic!(
                 "[len_of] row out-of-bound. [ buffer_len:{}, row:{} ]",
-                self.lines.len(),
+                self.len(),
                 row
             );
         }
 
-        self.lines[row].len()
+        self.line_len(row)
     }
 
-    pub fn get(&self, row: usize) -> &String {
-        if row >= self.lines.len() {
+    pub fn get(&self, row: usize) -> String {
+        if row >= self.len() {
             panic!(
                 "[get] row out-of-bound. [ buffer_len:{}, row:{} ]",
-                self.lines.len(),
+                self.len(),
                 row
             );
         }
 
-        &self.lines[row]
+        let start = self.text.line_to_byte(row);
+        self.slice(start, start + self.line_len(row))
     }
 
     pub fn get_string(&self, row: usize, col: usize, size: usize) -> String {
-        if row >= self.lines.len() {
+        if row >= self.len() {
             panic!(
                 "[get_string] row out-of-bound. [ buffer_len:{}, row:{} ]",
-                self.lines.len(),
+                self.len(),
                 row
             );
-        } else if col > self.lines[row].len() {
+        } else if col > self.line_len(row) {
             panic!(
                 "[get_string] col out-of-bound. [ line_len:{}, col:{} ]",
-                self.lines[row].len(),
+                self.line_len(row),
                 col
             );
-        } else if col + size > self.lines[row].len() {
+        } else if col + size > self.line_len(row) {
             panic!(
                 "[get_string] size out-of-bound. [ line_len:{}, col:{}, size:{} ]",
-                self.lines[row].len(),
+                self.line_len(row),
                 col,
                 size,
             )
         }
 
-        let (_, target) = self.lines[row].split_at(col);
-        let (target, _) = target.split_at(size);
-
-        target.to_string()
+        let start = self.pos_to_offset(row, col);
+        self.slice(start, start + size)
     }
 
-    pub fn insert(&mut self, row: usize, string: &String) {
-        if row >= self.lines.len() {
+    /// Inserts `string` as a new line at `row`. `row == len()` appends a line.
+    pub fn insert(&mut self, row: usize, string: &str) {
+        if row > self.len() {
             panic!(
                 "[insert] row out-of-bound. [ buffer_len:{}, row:{} ]",
-                self.lines.len(),
+                self.len(),
                 row
             );
         }
 
-        self.lines.insert(row, string.clone());
+        if row == self.len() {
+            let end = self.text.len_bytes();
+            self.insert_at(end, format!("\n{string}"));
+        } else {
+            let start = self.text.line_to_byte(row);
+            self.insert_at(start, format!("{string}\n"));
+        }
     }
 
     pub fn insert_char(&mut self, row: usize, col: usize, ch: char) {
-        if row >= self.lines.len() {
+        if row >= self.len() {
             panic!(
                 "[insert_char] row out-of-bound. [ buffer_len:{}, row:{} ]",
-                self.lines.len(),
+                self.len(),
                 row
             );
-        } else if col > self.lines[row].len() {
+        } else if col > self.line_len(row) {
             panic!(
                 "[insert_char] col out-of-bound. [ line_len:{}, col:{} ]",
-                self.lines[row].len(),
+                self.line_len(row),
                 col
             );
         }
 
-        self.lines[row].insert(col, ch);
+        self.insert_at(self.pos_to_offset(row, col), ch.to_string());
     }
 
-    pub fn insert_string(&mut self, row: usize, col: usize, string: &String) {
-        if row >= self.lines.len() {
+    pub fn insert_string(&mut self, row: usize, col: usize, string: &str) {
+        if row >= self.len() {
             panic!(
                 "[insert_string] row out-of-bound. [ buffer_len:{}, row:{} ]",
-                self.lines.len(),
+                self.len(),
                 row
             );
-        } else if col > self.lines[row].len() {
+        } else if col > self.line_len(row) {
             panic!(
                 "[insert_string] col out-of-bound. [ line_len:{}, col:{} ]",
-                self.lines[row].len(),
+                self.line_len(row),
                 col
             );
         }
 
-        let (front, rear) = self.lines[row].split_at(col);
-
-        self.lines[row] = front.to_string() + string + rear;
+        self.insert_at(self.pos_to_offset(row, col), string.to_string());
     }
 
     pub fn remove(&mut self, row: usize) {
-        if row >= self.lines.len() {
+        if row >= self.len() {
             panic!(
                 "[remove] row out-of-bound. [ buffer_len:{}, row:{} ]",
-                self.lines.len(),
+                self.len(),
                 row
             );
         }
 
-        self.lines.remove(row);
+        // Removing the only line leaves a single empty one.
+        let (start, end) = if row + 1 < self.len() {
+            (self.text.line_to_byte(row), self.text.line_to_byte(row + 1))
+        } else if row > 0 {
+            (self.text.line_to_byte(row) - 1, self.text.len_bytes())
+        } else {
+            (0, self.text.len_bytes())
+        };
+
+        self.remove_at(start, end);
     }
 
     pub fn remove_char(&mut self, row: usize, col: usize) {
-        if row >= self.lines.len() {
+        if row >= self.len() {
             panic!(
                 "[remove_char] row out-of-bound. [ buffer_len:{}, row:{} ]",
-                self.lines.len(),
+                self.len(),
                 row
             );
-        } else if col >= self.lines[row].len() {
+        } else if col >= self.line_len(row) {
             panic!(
                 "[remove_char] col out-of-bound. [ line_len:{}, col:{} ]",
-                self.lines[row].len(),
+                self.line_len(row),
                 col
             );
         }
 
-        self.lines[row].remove(col);
+        let start = self.pos_to_offset(row, col);
+        let char_idx = self.text.byte_to_char(start);
+        let end = self.text.char_to_byte(char_idx + 1);
+        self.remove_at(start, end);
     }
 
     pub fn remove_string(&mut self, row: usize, col: usize, size: usize) {
-        if row >= self.lines.len() {
+        if row >= self.len() {
             panic!(
                 "[remove_string] row out-of-bound. [ buffer_len:{}, row:{} ]",
-                self.lines.len(),
+                self.len(),
                 row
             );
-        } else if col > self.lines[row].len() {
+        } else if col > self.line_len(row) {
             panic!(
                 "[remove_string] col out-of-bound. [ line_len:{}, col:{} ]",
-                self.lines[row].len(),
+                self.line_len(row),
                 col
             );
-        } else if col + size > self.lines[row].len() {
+        } else if col + size > self.line_len(row) {
             panic!(
                 "[remove_string] size out-of-bound. [ line_len:{}, col:{}, size:{} ]",
-                self.lines[row].len(),
+                self.line_len(row),
                 col,
                 size,
             )
         }
 
-        let (front, tmp_rear) = self.lines[row].split_at(col);
-        let (_, rear) = tmp_rear.split_at(size);
+        let start = self.pos_to_offset(row, col);
+        self.remove_at(start, start + size);
+    }
+
+    /// Converts a (row, col) position into a byte offset from the start of the buffer.
+    pub fn pos_to_offset(&self, row: usize, col: usize) -> usize {
+        if row >= self.len() {
+            panic!(
+                "[pos_to_offset] row out-of-bound. [ buffer_len:{}, row:{} ]",
+                self.len(),
+                row
+            );
+        }
+
+        self.text.line_to_byte(row) + col
+    }
+
+    /// Converts a byte offset from the start of the buffer into a (row, col) position.
+    pub fn offset_to_pos(&self, offset: usize) -> (usize, usize) {
+        if offset > self.text.len_bytes() {
+            panic!(
+                "[offset_to_pos] offset out-of-bound. [ buffer_bytes:{}, offset:{} ]",
+                self.text.len_bytes(),
+                offset
+            );
+        }
+
+        let row = self.text.byte_to_line(offset);
+        (row, offset - self.text.line_to_byte(row))
+    }
+
+    /// Total size of the buffer in bytes, counting `\n` between lines.
+    pub fn len_bytes(&self) -> usize {
+        self.text.len_bytes()
+    }
+
+    /// The whole text, lines joined by `\n`.
+    pub fn text(&self) -> String {
+        self.text.to_string()
+    }
+
+    /// Opens an undo step starting at `cursor`, if none is open yet.
+    pub fn begin_change(&mut self, cursor: Position) {
+        self.history.begin(cursor);
+    }
+
+    /// Closes the open undo step, remembering `cursor` for redo.
+    pub fn end_change(&mut self, cursor: Position) {
+        self.history.commit(cursor);
+    }
+
+    /// Opens an undo step that the commands run until `end_group` add to
+    /// instead of closing it.
+    pub fn begin_group(&mut self, cursor: Position) {
+        self.history.begin_group(cursor);
+    }
+
+    pub fn end_group(&mut self, cursor: Position) {
+        self.history.end_group(cursor);
+    }
+
+    /// Reverts the last change and returns the cursor position before it.
+    pub fn undo(&mut self, cursor: Position) -> Option<Position> {
+        self.history.commit(cursor);
+        let steps = self.history.undo();
+        self.travel(&steps)
+    }
+
+    /// Reapplies the last undone change and returns the cursor position after it.
+    pub fn redo(&mut self, cursor: Position) -> Option<Position> {
+        self.history.commit(cursor);
+        let steps = self.history.redo();
+        self.travel(&steps)
+    }
+
+    /// Moves `delta` states through the history in chronological order (`g-`/`g+`).
+    pub fn step_history(&mut self, cursor: Position, delta: isize) -> Option<Position> {
+        self.history.commit(cursor);
+        let steps = self.history.step_seq(delta);
+        self.travel(&steps)
+    }
+
+    pub fn earlier(&mut self, cursor: Position, travel: Travel) -> Option<Position> {
+        self.history.commit(cursor);
+        let steps = self.history.earlier(travel);
+        self.travel(&steps)
+    }
+
+    pub fn later(&mut self, cursor: Position, travel: Travel) -> Option<Position> {
+        self.history.commit(cursor);
+        let steps = self.history.later(travel);
+        self.travel(&steps)
+    }
+
+    /// Starts collecting the changes made from now on, to be fetched with
+    /// `drain_changes`. Edits, undo and redo are all reported.
+    pub fn subscribe(&mut self) -> SubscriberId {
+        self.changes.subscribe()
+    }
+
+    /// Changes made since the subscriber last asked, oldest first.
+    pub fn drain_changes(&mut self, id: SubscriberId) -> Vec<TextChange> {
+        self.changes.drain(id)
+    }
+
+    /// Stops collecting changes for the subscriber.
+    pub fn unsubscribe(&mut self, id: SubscriberId) {
+        self.changes.unsubscribe(id);
+    }
+
+    pub fn history_seq(&self) -> usize {
+        self.history.seq()
+    }
+
+    pub fn undo_tree(&self) -> Vec<String> {
+        self.history.tree_lines()
+    }
+
+    /// Applies undo-tree steps and returns where the cursor should land.
+    fn travel(&mut self, steps: &[Step]) -> Option<Position> {
+        let mut pos = None;
+        for step in steps {
+            let (edits, cursor) = match *step {
+                Step::Revert(id) => {
+                    let change = self.history.change(id);
+                    let edits: Vec<Edit> = change.edits.iter().rev().map(Edit::inverse).collect();
+                    (edits, change.cursor_before)
+                }
+                Step::Apply(id) => {
+                    let change = self.history.change(id);
+                    (change.edits.clone(), change.cursor_after)
+                }
+            };
+            for edit in &edits {
+                self.apply(edit);
+            }
+            pos = Some(cursor);
+        }
+
+        pos
+    }
+
+    fn insert_at(&mut self, offset: usize, text: String) {
+        let edit = Edit::Insert { offset, text };
+        self.record(&edit);
+        self.apply(&edit);
+    }
+
+    fn remove_at(&mut self, start: usize, end: usize) {
+        let edit = Edit::Remove {
+            offset: start,
+            text: self.slice(start, end),
+        };
+        self.record(&edit);
+        self.apply(&edit);
+    }
+
+    fn record(&mut self, edit: &Edit) {
+        let offset = match edit {
+            Edit::Insert { offset, .. } | Edit::Remove { offset, .. } => *offset,
+        };
+        let (row, col) = self.offset_to_pos(offset);
+        self.history.record(edit.clone(), Position { row, col });
+    }
+
+    fn apply(&mut self, edit: &Edit) {
+        let (start_byte, old_end_byte, text) = match edit {
+            Edit::Insert { offset, text } => (*offset, *offset, text.clone()),
+            Edit::Remove { offset, text } => (*offset, *offset + text.len(), String::new()),
+        };
+        let start = self.point(start_byte);
+        let old_end = self.point(old_end_byte);
+
+        Self::apply_to(&mut self.text, edit);
+        if matches!(self.swap, Swap::Active(_)) {
+            self.journal.push(edit.clone());
+        }
+
+        let new_end_byte = start_byte + text.len();
+        self.changes.push(TextChange {
+            revision: 0,
+            start_byte,
+            old_end_byte,
+            new_end_byte,
+            start,
+            old_end,
+            new_end: self.point(new_end_byte),
+            text,
+        });
+    }
+
+    fn point(&self, offset: usize) -> Position {
+        let (row, col) = self.offset_to_pos(offset);
+        Position { row, col }
+    }
+
+    /// Applies an edit read from disk, refusing ones that don't fit the text.
+    fn apply_checked(rope: &mut Rope, edit: &Edit) -> bool {
+        let on_boundary = |offset: usize| {
+            offset <= rope.len_bytes() && rope.char_to_byte(rope.byte_to_char(offset)) == offset
+        };
+        let valid = match edit {
+            Edit::Insert { offset, .. } => on_boundary(*offset),
+            Edit::Remove { offset, text } => {
+                on_boundary(*offset)
+                    && on_boundary(*offset + text.len())
+                    && rope
+                        .byte_slice(*offset..*offset + text.len())
+                        .chars()
+                        .eq(text.chars())
+            }
+        };
+
+        if valid {
+            Self::apply_to(rope, edit);
+        }
+        valid
+    }
+
+    fn apply_to(rope: &mut Rope, edit: &Edit) {
+        match edit {
+            Edit::Insert { offset, text } => {
+                let char_idx = rope.byte_to_char(*offset);
+                rope.insert(char_idx, text);
+            }
+            Edit::Remove { offset, text } => {
+                let start = rope.byte_to_char(*offset);
+                let end = rope.byte_to_char(*offset + text.len());
+                rope.remove(start..end);
+            }
+        }
+    }
+
+    fn line_len(&self, row: usize) -> usize {
+        let line = self.text.line(row);
+        let len = line.len_bytes();
+        if row + 1 < self.len() { len - 1 } else { len }
+    }
+
+    fn slice(&self, start: usize, end: usize) -> String {
+        let start = self.text.byte_to_char(start);
+        let end = self.text.byte_to_char(end);
+        self.text.slice(start..end).to_string()
+    }
+}
+
+#[cfg(test)]
+pub mod test_util {
+    use super::*;
+
+    /// An unsaved buffer of `text`, for tests here and in other modules.
+    pub fn buffer_of(text: &str) -> Buffer {
+        Buffer {
+            text: Rope::from_str(text),
+            history: History::new(),
+            format: FileFormat::new(),
+            swap: Swap::Off,
+            journal: Vec::new(),
+            disk: DiskStamp {
+                mtime: None,
+                size: 0,
+                hash: 0,
+            },
+            base: Rope::from_str(text),
+            saved: Some(0),
+            read_only: false,
+            new_file: false,
+            changes: ChangeLog::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Mode;
+    use test_util::buffer_of;
+
+    #[test]
+    fn subscribers_get_every_change() {
+        let mut buffer = buffer_of("ab\ncd");
+        let id = buffer.subscribe();
+        let cursor = Position { row: 0, col: 0 };
+
+        buffer.begin_change(cursor);
+        buffer.insert_string(1, 1, "X\nY");
+        buffer.end_change(cursor);
+        buffer.undo(cursor);
+
+        let changes = buffer.drain_changes(id);
+        assert_eq!(changes.len(), 2);
+        let insert = &changes[0];
+        assert_eq!(insert.revision, 1);
+        assert_eq!(
+            (insert.start_byte, insert.old_end_byte, insert.new_end_byte),
+            (4, 4, 7)
+        );
+        assert_eq!((insert.start.row, insert.start.col), (1, 1));
+        assert_eq!((insert.new_end.row, insert.new_end.col), (2, 1));
+        assert_eq!(insert.text, "X\nY");
+
+        let undo = &changes[1];
+        assert_eq!(
+            (undo.start_byte, undo.old_end_byte, undo.new_end_byte),
+            (4, 7, 4)
+        );
+        assert_eq!((undo.old_end.row, undo.old_end.col), (2, 1));
+        assert_eq!(undo.revision, 2);
+        assert!(buffer.drain_changes(id).is_empty());
+
+        buffer.unsubscribe(id);
+        buffer.begin_change(cursor);
+        buffer.insert_string(0, 0, "Z");
+        buffer.end_change(cursor);
+        assert!(buffer.drain_changes(id).is_empty());
+        assert_eq!(buffer.subscribe(), id);
+    }
+
+    #[test]
+    fn undoing_to_the_saved_state_is_clean() {
+        let mut buffer = buffer_of("a");
+        let cursor = Position { row: 0, col: 0 };
+        assert!(!buffer.is_modified());
+
+        buffer.begin_change(cursor);
+        buffer.insert_char(0, 1, 'b');
+        assert!(buffer.is_modified());
+        buffer.end_change(cursor);
+        assert!(buffer.is_modified());
+
+        buffer.undo(cursor);
+        assert!(!buffer.is_modified());
+        buffer.redo(cursor);
+        assert!(buffer.is_modified());
+    }
+
+    #[test]
+    fn puts_each_kind_of_register() {
+        let at = |row, col| Position { row, col };
+        let lines = |text: &str| text.split('\n').map(String::from).collect();
+
+        let mut buffer = buffer_of("abc\ndef");
+        let pos = buffer.put(at(0, 1), &Register::charwise("XY"), false);
+        assert_eq!(
+            (buffer.text.to_string(), pos),
+            ("abXYc\ndef".into(), at(0, 3))
+        );
+
+        let pos = buffer.put(at(1, 0), &buffer.yank_lines(0, 0), true);
+        assert_eq!(
+            (buffer.text.to_string(), pos),
+            ("abXYc\nabXYc\ndef".into(), at(1, 0))
+        );
+
+        let mut buffer = buffer_of("a\nbcd");
+        let block = Register::new(RegisterKind::Blockwise, lines("1\n22\n3"));
+        buffer.put(at(0, 0), &block, false);
+        assert_eq!(buffer.text.to_string(), "a1\nb22cd\n 3");
+    }
+
+    #[test]
+    fn merges_external_changes() {
+        let path = std::env::temp_dir().join(format!("hoditor-merge-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "a\nb\nC\n").unwrap();
+
+        let mut buffer = buffer_of("a\nb\nc");
+        let cursor = Position { row: 0, col: 0 };
+        buffer.begin_change(cursor);
+        buffer.remove_char(0, 0);
+        buffer.insert_char(0, 0, 'A');
+        buffer.end_change(cursor);
+
+        assert!(matches!(
+            buffer.check_disk(path),
+            DiskChange::Changed {
+                modified_here: true
+            }
+        ));
+        assert_eq!(buffer.merge_with_disk(path, cursor).unwrap(), 0);
+        assert_eq!(buffer.text.to_string(), "A\nb\nC");
+        assert!(matches!(buffer.check_disk(path), DiskChange::Unchanged));
+
+        buffer.undo(cursor);
+        assert_eq!(buffer.text.to_string(), "A\nb\nc");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn lines_are_split_on_newline() {
+        let buffer = buffer_of("ab\n\ncd");
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.get(0), "ab");
+        assert_eq!(buffer.get(1), "");
+        assert_eq!(buffer.len_of(2), 2);
+    }
+
+    #[test]
+    fn insert_and_remove_lines() {
+        let mut buffer = buffer_of("a\nb");
+        buffer.insert(2, "c");
+        buffer.insert(0, "z");
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer.get(0), "z");
+        assert_eq!(buffer.get(3), "c");
+
+        buffer.remove(3);
+        buffer.remove(0);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.get(0), "a");
+        assert_eq!(buffer.get(1), "b");
+
+        buffer.remove(1);
+        buffer.remove(0);
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.get(0), "");
+    }
+
+    #[test]
+    fn edits_within_line() {
+        let mut buffer = buffer_of("hello\nworld");
+        buffer.insert_char(1, 0, 'W');
+        buffer.remove_char(1, 1);
+        buffer.insert_string(0, 5, ", there");
+        buffer.remove_string(0, 0, 1);
+        assert_eq!(buffer.get(0), "ello, there");
+        assert_eq!(buffer.get(1), "World");
+        assert_eq!(buffer.get_string(0, 6, 5), "there");
+    }
+
+    #[test]
+    fn offset_round_trip() {
+        let buffer = buffer_of("ab\ncde\n\nf");
+        assert_eq!(buffer.pos_to_offset(1, 2), 5);
+        assert_eq!(buffer.offset_to_pos(5), (1, 2));
+        assert_eq!(buffer.offset_to_pos(7), (2, 0));
+        assert_eq!(buffer.offset_to_pos(buffer.len_bytes()), (3, 1));
+    }
+
+    #[test]
+    fn undo_redo_restores_text_and_cursor() {
+        let mut buffer = buffer_of("ab");
+        let start = Position { row: 0, col: 2 };
+        buffer.begin_change(start);
+        buffer.insert_char(0, 2, 'c');
+        buffer.insert(1, "d");
+        let end = Position { row: 1, col: 1 };
+        buffer.end_change(end);
+
+        buffer.remove_char(0, 0);
+        assert_eq!(buffer.get(0), "bc");
+
+        assert_eq!(buffer.undo(end).map(|p| (p.row, p.col)), Some((0, 0)));
+        assert_eq!(buffer.get(0), "abc");
+        assert_eq!(buffer.undo(end).map(|p| (p.row, p.col)), Some((0, 2)));
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.get(0), "ab");
+        assert!(buffer.undo(start).is_none());
+
+        assert_eq!(buffer.redo(start).map(|p| (p.row, p.col)), Some((1, 1)));
+        assert_eq!(buffer.get(0), "abc");
+        assert_eq!(buffer.get(1), "d");
+    }
+
+    #[test]
+    fn undo_tree_keeps_abandoned_branch() {
+        let mut buffer = buffer_of("");
+        let origin = Position { row: 0, col: 0 };
+        buffer.insert_string(0, 0, "a");
+        buffer.end_change(origin);
+        buffer.insert_string(0, 1, "b");
+        buffer.end_change(origin);
+
+        buffer.undo(origin);
+        buffer.insert_string(0, 1, "c");
+        buffer.end_change(origin);
+        assert_eq!(buffer.get(0), "ac");
+        assert_eq!(buffer.history_seq(), 3);
+
+        // g- walks back chronologically, reaching the abandoned "ab" state.
+        buffer.step_history(origin, -1);
+        assert_eq!(buffer.get(0), "ab");
+        buffer.earlier(origin, Travel::Steps(2));
+        assert_eq!(buffer.get(0), "");
+        buffer.later(origin, Travel::Steps(3));
+        assert_eq!(buffer.get(0), "ac");
+    }
+
+    #[test]
+    fn journaled_edits_are_checked_before_replay() {
+        let mut rope = Rope::from_str("héllo");
+        let insert = Edit::Insert {
+            offset: 1,
+            text: String::from("x"),
+        };
+        let inside_char = Edit::Insert {
+            offset: 3,
+            text: String::from("x"),
+        };
+        let wrong_text = Edit::Remove {
+            offset: 0,
+            text: String::from("q"),
+        };
+
+        assert!(Buffer::apply_checked(&mut rope, &insert));
+        assert!(!Buffer::apply_checked(&mut rope, &inside_char));
+        assert!(!Buffer::apply_checked(&mut rope, &wrong_text));
+        assert_eq!(rope.to_string(), "hxéllo");
+    }
+
+    #[test]
+    fn substitutes_as_one_change() {
+        use crate::substitute::{Flags, Session, Substitute};
+
+        let mut buffer = buffer_of("a=1, b=2\nc=3\nd=4");
+        let cursor = Position { row: 0, col: 0 };
+        let flags = Flags::parse("g", Flags::default()).unwrap();
+        let substitute = Substitute::new(r"(\w)=(\d)", r"\u\1:\2\r", flags, false).unwrap();
+        let mut session = Session::new(substitute, 0, 1);
+
+        buffer.begin_change(cursor);
+        assert!(session.advance(&buffer));
+        session.skip();
+        assert_eq!(session.current(), Some((0, 5..8)));
+        session.replace_all(&mut buffer);
+        buffer.end_change(cursor);
+
+        assert_eq!(buffer.text.to_string(), "a=1, B:2\n\nC:3\n\nd=4");
+        assert_eq!(session.summary(), "2 substitutions on 2 lines");
+        assert_eq!(session.last_changed_row(), Some(3));
+
+        buffer.undo(cursor);
+        assert_eq!(buffer.text.to_string(), "a=1, b=2\nc=3\nd=4");
+    }
+
+    #[test]
+    fn marked_lines_survive_deletions_in_one_group() {
+        use crate::marks::Marks;
+
+        let mut buffer = buffer_of("x1\ny\nx2\nx3\nz");
+        let mut marks = Marks::new(&mut buffer);
+        let cursor = Position { row: 0, col: 0 };
+        marks.mark_lines([0, 2, 3]);
+
+        // Like `:g/x/.,+1d`: the first visit deletes `x1` and `y`, the second
+        // `x2` and `x3`, so nothing is left for a third.
+        buffer.begin_group(cursor);
+        let mut visited = Vec::new();
+        loop {
+            marks.sync(&mut buffer);
+            let Some(row) = marks.next_marked_line() else {
+                break;
+            };
+            visited.push(row);
+            buffer.begin_change(cursor);
+            buffer.remove(row);
+            buffer.remove(row);
+            buffer.end_change(cursor);
+        }
+        buffer.end_group(cursor);
+
+        assert_eq!(visited, vec![0, 0]);
+        assert_eq!(buffer.text.to_string(), "z");
+        buffer.undo(cursor);
+        assert_eq!(buffer.text.to_string(), "x1\ny\nx2\nx3\nz");
+        assert_eq!(buffer.undo(cursor), None);
+    }
+
+    #[test]
+    fn edits_visual_selections() {
+        let at = |row, col| Position { row, col };
+        let select = |mode, anchor, cursor| Selection::new(mode, anchor, cursor).unwrap();
+
+        let mut buffer = buffer_of("abc\ndef\nghi");
+        let chars = select(Mode::Visual, at(0, 1), at(1, 0));
+        assert_eq!(buffer.yank_selection(&chars).lines, vec!["bc", "d"]);
+        assert_eq!(buffer.delete_selection(&chars), at(0, 1));
+        assert_eq!(buffer.text.to_string(), "aef\nghi");
+
+        let mut buffer = buffer_of("abcd\nef\nghij");
+        let block = select(Mode::VisualBlock, at(0, 1), at(2, 2));
+        assert_eq!(buffer.delete_selection(&block), at(0, 1));
+        assert_eq!(buffer.text.to_string(), "ad\ne\ngj");
+        buffer.insert_block(1..=2, 1, "XY", false);
+        assert_eq!(buffer.text.to_string(), "ad\neXY\ngXYj");
+        buffer.insert_block(0..=1, 4, "|", true);
+        assert_eq!(buffer.text.to_string(), "ad  |\neXY |\ngXYj");
+
+        let mut buffer = buffer_of("one\n\n      two");
+        buffer.shift_lines(0, 2, false);
+        assert_eq!(buffer.text.to_string(), "    one\n\n          two");
+        let lines = select(Mode::VisualLine, at(2, 0), at(0, 0));
+        buffer.change_case(&lines, Operator::ToggleCase);
+        assert_eq!(buffer.text.to_string(), "    ONE\n\n          TWO");
+    }
 
-        self.lines[row] = front.to_string() + rear;
+    #[test]
+    fn reindents_by_brackets() {
+        let mut buffer = buffer_of("fn f() {\nif x {\n  y();\n\n        }\n}");
+        buffer.reindent_lines(1, 5);
+        assert_eq!(
+            buffer.text.to_string(),
+            "fn f() {\n    if x {\n        y();\n\n    }\n}"
+        );
     }
 }
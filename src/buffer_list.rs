@@ -0,0 +1,185 @@
+use crate::{
+    buffer::Buffer,
+    cursor::{Cursor, Position},
+    marks::Marks,
+};
+use std::collections::HashMap;
+
+/// Everything that makes up one open file besides what is shared by all of
+/// them. The shown buffer's parts are lent out to `Application`; the others
+/// are parked here until switched to.
+pub struct OpenBuffer {
+    pub buffer: Buffer,
+    pub cursor: Cursor,
+    pub marks: Marks,
+    pub viewport_offset: usize,
+    pub file_name: String,
+}
+
+struct Entry {
+    /// Buffer number shown by `:ls` and used by `:b N`. Never reused.
+    id: usize,
+    /// `None` for the shown buffer.
+    parked: Option<OpenBuffer>,
+}
+
+/// Where a global mark (`A-Z`) outside the shown buffer is.
+pub enum GlobalMark {
+    Parked(usize),
+    /// In a file whose buffer was deleted.
+    Closed(String, Position),
+}
+
+/// Open buffers in the order they were opened.
+pub struct BufferList {
+    entries: Vec<Entry>,
+    current: usize,
+    alternate: Option<usize>,
+    next_id: usize,
+    /// Global marks of deleted buffers, by name.
+    closed_marks: HashMap<char, (String, Position)>,
+}
+
+impl BufferList {
+    /// Starts the list with the shown buffer as number 1.
+    pub fn new() -> Self {
+        Self {
+            entries: vec![Entry {
+                id: 1,
+                parked: None,
+            }],
+            current: 0,
+            alternate: None,
+            next_id: 2,
+            closed_marks: HashMap::new(),
+        }
+    }
+
+    pub fn current_id(&self) -> usize {
+        self.entries[self.current].id
+    }
+
+    /// Buffer last shown before the current one, for `Ctrl-^`.
+    pub fn alternate_id(&self) -> Option<usize> {
+        self.alternate
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Buffer numbers with the parked buffers; the shown one has `None`.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Option<&OpenBuffer>)> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.id, entry.parked.as_ref()))
+    }
+
+    pub fn get(&self, id: usize) -> Option<&OpenBuffer> {
+        let index = self.index_of(id)?;
+        self.entries[index].parked.as_ref()
+    }
+
+    pub fn parked_mut(&mut self) -> impl Iterator<Item = &mut OpenBuffer> {
+        self.entries
+            .iter_mut()
+            .filter_map(|entry| entry.parked.as_mut())
+    }
+
+    /// Number of a parked buffer showing `file_name`.
+    pub fn find(&self, file_name: &str) -> Option<usize> {
+        self.iter()
+            .find(|(_, parked)| parked.is_some_and(|open| open.file_name == file_name))
+            .map(|(id, _)| id)
+    }
+
+    /// Number of the buffer `delta` places after the current one, wrapping around.
+    pub fn id_after(&self, delta: isize) -> usize {
+        let len = self.entries.len() as isize;
+        let index = (self.current as isize + delta).rem_euclid(len) as usize;
+        self.entries[index].id
+    }
+
+    /// Parks a newly opened buffer and returns its number.
+    pub fn add(&mut self, open: OpenBuffer) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(Entry {
+            id,
+            parked: Some(open),
+        });
+        id
+    }
+
+    /// Shows buffer `id`. `swap_shown` exchanges the parked buffer with the
+    /// shown one, which is then parked in its place. Returns false if there
+    /// is no other buffer with that number.
+    pub fn switch(&mut self, id: usize, swap_shown: impl FnOnce(&mut OpenBuffer)) -> bool {
+        let Some(index) = self.index_of(id) else {
+            return false;
+        };
+        if index == self.current {
+            return false;
+        }
+
+        let mut open = self.entries[index]
+            .parked
+            .take()
+            .expect("hidden buffers are parked");
+        swap_shown(&mut open);
+        self.entries[self.current].parked = Some(open);
+        self.alternate = Some(self.current_id());
+        self.current = index;
+
+        true
+    }
+
+    /// Removes parked buffer `id` from the list and hands it back.
+    pub fn remove(&mut self, id: usize) -> Option<OpenBuffer> {
+        let index = self.index_of(id)?;
+        let open = self.entries[index].parked.take()?;
+
+        self.entries.remove(index);
+        if index < self.current {
+            self.current -= 1;
+        }
+        if self.alternate == Some(id) {
+            self.alternate = None;
+        }
+
+        Some(open)
+    }
+
+    /// Finds global mark `name` among the parked and deleted buffers.
+    pub fn find_global_mark(&self, name: char) -> Option<GlobalMark> {
+        self.iter()
+            .find(|(_, parked)| parked.is_some_and(|open| open.marks.get(name).is_some()))
+            .map(|(id, _)| GlobalMark::Parked(id))
+            .or_else(|| {
+                self.closed_marks
+                    .get(&name)
+                    .map(|(file_name, pos)| GlobalMark::Closed(file_name.clone(), *pos))
+            })
+    }
+
+    /// Drops global mark `name` from every buffer but the shown one, before
+    /// it is set there.
+    pub fn clear_global_mark(&mut self, name: char) {
+        for open in self.parked_mut() {
+            open.marks.remove(name);
+        }
+        self.closed_marks.remove(&name);
+    }
+
+    /// Keeps the global marks of a deleted buffer, so jumping to them reopens it.
+    pub fn keep_global_marks(&mut self, open: &OpenBuffer) {
+        for (name, pos) in open.marks.globals() {
+            self.closed_marks
+                .insert(name, (open.file_name.clone(), pos));
+        }
+    }
+
+    fn index_of(&self, id: usize) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.id == id)
+    }
+}
@@ -0,0 +1,78 @@
+use crate::cursor::Position;
+
+/// One replacement of a range of the text. Byte offsets and (row, byte col)
+/// points are given before and after the change, in the shape tree-sitter's
+/// `InputEdit` and LSP's incremental sync expect.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextChange {
+    /// Revision of the buffer after this change.
+    pub revision: u64,
+    pub start_byte: usize,
+    pub old_end_byte: usize,
+    pub new_end_byte: usize,
+    pub start: Position,
+    pub old_end: Position,
+    pub new_end: Position,
+    /// Text that replaced `start_byte..old_end_byte`.
+    pub text: String,
+}
+
+pub type SubscriberId = usize;
+
+/// Queues every change of a buffer for each subscriber until it drains them,
+/// so consumers can catch up whenever suits them instead of rescanning.
+pub struct ChangeLog {
+    revision: u64,
+    /// Queue of each subscriber by id; `None` once it unsubscribed.
+    queues: Vec<Option<Vec<TextChange>>>,
+}
+
+impl ChangeLog {
+    pub fn new() -> Self {
+        Self {
+            revision: 0,
+            queues: Vec::new(),
+        }
+    }
+
+    /// Starts queueing changes made from now on for a new subscriber.
+    pub fn subscribe(&mut self) -> SubscriberId {
+        match self.queues.iter().position(Option::is_none) {
+            Some(id) => {
+                self.queues[id] = Some(Vec::new());
+                id
+            }
+            None => {
+                self.queues.push(Some(Vec::new()));
+                self.queues.len() - 1
+            }
+        }
+    }
+
+    /// Stops queueing changes for `id` and drops those it didn't take. The id
+    /// may be given to a later subscriber.
+    pub fn unsubscribe(&mut self, id: SubscriberId) {
+        if let Some(queue) = self.queues.get_mut(id) {
+            *queue = None;
+        }
+    }
+
+    /// Takes the changes queued for `id`, oldest first.
+    pub fn drain(&mut self, id: SubscriberId) -> Vec<TextChange> {
+        self.queues
+            .get_mut(id)
+            .and_then(Option::as_mut)
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Stamps `change` with the next revision and queues it for every subscriber.
+    pub fn push(&mut self, mut change: TextChange) {
+        self.revision += 1;
+        change.revision = self.revision;
+
+        for queue in self.queues.iter_mut().flatten() {
+            queue.push(change.clone());
+        }
+    }
+}
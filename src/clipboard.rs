@@ -0,0 +1,166 @@
+use anyhow::{Context as AnyhowContext, Result, bail};
+use std::{
+    env,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Helper commands for the system clipboard behind `"+` and `"*`.
+///
+/// Copies are also sent to the terminal as OSC 52 (see `osc52`), which reaches
+/// the local clipboard through SSH and tmux where no helper can. Pasting needs
+/// a helper, since terminals rarely answer clipboard queries.
+pub struct Clipboard {
+    /// Shell command that reads the text to copy from stdin.
+    pub copy_cmd: Option<String>,
+    /// Shell command that prints the clipboard.
+    pub paste_cmd: Option<String>,
+}
+
+impl Clipboard {
+    /// Picks `wl-copy` under Wayland and `xclip` under X11, if installed.
+    pub fn detect() -> Self {
+        let (copy, paste) = if env::var_os("WAYLAND_DISPLAY").is_some() && installed("wl-copy") {
+            ("wl-copy", "wl-paste --no-newline")
+        } else if env::var_os("DISPLAY").is_some() && installed("xclip") {
+            (
+                "xclip -selection clipboard",
+                "xclip -selection clipboard -o",
+            )
+        } else {
+            return Self {
+                copy_cmd: None,
+                paste_cmd: None,
+            };
+        };
+
+        Self {
+            copy_cmd: Some(copy.to_string()),
+            paste_cmd: Some(paste.to_string()),
+        }
+    }
+
+    /// Passes `text` to the copy helper, if there is one.
+    pub fn copy(&self, text: &str) -> Result<()> {
+        let Some(copy_cmd) = &self.copy_cmd else {
+            return Ok(());
+        };
+
+        let mut child = Command::new("sh")
+            .args(["-c", copy_cmd])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Can't run {copy_cmd}"))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(text.as_bytes())
+                .with_context(|| format!("Can't write to {copy_cmd}"))?;
+        }
+
+        let status = child
+            .wait()
+            .with_context(|| format!("Can't run {copy_cmd}"))?;
+        if !status.success() {
+            bail!("{copy_cmd} failed with {status}");
+        }
+
+        Ok(())
+    }
+
+    /// The clipboard text from the paste helper, or `None` without one.
+    pub fn paste(&self) -> Result<Option<String>> {
+        let Some(paste_cmd) = &self.paste_cmd else {
+            return Ok(None);
+        };
+
+        let output = Command::new("sh")
+            .args(["-c", paste_cmd])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .with_context(|| format!("Can't run {paste_cmd}"))?;
+        if !output.status.success() {
+            bail!("{paste_cmd} failed with {}", output.status);
+        }
+
+        Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+    }
+}
+
+fn installed(program: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// Escape sequence asking the terminal to put `text` on the clipboard, or on
+/// the primary selection for `"*`.
+pub fn osc52(register: char, text: &str) -> String {
+    let selection = if register == '*' { 'p' } else { 'c' };
+    format!("\x1b]52;{selection};{}\x07", base64(text.as_bytes()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn osc52_encodes_base64() {
+        assert_eq!(base64(b"hello"), "aGVsbG8=");
+        assert_eq!(base64(b"hi"), "aGk=");
+        assert_eq!(base64(b"abc"), "YWJj");
+        assert_eq!(osc52('+', "hi"), "\x1b]52;c;aGk=\x07");
+        assert_eq!(osc52('*', ""), "\x1b]52;p;\x07");
+    }
+
+    #[test]
+    fn copies_and_pastes_through_helpers() {
+        let dir = env::temp_dir().join(format!("hoditor-clipboard-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = dir.join("clip");
+        let script = |name: &str, body: String| {
+            let path = dir.join(name);
+            fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+            format!("sh {}", path.display())
+        };
+
+        let clipboard = Clipboard {
+            copy_cmd: Some(script("copy.sh", format!("cat > {}", store.display()))),
+            paste_cmd: Some(script("paste.sh", format!("cat {}", store.display()))),
+        };
+        clipboard.copy("one\ntwo\n").unwrap();
+        assert_eq!(clipboard.paste().unwrap().as_deref(), Some("one\ntwo\n"));
+
+        let failing = Clipboard {
+            copy_cmd: Some(String::from("exit 1")),
+            paste_cmd: None,
+        };
+        assert!(failing.copy("x").is_err());
+        assert_eq!(failing.paste().unwrap(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,11 @@
-use crate::{input_handler::Command, logger::Logger};
+use crate::{
+    ex,
+    input_handler::Command,
+    logger::Logger,
+    motion::Keys,
+    operator::{self, Parsed},
+    registers::UNNAMED,
+};
 use std::collections::HashMap;
 
 pub struct CmdDispatcher {
@@ -22,28 +29,53 @@ impl CmdDispatcher {
         self.query.push(ch);
     }
 
-    pub fn get(&mut self) -> Option<Command> {
+    /// Looks up a whole ex command line such as `%s/a/b/`: an optional line
+    /// range, the command name and its argument.
+    pub fn get_ex(&mut self) -> Option<Command> {
         let query = self.query.clone();
-        let query: Vec<_> = query.split(' ').collect();
-        let cmd = query[0].to_string();
-        Logger::log(format!("cmd: {}, query:{{{:?}}}", &cmd, &query));
-        let result = self.root.find(&cmd);
-        Logger::log(format!("{:?}", result));
-        match result {
-            CmdFindResult::Invalid => {
-                self.clear();
-                None
-            }
-            CmdFindResult::Incomplete => None,
-            CmdFindResult::Complete(cmd) => {
+        let (range, name, arg) = ex::split(&query);
+        Logger::log(format!("range: {range}, cmd: {name}, arg: {arg}"));
+        self.clear();
+        match self.root.find(name) {
+            CmdFindResult::Complete(cmd) => Some(cmd.with_arg(arg).with_range(range)),
+            _ => None,
+        }
+    }
+
+    /// Looks up normal or visual mode keys: `[count]operator[count]motion`,
+    /// `[count]motion`, or a fixed sequence, which ignores a count before it.
+    /// Operators are only read if `operators`.
+    pub fn get_keys(&mut self, operators: bool) -> Option<Command> {
+        let parsed = operator::parse(&self.query, operators);
+        let fixed = self.root.find(
+            self.query
+                .trim_start_matches(|ch: char| ch.is_ascii_digit()),
+        );
+        Logger::log(format!("keys: {}, {parsed:?}, {fixed:?}", self.query));
+        let cmd = match (parsed, fixed) {
+            (Keys::Complete(Parsed::Move { motion, count }), _) => Command::Move { motion, count },
+            (
+                Keys::Complete(Parsed::Operate {
+                    operator,
+                    motion,
+                    count,
+                }),
+                _,
+            ) => Command::Operate {
+                operator,
+                motion,
+                count,
+                register: UNNAMED,
+            },
+            (_, CmdFindResult::Complete(cmd)) => cmd,
+            (Keys::Invalid, CmdFindResult::Invalid) => {
                 self.clear();
-                if matches!(cmd, Command::OpenFile(_)) {
-                    Some(Command::OpenFile(query[1].to_string()))
-                } else {
-                    Some(cmd)
-                }
+                return None;
             }
-        }
+            _ => return None,
+        };
+        self.clear();
+        Some(cmd)
     }
 
     pub fn get_query(&self) -> String {
@@ -1,10 +1,12 @@
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, Clone, Copy)]
 pub enum CursorStyle {
     Block,
     Bar,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Position {
     pub row: usize,
     pub col: usize,
@@ -14,6 +16,9 @@ pub struct Position {
 pub struct Cursor {
     pos: Position,
     style: CursorStyle,
+    /// Column to go back to when moving up and down past shorter lines;
+    /// `usize::MAX` after `$`.
+    preferred_col: Option<usize>,
 }
 
 pub const SCROLL_HEIGHT: usize = 15;
@@ -22,6 +27,7 @@ impl Cursor {
         Self {
             pos: Position { row: 0, col: 0 },
             style: CursorStyle::Block,
+            preferred_col: None,
         }
     }
 
@@ -37,17 +43,10 @@ impl Cursor {
         self.pos.col
     }
 
-    pub fn set_row(&mut self, row: usize) {
-        self.pos.row = row;
-    }
-
-    pub fn set_col(&mut self, col: usize) {
-        self.pos.col = col;
-    }
-
     pub fn move_to(&mut self, row: usize, col: usize) {
         self.pos.row = row;
         self.pos.col = col;
+        self.preferred_col = None;
     }
 
     pub fn move_down(&mut self, n: usize) {
@@ -68,14 +67,40 @@ impl Cursor {
         }
 
         self.pos.col -= n;
+        self.preferred_col = None;
     }
 
     pub fn move_right(&mut self, n: usize) {
         self.pos.col += n;
+        self.preferred_col = None;
     }
 
     pub fn move_to_col(&mut self, col: usize) {
         self.pos.col = col;
+        self.preferred_col = None;
+    }
+
+    /// Moves to `row`, whose text is `line`, as close as it can get to the
+    /// column it was at before moving up and down. The cursor stays on a
+    /// character, or may go just past the last one if `past_end`, as in
+    /// insert mode.
+    pub fn move_vertically(&mut self, row: usize, line: &str, past_end: bool) {
+        let col = *self.preferred_col.get_or_insert(self.pos.col);
+        let last = if past_end {
+            line.len()
+        } else {
+            line.char_indices().last().map_or(0, |(col, _)| col)
+        };
+        let col = (0..=col.min(last))
+            .rev()
+            .find(|&i| line.is_char_boundary(i))
+            .unwrap_or(0);
+        self.pos = Position { row, col };
+    }
+
+    /// Keeps the cursor at the ends of lines as it moves up and down, after `$`.
+    pub fn keep_at_line_end(&mut self) {
+        self.preferred_col = Some(usize::MAX);
     }
 
     pub fn set_style(&mut self, style: CursorStyle) {
@@ -86,3 +111,32 @@ impl Cursor {
         self.style
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_vertically_onto_characters() {
+        let mut cursor = Cursor::new();
+        cursor.move_to(0, 2);
+        cursor.move_vertically(1, "héllo", false);
+        assert_eq!(cursor.pos(), Position { row: 1, col: 1 });
+        cursor.move_vertically(2, "ab", false);
+        assert_eq!(cursor.pos(), Position { row: 2, col: 1 });
+        cursor.move_vertically(3, "abcd", false);
+        assert_eq!(cursor.pos(), Position { row: 3, col: 2 });
+        cursor.move_vertically(4, "ab", true);
+        assert_eq!(cursor.pos(), Position { row: 4, col: 2 });
+
+        cursor.keep_at_line_end();
+        cursor.move_vertically(5, "naïve", false);
+        assert_eq!(cursor.pos(), Position { row: 5, col: 5 });
+        cursor.move_vertically(6, "é", false);
+        assert_eq!(cursor.pos(), Position { row: 6, col: 0 });
+        cursor.move_vertically(7, "", false);
+        assert_eq!(cursor.pos(), Position { row: 7, col: 0 });
+        cursor.move_vertically(8, "abé", true);
+        assert_eq!(cursor.pos(), Position { row: 8, col: 4 });
+    }
+}
@@ -0,0 +1,249 @@
+/// Lines of context kept around each change in `unified`.
+const CONTEXT: usize = 2;
+
+/// Largest LCS table computed before falling back to replacing the whole
+/// differing middle part.
+const MAX_TABLE: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffOp {
+    Equal { old: usize, new: usize },
+    Delete { old: usize },
+    Insert { new: usize },
+}
+
+/// Line diff of `old` against `new`, as a sequence of per-line operations.
+pub fn diff_lines<S: AsRef<str>>(old: &[S], new: &[S]) -> Vec<DiffOp> {
+    let eq = |i: usize, j: usize| old[i].as_ref() == new[j].as_ref();
+
+    let prefix = (0..old.len().min(new.len()))
+        .take_while(|&i| eq(i, i))
+        .count();
+    let suffix = (0..old.len().min(new.len()) - prefix)
+        .take_while(|&i| eq(old.len() - 1 - i, new.len() - 1 - i))
+        .count();
+
+    let mut ops: Vec<DiffOp> = (0..prefix)
+        .map(|i| DiffOp::Equal { old: i, new: i })
+        .collect();
+
+    let (old_start, old_end) = (prefix, old.len() - suffix);
+    let (new_start, new_end) = (prefix, new.len() - suffix);
+    let (n, m) = (old_end - old_start, new_end - new_start);
+
+    if n * m <= MAX_TABLE {
+        // lcs[i][j]: LCS length of old[old_start + i..old_end] and new[new_start + j..new_end].
+        let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if eq(old_start + i, new_start + j) {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && eq(old_start + i, new_start + j) {
+                ops.push(DiffOp::Equal {
+                    old: old_start + i,
+                    new: new_start + j,
+                });
+                i += 1;
+                j += 1;
+            } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+                ops.push(DiffOp::Delete { old: old_start + i });
+                i += 1;
+            } else {
+                ops.push(DiffOp::Insert { new: new_start + j });
+                j += 1;
+            }
+        }
+    } else {
+        ops.extend((old_start..old_end).map(|old| DiffOp::Delete { old }));
+        ops.extend((new_start..new_end).map(|new| DiffOp::Insert { new }));
+    }
+
+    ops.extend((0..suffix).map(|i| DiffOp::Equal {
+        old: old_end + i,
+        new: new_end + i,
+    }));
+    ops
+}
+
+/// Renders a unified diff with `-`/`+` prefixed lines and `@@` hunk headers.
+pub fn unified<S: AsRef<str>>(old: &[S], new: &[S]) -> Vec<String> {
+    let ops = diff_lines(old, new);
+    let changed: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, DiffOp::Equal { .. }))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut lines = Vec::new();
+    let mut shown_until = 0;
+    for (k, &i) in changed.iter().enumerate() {
+        let start = i.saturating_sub(CONTEXT).max(shown_until);
+        if k == 0 || start > shown_until {
+            let (old_line, new_line) = match ops[start] {
+                DiffOp::Equal { old, new } => (old, new),
+                DiffOp::Delete { old } => (old, 0),
+                DiffOp::Insert { new } => (0, new),
+            };
+            lines.push(format!("@@ -{} +{} @@", old_line + 1, new_line + 1));
+        }
+
+        let end = (i + 1 + CONTEXT).min(ops.len());
+        let end = match changed.get(k + 1) {
+            Some(&next) if next <= end + CONTEXT => next,
+            _ => end,
+        };
+
+        for op in &ops[start..end] {
+            lines.push(match *op {
+                DiffOp::Equal { old: o, .. } => format!(" {}", old[o].as_ref()),
+                DiffOp::Delete { old: o } => format!("-{}", old[o].as_ref()),
+                DiffOp::Insert { new: n } => format!("+{}", new[n].as_ref()),
+            });
+        }
+        shown_until = end;
+    }
+
+    lines
+}
+
+/// Three-way merge of `mine` and `theirs`, both derived from `base`.
+///
+/// Regions changed on one side only take that side. Regions changed on both
+/// sides differently become conflicts wrapped in `<<<<<<<`/`|||||||`/`=======`/
+/// `>>>>>>>` markers. Returns the merged lines and the number of conflicts.
+pub fn merge3<S: AsRef<str>>(base: &[S], mine: &[S], theirs: &[S]) -> (Vec<String>, usize) {
+    let matches = |other: &[S]| {
+        let mut matched = vec![None; base.len()];
+        for op in diff_lines(base, other) {
+            if let DiffOp::Equal { old, new } = op {
+                matched[old] = Some(new);
+            }
+        }
+        matched
+    };
+    let (match_mine, match_theirs) = (matches(mine), matches(theirs));
+
+    let to_strings =
+        |lines: &[S]| -> Vec<String> { lines.iter().map(|l| l.as_ref().to_string()).collect() };
+
+    let mut merged = Vec::new();
+    let mut conflicts = 0;
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    while i < base.len() || j < mine.len() || k < theirs.len() {
+        if i < base.len() && match_mine[i] == Some(j) && match_theirs[i] == Some(k) {
+            merged.push(base[i].as_ref().to_string());
+            i += 1;
+            j += 1;
+            k += 1;
+            continue;
+        }
+
+        // Next base line kept by both sides ends the differing region.
+        let next = (i..base.len()).find(|&b| match_mine[b].is_some() && match_theirs[b].is_some());
+        let (i2, j2, k2) = match next {
+            Some(b) => (b, match_mine[b].unwrap(), match_theirs[b].unwrap()),
+            None => (base.len(), mine.len(), theirs.len()),
+        };
+
+        let base_part = to_strings(&base[i..i2]);
+        let mine_part = to_strings(&mine[j..j2]);
+        let theirs_part = to_strings(&theirs[k..k2]);
+
+        if mine_part == base_part || mine_part == theirs_part {
+            merged.extend(theirs_part);
+        } else if theirs_part == base_part {
+            merged.extend(mine_part);
+        } else {
+            conflicts += 1;
+            merged.push(String::from("<<<<<<< mine"));
+            merged.extend(mine_part);
+            merged.push(String::from("||||||| base"));
+            merged.extend(base_part);
+            merged.push(String::from("======="));
+            merged.extend(theirs_part);
+            merged.push(String::from(">>>>>>> disk"));
+        }
+
+        (i, j, k) = (i2, j2, k2);
+    }
+
+    (merged, conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge3_combines_separate_changes() {
+        let base = ["a", "b", "c", "d"];
+        let mine = ["a", "B", "c", "d"];
+        let theirs = ["a", "b", "c", "D", "e"];
+
+        let (merged, conflicts) = merge3(&base, &mine, &theirs);
+        assert_eq!(merged, vec!["a", "B", "c", "D", "e"]);
+        assert_eq!(conflicts, 0);
+    }
+
+    #[test]
+    fn merge3_marks_conflicts() {
+        let base = ["a", "b", "c"];
+        let mine = ["a", "x", "c"];
+        let theirs = ["a", "y", "c"];
+
+        let (merged, conflicts) = merge3(&base, &mine, &theirs);
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            merged,
+            vec![
+                "a",
+                "<<<<<<< mine",
+                "x",
+                "||||||| base",
+                "b",
+                "=======",
+                "y",
+                ">>>>>>> disk",
+                "c",
+            ]
+        );
+    }
+
+    #[test]
+    fn unified_diff_shows_changes_with_context() {
+        let old = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let new = ["a", "b", "x", "d", "e", "f", "g", "h", "i"];
+
+        assert_eq!(
+            unified(&old, &new),
+            vec![
+                "@@ -1 +1 @@",
+                " a",
+                " b",
+                "-c",
+                "+x",
+                " d",
+                " e",
+                "@@ -7 +7 @@",
+                " g",
+                " h",
+                "+i",
+            ]
+        );
+    }
+
+    #[test]
+    fn identical_inputs_have_no_diff() {
+        let lines = ["a", "b"];
+        assert!(unified(&lines, &lines).is_empty());
+    }
+}
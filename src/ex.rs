@@ -0,0 +1,211 @@
+use anyhow::{Result, bail};
+
+/// Splits an ex command line into its line range, command name and
+/// argument: `%s/a/b/` gives `("%", "s", "/a/b/")` and `e foo` gives
+/// `("", "e", "foo")`. Names are a run of letters with an optional `!`, or a
+/// run of one symbol such as `&&`.
+pub fn split(line: &str) -> (&str, &str, &str) {
+    let range_len = range_len(line);
+    let (range, rest) = line.split_at(range_len);
+    let rest = rest.trim_start();
+
+    let name_len = match rest.chars().next() {
+        Some(first) if first.is_ascii_alphabetic() => {
+            let letters = rest
+                .find(|ch: char| !ch.is_ascii_alphabetic())
+                .unwrap_or(rest.len());
+            letters + usize::from(rest[letters..].starts_with('!'))
+        }
+        Some(first) => rest.find(|ch| ch != first).unwrap_or(rest.len()),
+        None => 0,
+    };
+    let (name, arg) = rest.split_at(name_len);
+
+    (range.trim(), name, arg.trim_start())
+}
+
+fn range_len(line: &str) -> usize {
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(i, ch)) = chars.peek() {
+        match ch {
+            '0'..='9' | '.' | '$' | '%' | ',' | ';' | '+' | '-' | ' ' => {
+                chars.next();
+            }
+            '\'' => {
+                chars.next();
+                if chars.next().is_none() {
+                    return line.len();
+                }
+            }
+            _ => return i,
+        }
+    }
+    line.len()
+}
+
+/// Resolves a line range such as `%`, `.,$`, `'a,'b` or `.+1;+3` to 0-based
+/// rows, given the cursor row, the last row and a lookup for mark rows.
+/// Returns `None` for an empty range, which commands take their own way.
+pub fn resolve(
+    range: &str,
+    current: usize,
+    last: usize,
+    mark: impl Fn(char) -> Option<usize>,
+) -> Result<Option<(usize, usize)>> {
+    let range: String = range.chars().filter(|ch| !ch.is_whitespace()).collect();
+    if range.is_empty() {
+        return Ok(None);
+    }
+    if range == "%" {
+        return Ok(Some((0, last)));
+    }
+
+    let (start, end) = match range.find([',', ';']) {
+        Some(i) => {
+            let start = address(&range[..i], current, last, &mark)?;
+            // `;` makes the first line the current one for the second address.
+            let current = if range[i..].starts_with(';') {
+                start
+            } else {
+                current
+            };
+            (start, address(&range[i + 1..], current, last, &mark)?)
+        }
+        None => {
+            let row = address(&range, current, last, &mark)?;
+            (row, row)
+        }
+    };
+
+    Ok(Some((start.min(end), start.max(end))))
+}
+
+fn address(
+    text: &str,
+    current: usize,
+    last: usize,
+    mark: &impl Fn(char) -> Option<usize>,
+) -> Result<usize> {
+    let mut chars = text.chars().peekable();
+    let mut row = match chars.peek() {
+        Some('.') => {
+            chars.next();
+            current as isize
+        }
+        Some('$') => {
+            chars.next();
+            last as isize
+        }
+        Some('\'') => {
+            chars.next();
+            let name = chars.next().unwrap_or(' ');
+            match mark(name) {
+                Some(row) => row as isize,
+                None => bail!("Mark not set: {name}"),
+            }
+        }
+        Some(ch) if ch.is_ascii_digit() => {
+            let digits: String =
+                std::iter::from_fn(|| chars.next_if(char::is_ascii_digit)).collect();
+            digits.parse::<isize>().unwrap_or(0).max(1) - 1
+        }
+        _ => current as isize,
+    };
+
+    while let Some(sign) = chars.next() {
+        let digits: String = std::iter::from_fn(|| chars.next_if(char::is_ascii_digit)).collect();
+        let amount = if digits.is_empty() {
+            1
+        } else {
+            digits.parse::<isize>().unwrap_or(0)
+        };
+        match sign {
+            '+' => row += amount,
+            '-' => row -= amount,
+            _ => bail!("Invalid range: {text}"),
+        }
+    }
+
+    if row < 0 || row > last as isize {
+        bail!("Invalid range: {text}");
+    }
+    Ok(row as usize)
+}
+
+/// Splits `/pattern/rest` at the delimiter its first character is, as `:s`
+/// and `:g` take their pattern. A delimiter escaped with `\` is taken
+/// literally.
+pub fn split_pattern(arg: &str) -> Result<(String, &str)> {
+    let Some(delimiter) = arg.chars().next() else {
+        bail!("Missing pattern");
+    };
+    if delimiter.is_alphanumeric() || "\\\"| ".contains(delimiter) {
+        bail!("Invalid delimiter: {delimiter}");
+    }
+
+    Ok(take_until(&arg[delimiter.len_utf8()..], delimiter))
+}
+
+/// The text up to an unescaped `delimiter` and the rest after it.
+pub fn take_until(text: &str, delimiter: char) -> (String, &str) {
+    let mut part = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        if ch == delimiter {
+            return (part, &text[i + ch.len_utf8()..]);
+        }
+        if ch == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => part.push(next),
+                Some((_, next)) => {
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => part.push('\\'),
+            }
+        } else {
+            part.push(ch);
+        }
+    }
+    (part, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_range_name_and_argument() {
+        assert_eq!(split("%s/a/b/g"), ("%", "s", "/a/b/g"));
+        assert_eq!(split("e foo.rs"), ("", "e", "foo.rs"));
+        assert_eq!(split("w!"), ("", "w!", ""));
+        assert_eq!(split("b2"), ("", "b", "2"));
+        assert_eq!(split(".,$&&"), (".,$", "&&", ""));
+        assert_eq!(split("'a,'bs#x#y#"), ("'a,'b", "s", "#x#y#"));
+        assert_eq!(split("g!/x/d"), ("", "g!", "/x/d"));
+        assert_eq!(
+            split_pattern(r"/a\/b/s/x/y/").unwrap(),
+            (String::from("a/b"), "s/x/y/")
+        );
+    }
+
+    #[test]
+    fn resolves_addresses() {
+        let resolve = |range| {
+            resolve(range, 4, 9, |name| (name == 'a').then_some(2))
+                .ok()
+                .flatten()
+        };
+
+        assert_eq!(resolve("%"), Some((0, 9)));
+        assert_eq!(resolve(".,$"), Some((4, 9)));
+        assert_eq!(resolve("3"), Some((2, 2)));
+        assert_eq!(resolve("'a,.+2"), Some((2, 6)));
+        assert_eq!(resolve("2;+1"), Some((1, 2)));
+        assert_eq!(resolve("-,+"), Some((3, 5)));
+        assert_eq!(resolve("$,1"), Some((0, 9)));
+        assert_eq!(resolve(""), None);
+        assert_eq!(resolve("'b"), None);
+        assert_eq!(resolve("12"), None);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::hex;
+use anyhow::{Result, bail};
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use std::io::Write;
+
+/// Number of leading bytes inspected when guessing a file's encoding.
+const SNIFF_LEN: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Unix,
+    Dos,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Unix => "\n",
+            LineEnding::Dos => "\r\n",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Unix => "unix",
+            LineEnding::Dos => "dos",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(LineEnding::Unix),
+            "dos" => Some(LineEnding::Dos),
+            _ => None,
+        }
+    }
+}
+
+/// How a file was laid out on disk, so saving writes it back unchanged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileFormat {
+    pub line_ending: LineEnding,
+    pub final_newline: bool,
+    pub bom: bool,
+    pub encoding: &'static Encoding,
+    /// Not text; the buffer holds a hex dump of the bytes.
+    pub binary: bool,
+}
+
+impl FileFormat {
+    /// Format of a file created by the editor.
+    pub fn new() -> Self {
+        Self {
+            line_ending: LineEnding::Unix,
+            final_newline: true,
+            bom: false,
+            encoding: UTF_8,
+            binary: false,
+        }
+    }
+
+    /// Lowercase encoding name shown to the user, e.g. `utf-8` or `windows-1252`.
+    pub fn encoding_name(&self) -> String {
+        if self.binary {
+            return String::from("binary");
+        }
+        self.encoding.name().to_lowercase()
+    }
+
+    /// Parses a `fileencoding` value. Accepts WHATWG labels like `latin1` or `utf-16le`.
+    pub fn parse_encoding(label: &str) -> Option<&'static Encoding> {
+        Encoding::for_label(label.trim().as_bytes())
+    }
+
+    /// Decodes file contents into `\n`-joined text without a trailing newline,
+    /// detecting the format it was stored in.
+    ///
+    /// The encoding comes from the BOM if there is one, otherwise from
+    /// `sniff_encoding`. A file is `dos` when all of its line breaks are
+    /// `\r\n`. A file that mixes them is `unix`, with the `\r`s kept at the
+    /// ends of their lines, so it round-trips. Binary files decode to a hex
+    /// dump instead, and so do files that aren't valid in their encoding,
+    /// e.g. UTF-8 with a BOM and stray bytes, so saving doesn't replace those.
+    pub fn decode(bytes: &[u8]) -> Result<(String, Self)> {
+        let binary = || {
+            let format = Self {
+                binary: true,
+                ..Self::new()
+            };
+            Ok((hex::dump(bytes), format))
+        };
+        if Self::is_binary(bytes) {
+            return binary();
+        }
+
+        let (encoding, bom, body) = match Encoding::for_bom(bytes) {
+            Some((encoding, bom_len)) => (encoding, true, &bytes[bom_len..]),
+            None => (Self::sniff_encoding(bytes), false, bytes),
+        };
+
+        let (text, had_errors) = encoding.decode_without_bom_handling(body);
+        if had_errors {
+            return binary();
+        }
+        let mut text = text.into_owned();
+
+        let newlines = text.matches('\n').count();
+        let crlfs = text.matches("\r\n").count();
+        let line_ending = if crlfs > 0 && crlfs == newlines {
+            LineEnding::Dos
+        } else {
+            LineEnding::Unix
+        };
+
+        if line_ending == LineEnding::Dos {
+            text = text.replace("\r\n", "\n");
+        }
+
+        let final_newline = text.ends_with('\n');
+        if final_newline {
+            text.pop();
+        }
+
+        Ok((
+            text,
+            Self {
+                line_ending,
+                final_newline,
+                bom,
+                encoding,
+                binary: false,
+            },
+        ))
+    }
+
+    /// Text has no NUL bytes (apart from UTF-16, where they are every other
+    /// byte) and few control characters.
+    fn is_binary(bytes: &[u8]) -> bool {
+        let head = &bytes[..bytes.len().min(SNIFF_LEN)];
+        if Encoding::for_bom(bytes).is_some() {
+            return false;
+        }
+        let encoding = Self::sniff_encoding(bytes);
+        if encoding == UTF_16LE || encoding == UTF_16BE {
+            return false;
+        }
+
+        let controls = head
+            .iter()
+            .filter(|&&b| b < 0x20 && !b"\t\n\r\x0c\x1b".contains(&b))
+            .count();
+        head.contains(&0) || controls * 10 > head.len()
+    }
+
+    /// Guesses the encoding of a file without BOM: UTF-16 if most high or low
+    /// bytes of the leading code units are zero, UTF-8 if it decodes as such,
+    /// and windows-1252 (a Latin-1 superset that accepts any byte) otherwise.
+    fn sniff_encoding(bytes: &[u8]) -> &'static Encoding {
+        let head = &bytes[..bytes.len().min(SNIFF_LEN)];
+        let units = head.len() / 2;
+        if bytes.len().is_multiple_of(2) && units > 0 {
+            let even_zeros = head.iter().step_by(2).filter(|&&b| b == 0).count();
+            let odd_zeros = head.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+            if odd_zeros * 10 >= units * 4 && even_zeros * 10 < units {
+                return UTF_16LE;
+            } else if even_zeros * 10 >= units * 4 && odd_zeros * 10 < units {
+                return UTF_16BE;
+            }
+        }
+
+        if std::str::from_utf8(bytes).is_ok() {
+            UTF_8
+        } else {
+            WINDOWS_1252
+        }
+    }
+
+    /// Writes `lines` back in this format.
+    pub fn encode<W: Write>(
+        &self,
+        writer: &mut W,
+        lines: impl ExactSizeIterator<Item = impl AsRef<str>>,
+    ) -> Result<()> {
+        if self.binary {
+            writer.write_all(&hex::parse(lines)?)?;
+            return Ok(());
+        }
+
+        if self.bom {
+            writer.write_all(&self.encode_str("\u{FEFF}")?)?;
+        }
+
+        let line_ending = self.encode_str(self.line_ending.as_str())?;
+        let len = lines.len();
+        for (i, line) in lines.enumerate() {
+            writer.write_all(&self.encode_str(line.as_ref())?)?;
+            if i + 1 < len || self.final_newline {
+                writer.write_all(&line_ending)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encodes `text` in this file's encoding, failing on characters the
+    /// encoding can't represent instead of silently replacing them.
+    fn encode_str(&self, text: &str) -> Result<Vec<u8>> {
+        // encoding_rs only decodes UTF-16, so it is encoded by hand.
+        if self.encoding == UTF_16LE {
+            return Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect());
+        } else if self.encoding == UTF_16BE {
+            return Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect());
+        }
+
+        let (bytes, _, had_errors) = self.encoding.encode(text);
+        if had_errors {
+            bail!(
+                "Text can't be represented in {}. Change fileencoding to save",
+                self.encoding_name()
+            );
+        }
+
+        Ok(bytes.into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) -> (FileFormat, Vec<u8>) {
+        let (text, format) = FileFormat::decode(bytes).unwrap();
+        let mut out = Vec::new();
+        let lines: Vec<_> = text.split('\n').collect();
+        format.encode(&mut out, lines.iter()).unwrap();
+        (format, out)
+    }
+
+    #[test]
+    fn keeps_crlf_and_missing_final_newline() {
+        let (format, out) = round_trip(b"a\r\nb\r\nc");
+        assert_eq!(format.line_ending, LineEnding::Dos);
+        assert!(!format.final_newline);
+        assert_eq!(out, b"a\r\nb\r\nc");
+    }
+
+    #[test]
+    fn keeps_bom_and_mixed_endings() {
+        let (format, out) = round_trip(b"\xEF\xBB\xBFa\nb\r\nc\n");
+        assert!(format.bom);
+        assert_eq!(format.line_ending, LineEnding::Unix);
+        assert!(format.final_newline);
+        assert_eq!(out, b"\xEF\xBB\xBFa\nb\r\nc\n");
+
+        let (text, format) = FileFormat::decode(b"a\r\nb\nc\r\nd\r\n").unwrap();
+        assert_eq!(format.line_ending, LineEnding::Unix);
+        assert_eq!(text, "a\r\nb\nc\r\nd\r");
+        let (_, out) = round_trip(b"a\r\nb\nc\r\nd\r\n");
+        assert_eq!(out, b"a\r\nb\nc\r\nd\r\n");
+    }
+
+    #[test]
+    fn empty_file_stays_empty() {
+        let (_, out) = round_trip(b"");
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn latin1_round_trips() {
+        let (format, out) = round_trip(b"caf\xE9\n");
+        assert_eq!(format.encoding, WINDOWS_1252);
+        assert_eq!(out, b"caf\xE9\n");
+    }
+
+    #[test]
+    fn utf16_with_and_without_bom() {
+        let (format, out) = round_trip(b"\xFF\xFEa\x00\r\x00\n\x00");
+        assert_eq!(format.encoding, UTF_16LE);
+        assert!(format.bom);
+        assert_eq!(format.line_ending, LineEnding::Dos);
+        assert_eq!(out, b"\xFF\xFEa\x00\r\x00\n\x00");
+
+        let (text, format) = FileFormat::decode(b"\x00h\x00i\x00\n").unwrap();
+        assert_eq!(format.encoding, UTF_16BE);
+        assert_eq!(text, "hi");
+    }
+
+    #[test]
+    fn binary_files_round_trip_as_hex() {
+        let bytes = b"\x7fELF\x02\x01\x01\x00\x00\x00\xff\n";
+        let (format, out) = round_trip(bytes);
+        assert!(format.binary);
+        assert_eq!(out, bytes);
+    }
+
+    #[test]
+    fn invalid_text_round_trips_as_hex() {
+        for bytes in [
+            &b"\xEF\xBB\xBFok \xFF\xFE bad\n"[..],
+            &b"\xFF\xFEa\x00\x00\xD8b\x00"[..],
+        ] {
+            let (format, out) = round_trip(bytes);
+            assert!(format.binary);
+            assert_eq!(out, bytes);
+        }
+    }
+
+    #[test]
+    fn unmappable_characters_fail_to_encode() {
+        let mut format = FileFormat::new();
+        format.encoding = WINDOWS_1252;
+        assert!(format.encode(&mut Vec::new(), ["한글"].iter()).is_err());
+    }
+}
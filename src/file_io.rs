@@ -0,0 +1,230 @@
+use anyhow::{Context as AnyhowContext, Result};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    os::unix::fs::{MetadataExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// What a file looked like on disk when the editor last read or wrote it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiskStamp {
+    pub mtime: Option<SystemTime>,
+    pub size: u64,
+    /// Hash of the raw bytes, to tell a real change from a mere touch.
+    pub hash: u64,
+}
+
+impl DiskStamp {
+    pub fn new(file_path: &str, hash: u64) -> Self {
+        let metadata = fs::metadata(file_path).ok();
+        Self {
+            mtime: metadata.as_ref().and_then(|m| m.modified().ok()),
+            size: metadata.map_or(0, |m| m.len()),
+            hash,
+        }
+    }
+
+    /// Cheap check whether the file may have changed, without reading it.
+    pub fn metadata_matches(&self, file_path: &str) -> bool {
+        match fs::metadata(file_path) {
+            Ok(metadata) => metadata.modified().ok() == self.mtime && metadata.len() == self.size,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Replaces the contents of `file_path` with `bytes` without ever leaving a
+/// half-written file behind.
+///
+/// The data is written and fsynced to a temp file in the same directory, which
+/// is then renamed over the original. Symlinks are followed so the link stays
+/// intact, and the original's permissions and owner carry over. With `backup`
+/// the previous contents are kept in `file~`. Hard-linked files are written
+/// in place instead, since a rename would split them from their other names.
+pub fn write_atomic(file_path: &str, bytes: &[u8], backup: bool) -> Result<()> {
+    let target = resolve_symlink(Path::new(file_path))?;
+    let metadata = fs::metadata(&target).ok();
+
+    if backup && metadata.is_some() {
+        let mut backup_path = target.clone().into_os_string();
+        backup_path.push("~");
+        fs::copy(&target, &backup_path)
+            .with_context(|| format!("Failed to write backup {backup_path:?}"))?;
+    }
+
+    if metadata.as_ref().is_some_and(|m| m.nlink() > 1) {
+        return write_in_place(&target, bytes);
+    }
+
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = target
+        .file_name()
+        .with_context(|| format!("Invalid file name {target:?}"))?
+        .to_string_lossy();
+    let temp_path = dir.join(format!(".{file_name}.hoditor-{}.tmp", std::process::id()));
+
+    let result = write_temp(&temp_path, bytes, metadata.as_ref()).and_then(|()| {
+        fs::rename(&temp_path, &target).with_context(|| format!("Failed to replace {target:?}"))
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+        return result;
+    }
+
+    // Persist the rename itself; not all filesystems support syncing a directory.
+    if let Ok(dir) = File::open(&dir) {
+        let _ = dir.sync_all();
+    }
+
+    Ok(())
+}
+
+/// Writes the temp file, which is never readable by more users than the
+/// original: it is created with the original's mode, and gets its exact
+/// permissions and owner before any bytes go in.
+fn write_temp(temp_path: &Path, bytes: &[u8], metadata: Option<&fs::Metadata>) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    if let Some(metadata) = metadata {
+        options.mode(metadata.mode() & 0o7777);
+    }
+    let mut f = options
+        .open(temp_path)
+        .with_context(|| format!("Failed to create {temp_path:?}"))?;
+
+    if let Some(metadata) = metadata {
+        f.set_permissions(metadata.permissions())
+            .with_context(|| format!("Failed to set permissions of {temp_path:?}"))?;
+        // Only root can give files away; keeping our own ownership is fine otherwise.
+        let _ = std::os::unix::fs::fchown(&f, Some(metadata.uid()), Some(metadata.gid()));
+    }
+
+    f.write_all(bytes)
+        .with_context(|| format!("Failed to write {temp_path:?}"))?;
+    f.sync_all()
+        .with_context(|| format!("Failed to sync {temp_path:?}"))?;
+
+    Ok(())
+}
+
+/// Overwrites `target` and only then cuts it to the new length, so a failed
+/// write never leaves it truncated.
+fn write_in_place(target: &Path, bytes: &[u8]) -> Result<()> {
+    let mut f = OpenOptions::new()
+        .write(true)
+        .open(target)
+        .with_context(|| format!("Failed to open {target:?}"))?;
+    f.write_all(bytes)
+        .with_context(|| format!("Failed to write {target:?}"))?;
+    f.set_len(bytes.len() as u64)
+        .with_context(|| format!("Failed to truncate {target:?}"))?;
+    f.sync_all()
+        .with_context(|| format!("Failed to sync {target:?}"))?;
+
+    Ok(())
+}
+
+/// Whether `file_path` can be opened for writing, without modifying it.
+pub fn is_writable(file_path: &str) -> bool {
+    OpenOptions::new().write(true).open(file_path).is_ok()
+}
+
+/// Per-file state of `kind` (undo history, swap journal) lives in
+/// `$XDG_STATE_HOME/hoditor/<kind>/<absolute path with '/' replaced by '%'>`.
+pub fn state_path(kind: &str, file_path: &str) -> Result<PathBuf> {
+    let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let home = std::env::var_os("HOME").context("HOME is not set")?;
+            Path::new(&home).join(".local/state")
+        }
+    };
+
+    let abs_path = std::path::absolute(file_path)
+        .with_context(|| format!("Failed to resolve path. {file_path}"))?;
+    let file_name = abs_path.to_string_lossy().replace('/', "%");
+
+    Ok(state_dir.join("hoditor").join(kind).join(file_name))
+}
+
+/// Follows `path` through any chain of symlinks. A dangling link resolves to
+/// the path it points at, so saving creates the missing target.
+fn resolve_symlink(path: &Path) -> Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let link = fs::read_link(&path)
+                    .with_context(|| format!("Failed to read link {path:?}"))?;
+                path = match path.parent() {
+                    Some(parent) => parent.join(link),
+                    None => link,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+
+    anyhow::bail!("Too many levels of symbolic links {path:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{PermissionsExt, symlink};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hoditor-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn keeps_permissions_symlink_and_backup() {
+        let dir = temp_dir("file_io");
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+        symlink("target.txt", &link).unwrap();
+
+        write_atomic(link.to_str().unwrap(), b"new", true).unwrap();
+
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("target.txt~")).unwrap(), "old");
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_hard_linked_files_in_place() {
+        let dir = temp_dir("file_io_hard_link");
+        let target = dir.join("target.txt");
+        let other = dir.join("other.txt");
+        fs::write(&target, "old and long").unwrap();
+        fs::hard_link(&target, &other).unwrap();
+
+        write_atomic(target.to_str().unwrap(), b"new", false).unwrap();
+
+        assert_eq!(fs::read_to_string(&other).unwrap(), "new");
+        assert_eq!(fs::metadata(&target).unwrap().nlink(), 2);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{Context as AnyhowContext, Result, bail};
+use std::fmt::Write;
+
+/// Bytes shown on each line of a hex dump.
+const BYTES_PER_LINE: usize = 16;
+
+/// Column of the first hex digit, after the `oooooooo: ` offset.
+const HEX_START: usize = 10;
+
+/// Column of the ASCII gutter, after one `xx ` per byte and a separating space.
+const ASCII_START: usize = HEX_START + BYTES_PER_LINE * 3 + 1;
+
+/// Renders `bytes` as `\n`-joined lines of `oooooooo: xx xx ..  ascii`.
+pub fn dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(BYTES_PER_LINE)
+        .enumerate()
+        .map(|(i, chunk)| line(i * BYTES_PER_LINE, chunk))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reads the bytes back from dump lines. Only the hex column counts, so the
+/// offsets and ASCII gutter don't need to be up to date.
+pub fn parse(lines: impl Iterator<Item = impl AsRef<str>>) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for (row, line) in lines.enumerate() {
+        let (_, line_bytes) = parse_line(line.as_ref())
+            .with_context(|| format!("Invalid hex dump on line {}", row + 1))?;
+        bytes.extend(line_bytes);
+    }
+
+    Ok(bytes)
+}
+
+/// Overwrites the byte under `col` with `ch`: one nibble if `col` is on a hex
+/// digit, the whole byte if it is in the ASCII gutter. Returns the updated
+/// line and the column to move to, or `None` if `ch` doesn't fit there.
+pub fn overwrite(dump_line: &str, col: usize, ch: char) -> Option<(String, usize)> {
+    let (offset, mut bytes) = parse_line(dump_line).ok()?;
+    let hex_end = HEX_START + bytes.len() * 3;
+    let ascii_end = ASCII_START + bytes.len();
+
+    let next_col = if (HEX_START..hex_end).contains(&col) && (col - HEX_START) % 3 != 2 {
+        let digit = ch.to_digit(16)? as u8;
+        let index = (col - HEX_START) / 3;
+        let byte = &mut bytes[index];
+        if (col - HEX_START).is_multiple_of(3) {
+            *byte = (digit << 4) | (*byte & 0x0f);
+            col + 1
+        } else {
+            *byte = (*byte & 0xf0) | digit;
+            (col + 2).min(hex_end - 2)
+        }
+    } else if (ASCII_START..ascii_end).contains(&col) && (ch == ' ' || ch.is_ascii_graphic()) {
+        bytes[col - ASCII_START] = ch as u8;
+        (col + 1).min(ascii_end - 1)
+    } else {
+        return None;
+    };
+
+    Some((line(offset, &bytes), next_col))
+}
+
+fn line(offset: usize, chunk: &[u8]) -> String {
+    let mut line = format!("{offset:08x}: ");
+    for i in 0..BYTES_PER_LINE {
+        match chunk.get(i) {
+            Some(byte) => {
+                let _ = write!(line, "{byte:02x} ");
+            }
+            None => line.push_str("   "),
+        }
+    }
+    line.push(' ');
+    line.extend(chunk.iter().map(|&byte| {
+        if byte == b' ' || byte.is_ascii_graphic() {
+            byte as char
+        } else {
+            '.'
+        }
+    }));
+
+    line
+}
+
+fn parse_line(line: &str) -> Result<(usize, Vec<u8>)> {
+    if line.is_empty() {
+        return Ok((0, Vec::new()));
+    }
+
+    let offset = line
+        .get(..HEX_START - 2)
+        .and_then(|offset| usize::from_str_radix(offset, 16).ok())
+        .context("Missing offset")?;
+    let hex = line
+        .get(HEX_START..ASCII_START.min(line.len()))
+        .context("Missing hex column")?;
+
+    let mut bytes = Vec::with_capacity(BYTES_PER_LINE);
+    for token in hex.split_whitespace() {
+        if token.len() != 2 {
+            bail!("Invalid byte {token}");
+        }
+        bytes.push(u8::from_str_radix(token, 16).with_context(|| format!("Invalid byte {token}"))?);
+    }
+
+    Ok((offset, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_round_trips() {
+        let bytes: Vec<u8> = (0..=40).collect();
+        let text = dump(&bytes);
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.starts_with("00000000: 00 01 02"));
+        assert_eq!(parse(text.lines()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn overwrites_nibbles_and_ascii() {
+        let text = dump(b"AB");
+        let (text, col) = overwrite(&text, HEX_START, '6').unwrap();
+        assert_eq!(col, HEX_START + 1);
+        let (text, col) = overwrite(&text, col, 'a').unwrap();
+        assert_eq!(col, HEX_START + 3);
+        assert!(text.ends_with("  jB"));
+
+        let (text, _) = overwrite(&text, ASCII_START + 1, 'z').unwrap();
+        assert_eq!(parse([text].iter()).unwrap(), b"jz");
+        assert!(overwrite(&dump(b"AB"), HEX_START, 'g').is_none());
+    }
+}
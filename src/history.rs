@@ -0,0 +1,399 @@
+use crate::cursor::Position;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+/// A single reversible mutation of the buffer, addressed by byte offset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Edit {
+    Insert { offset: usize, text: String },
+    Remove { offset: usize, text: String },
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        match self {
+            Edit::Insert { offset, text } => Edit::Remove {
+                offset: *offset,
+                text: text.clone(),
+            },
+            Edit::Remove { offset, text } => Edit::Insert {
+                offset: *offset,
+                text: text.clone(),
+            },
+        }
+    }
+}
+
+/// A group of edits undone and redone as one step, e.g. one insert-mode session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Change {
+    pub edits: Vec<Edit>,
+    pub cursor_before: Position,
+    pub cursor_after: Position,
+}
+
+/// One move through the undo tree: revert a node's change or apply it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Revert(usize),
+    Apply(usize),
+}
+
+/// Argument of `:earlier` / `:later`: a number of changes or a time span.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Travel {
+    Steps(usize),
+    Time(Duration),
+}
+
+impl Travel {
+    /// Parses `5`, `30s`, `5m`, `2h` or `1d`. An empty argument means one step.
+    /// Spans too long to represent are `None`.
+    pub fn parse(arg: &str) -> Option<Self> {
+        let arg = arg.trim();
+        if arg.is_empty() {
+            return Some(Travel::Steps(1));
+        }
+
+        let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+        let (num, unit) = arg.split_at(split);
+        let num: u64 = num.parse().ok()?;
+        let unit_secs = match unit {
+            "" => return Some(Travel::Steps(num.try_into().unwrap_or(usize::MAX))),
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 60 * 60 * 24,
+            _ => return None,
+        };
+
+        let span = Duration::from_secs(num.checked_mul(unit_secs)?);
+        // A span that can't be added to a time would overflow in `later`.
+        SystemTime::now().checked_add(span)?;
+        Some(Travel::Time(span))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Node {
+    parent: usize,
+    /// Child that redo follows; the most recently created or visited branch.
+    cur_child: Option<usize>,
+    children: Vec<usize>,
+    change: Option<Change>,
+    time: SystemTime,
+}
+
+/// Undo tree. Node ids are sequence numbers: node 0 is the original text and
+/// every committed change appends a node under the current one, so undoing and
+/// then editing starts a new branch instead of discarding the old one.
+#[derive(Serialize, Deserialize)]
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+    #[serde(skip)]
+    pending: Option<Change>,
+    /// Keeps the open group from being committed, see `begin_group`.
+    #[serde(skip)]
+    grouped: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node {
+                parent: 0,
+                cur_child: None,
+                children: Vec::new(),
+                change: None,
+                time: SystemTime::now(),
+            }],
+            current: 0,
+            pending: None,
+            grouped: false,
+        }
+    }
+
+    /// Opens a new change group unless one is already open.
+    pub fn begin(&mut self, cursor: Position) {
+        if self.pending.is_none() {
+            self.pending = Some(Change {
+                edits: Vec::new(),
+                cursor_before: cursor,
+                cursor_after: cursor,
+            });
+        }
+    }
+
+    pub fn record(&mut self, edit: Edit, cursor: Position) {
+        self.begin(cursor);
+        if let Some(change) = self.pending.as_mut() {
+            change.edits.push(edit);
+        }
+    }
+
+    /// Opens a change group that stays open until `end_group`, so that every
+    /// command run in between is undone as one step, e.g. for `:g`.
+    pub fn begin_group(&mut self, cursor: Position) {
+        self.begin(cursor);
+        self.grouped = true;
+    }
+
+    pub fn end_group(&mut self, cursor: Position) {
+        self.grouped = false;
+        self.commit(cursor);
+    }
+
+    /// Closes the open change group. Empty groups are dropped.
+    pub fn commit(&mut self, cursor: Position) {
+        if self.grouped {
+            return;
+        }
+        if let Some(mut change) = self.pending.take()
+            && !change.edits.is_empty()
+        {
+            change.cursor_after = cursor;
+            let id = self.nodes.len();
+            self.nodes.push(Node {
+                parent: self.current,
+                cur_child: None,
+                children: Vec::new(),
+                change: Some(change),
+                time: SystemTime::now(),
+            });
+            let parent = &mut self.nodes[self.current];
+            parent.children.push(id);
+            parent.cur_child = Some(id);
+            self.current = id;
+        }
+    }
+
+    /// Checks the links of a deserialized tree so a corrupt undo file can't
+    /// cause out-of-bound panics later.
+    pub fn is_consistent(&self) -> bool {
+        if self.nodes.is_empty() || self.current >= self.nodes.len() {
+            return false;
+        }
+
+        self.nodes.iter().enumerate().all(|(id, node)| {
+            let linked = |child: &usize| *child > id && *child < self.nodes.len();
+            (id == 0 || (node.parent < id && node.change.is_some()))
+                && node.children.iter().all(linked)
+                && node.cur_child.as_ref().is_none_or(linked)
+        })
+    }
+
+    pub fn change(&self, id: usize) -> &Change {
+        self.nodes[id]
+            .change
+            .as_ref()
+            .expect("[change] root node has no change")
+    }
+
+    /// Whether the open change group holds any edits yet.
+    pub fn has_pending(&self) -> bool {
+        self.pending
+            .as_ref()
+            .is_some_and(|change| !change.edits.is_empty())
+    }
+
+    pub fn seq(&self) -> usize {
+        self.current
+    }
+
+    pub fn undo(&mut self) -> Vec<Step> {
+        if self.current == 0 {
+            return Vec::new();
+        }
+
+        self.goto(self.nodes[self.current].parent)
+    }
+
+    pub fn redo(&mut self) -> Vec<Step> {
+        match self.nodes[self.current].cur_child {
+            Some(child) => self.goto(child),
+            None => Vec::new(),
+        }
+    }
+
+    /// Moves `delta` sequence numbers away from the current state (`g-`/`g+`).
+    pub fn step_seq(&mut self, delta: isize) -> Vec<Step> {
+        let target = self
+            .current
+            .saturating_add_signed(delta)
+            .min(self.nodes.len() - 1);
+
+        self.goto(target)
+    }
+
+    pub fn earlier(&mut self, travel: Travel) -> Vec<Step> {
+        match travel {
+            Travel::Steps(n) => self.step_seq(-isize::try_from(n).unwrap_or(isize::MAX)),
+            Travel::Time(span) => {
+                let time = self.nodes[self.current].time;
+                let target = time.checked_sub(span).unwrap_or(SystemTime::UNIX_EPOCH);
+                let seq = (0..=self.current)
+                    .rev()
+                    .find(|&id| self.nodes[id].time <= target)
+                    .unwrap_or(0);
+                self.goto(seq)
+            }
+        }
+    }
+
+    pub fn later(&mut self, travel: Travel) -> Vec<Step> {
+        match travel {
+            Travel::Steps(n) => self.step_seq(isize::try_from(n).unwrap_or(isize::MAX)),
+            Travel::Time(span) => {
+                let time = self.nodes[self.current].time;
+                let seq = time
+                    .checked_add(span)
+                    .and_then(|target| {
+                        (self.current..self.nodes.len()).find(|&id| self.nodes[id].time >= target)
+                    })
+                    .unwrap_or(self.nodes.len() - 1);
+                self.goto(seq)
+            }
+        }
+    }
+
+    /// Returns the steps leading from the current state to `target` through
+    /// their common ancestor, and makes `target` current.
+    fn goto(&mut self, target: usize) -> Vec<Step> {
+        let mut down = Vec::new();
+        let mut node = target;
+        let ancestors = {
+            let mut ancestors = vec![target];
+            while node != 0 {
+                node = self.nodes[node].parent;
+                ancestors.push(node);
+            }
+            ancestors
+        };
+
+        let mut steps = Vec::new();
+        let mut node = self.current;
+        while !ancestors.contains(&node) {
+            steps.push(Step::Revert(node));
+            let parent = self.nodes[node].parent;
+            self.nodes[parent].cur_child = Some(node);
+            node = parent;
+        }
+
+        for &id in ancestors.iter().take_while(|&&id| id != node) {
+            down.push(id);
+        }
+        for &id in down.iter().rev() {
+            let parent = self.nodes[id].parent;
+            self.nodes[parent].cur_child = Some(id);
+            steps.push(Step::Apply(id));
+        }
+
+        self.current = target;
+        steps
+    }
+
+    /// Renders the tree for `:undotree`, one node per line, newest branch last.
+    /// The current state is marked with `>`. Only branches are indented, so a
+    /// straight run of changes stays in one column.
+    pub fn tree_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        // Nodes still to render, with the prefix of their line and the one
+        // their children continue from; the next node is on top.
+        let mut stack = vec![(0, String::new(), String::new())];
+        while let Some((id, head, tail)) = stack.pop() {
+            let node = &self.nodes[id];
+            let marker = if id == self.current { '>' } else { ' ' };
+            let time = chrono::DateTime::<chrono::Local>::from(node.time).format("%H:%M:%S");
+            lines.push(format!("{marker}{head}{id} {time}"));
+
+            if let [child] = node.children[..] {
+                stack.push((child, tail.clone(), tail));
+                continue;
+            }
+            for (i, &child) in node.children.iter().enumerate().rev() {
+                let (branch, next_tail) = if i + 1 == node.children.len() {
+                    ("└ ", "  ")
+                } else {
+                    ("├ ", "│ ")
+                };
+                stack.push((
+                    child,
+                    format!("{tail}{branch}"),
+                    format!("{tail}{next_tail}"),
+                ));
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_travel_argument() {
+        assert_eq!(Travel::parse(""), Some(Travel::Steps(1)));
+        assert_eq!(Travel::parse("3"), Some(Travel::Steps(3)));
+        assert_eq!(
+            Travel::parse("5m"),
+            Some(Travel::Time(Duration::from_secs(300)))
+        );
+        assert_eq!(
+            Travel::parse("30s"),
+            Some(Travel::Time(Duration::from_secs(30)))
+        );
+        assert_eq!(Travel::parse("5x"), None);
+        assert_eq!(Travel::parse("m"), None);
+        assert_eq!(Travel::parse("999999999999999d"), None);
+        assert_eq!(Travel::parse("99999999999999999999"), None);
+        assert_eq!(
+            Travel::parse("18446744073709551615"),
+            Some(Travel::Steps(usize::MAX))
+        );
+    }
+
+    #[test]
+    fn huge_travels_stop_at_the_ends() {
+        let mut history = History::new();
+        let cursor = Position { row: 0, col: 0 };
+        for offset in 0..3 {
+            let text = String::from("x");
+            history.record(Edit::Insert { offset, text }, cursor);
+            history.commit(cursor);
+        }
+
+        history.earlier(Travel::Steps(usize::MAX));
+        assert_eq!(history.seq(), 0);
+        history.later(Travel::Steps(usize::MAX));
+        assert_eq!(history.seq(), 3);
+        history.earlier(Travel::Time(Duration::MAX));
+        assert_eq!(history.seq(), 0);
+        history.later(Travel::Time(Duration::MAX));
+        assert_eq!(history.seq(), 3);
+    }
+
+    #[test]
+    fn tree_indents_only_branches() {
+        let mut history = History::new();
+        let cursor = Position { row: 0, col: 0 };
+        let mut change = |history: &mut History| {
+            let text = String::from("x");
+            history.record(Edit::Insert { offset: 0, text }, cursor);
+            history.commit(cursor);
+        };
+        change(&mut history);
+        change(&mut history);
+        history.undo();
+        change(&mut history);
+        change(&mut history);
+
+        let lines: Vec<String> = history
+            .tree_lines()
+            .iter()
+            .map(|line| line.rsplit_once(' ').unwrap().0.to_string())
+            .collect();
+        assert_eq!(lines, vec![" 0", " 1", " ├ 2", " └ 3", ">  4"]);
+    }
+}
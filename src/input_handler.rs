@@ -1,24 +1,52 @@
 use crate::{
     app::Context,
-    buffer::Buffer,
+    buffer::{Buffer, DiskChange},
+    buffer_list::GlobalMark,
+    clipboard,
     cmd_dispatcher::{self, CmdDispatcher},
-    cursor::{CursorStyle, SCROLL_HEIGHT},
+    cursor::{CursorStyle, Position, SCROLL_HEIGHT},
+    ex,
+    file_format::{FileFormat, LineEnding},
+    history::Travel,
     logger::Logger,
-    state::Mode,
-};
-use std::{
-    env,
-    fs::File,
-    io::{BufWriter, Write},
-    os::unix::process::CommandExt,
-    process::Command as ProcessCommand,
+    marks::{LAST_INSERT, Marks, PREVIOUS_JUMP},
+    motion::{self, Motion, MotionKind, Screen, WordChars},
+    operator::Operator,
+    registers::{Register, Registers, UNNAMED},
+    search,
+    state::{Mode, Panel, Prompt},
+    substitute::{self, Flags, Session, Substitute},
+    syntax::Syntax,
+    text_object::TextObject,
+    visual::{BlockInsert, Selection, SelectionKind},
 };
+use std::{env, os::unix::process::CommandExt, process::Command as ProcessCommand};
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind};
 
-use crossterm::event::{Event, KeyCode, MouseEventKind};
+/// Answer to a prompt when Esc is pressed.
+const ESC: char = '\u{1b}';
+
+/// Number of search patterns kept for recalling with Up and Down.
+const SEARCH_HISTORY_LEN: usize = 50;
 
 pub struct EventHandler {
     normal_dispatcher: CmdDispatcher,
+    visual_dispatcher: CmdDispatcher,
     cmd_dispatcher: CmdDispatcher,
+    /// Register named with `"x` for the next normal or visual mode command.
+    register: Option<char>,
+    /// `"` was typed and the register name comes next.
+    awaiting_register: bool,
+    /// Command line of the ex command just returned, for `":`.
+    ex_line: Option<String>,
+    /// Search pattern being typed after `/` or `?`.
+    search_line: String,
+    search_backward: bool,
+    /// Entered search patterns, oldest first.
+    search_history: Vec<String>,
+    /// Entry of `search_history` shown while browsing it.
+    history_index: Option<usize>,
 }
 
 impl EventHandler {
@@ -26,47 +54,196 @@ impl EventHandler {
         Logger::log(format!("Event Handler Create"));
 
         let mut normal_dispatcher = CmdDispatcher::new();
-        normal_dispatcher.register("h", Command::MoveCursor { dx: -1, dy: 0 });
-        normal_dispatcher.register("j", Command::MoveCursor { dx: 0, dy: 1 });
-        normal_dispatcher.register("k", Command::MoveCursor { dx: 0, dy: -1 });
-        normal_dispatcher.register("l", Command::MoveCursor { dx: 1, dy: 0 });
-        normal_dispatcher.register("gg", Command::MoveCursorSOF);
-        normal_dispatcher.register("G", Command::MoveCursorEOF);
         normal_dispatcher.register("i", Command::ChangeMode(Mode::Edit));
         normal_dispatcher.register("o", Command::InsertEmptyLineBelow);
         normal_dispatcher.register("O", Command::InsertEmptyLineAbove);
         normal_dispatcher.register("A", Command::MoveCursorToLineEnd);
         normal_dispatcher.register(":", Command::ChangeMode(Mode::Cmd));
+        normal_dispatcher.register("v", Command::ChangeMode(Mode::Visual));
+        normal_dispatcher.register("V", Command::ChangeMode(Mode::VisualLine));
+        normal_dispatcher.register("u", Command::Undo);
+        normal_dispatcher.register("g-", Command::HistoryStep(-1));
+        normal_dispatcher.register("g+", Command::HistoryStep(1));
+        normal_dispatcher.register(
+            "Y",
+            Command::Operate {
+                operator: Operator::Yank,
+                motion: Motion::Lines,
+                count: None,
+                register: UNNAMED,
+            },
+        );
+        normal_dispatcher.register("x", Command::DeleteChar { register: UNNAMED });
+        let put = |before| Command::Put {
+            register: UNNAMED,
+            before,
+        };
+        normal_dispatcher.register("p", put(false));
+        normal_dispatcher.register("P", put(true));
+        normal_dispatcher.register("n", Command::SearchNext { reverse: false });
+        normal_dispatcher.register("N", Command::SearchNext { reverse: true });
+        normal_dispatcher.register("*", Command::SearchWord { backward: false });
+        normal_dispatcher.register("#", Command::SearchWord { backward: true });
+        normal_dispatcher.register("&", Command::RepeatSubstitute(String::new(), String::new()));
+        for name in ('a'..='z').chain('A'..='Z').chain(['\'', '`']) {
+            normal_dispatcher.register(&format!("m{name}"), Command::SetMark(name));
+        }
+        for name in ('a'..='z')
+            .chain('A'..='Z')
+            .chain(['.', '^', '\'', '`', '<', '>'])
+        {
+            let line = Command::JumpToMark { name, exact: false };
+            let exact = Command::JumpToMark { name, exact: true };
+            normal_dispatcher.register(&format!("'{name}"), line);
+            normal_dispatcher.register(&format!("`{name}"), exact);
+        }
+
+        let mut visual_dispatcher = CmdDispatcher::new();
+        visual_dispatcher.register("v", Command::ChangeMode(Mode::Visual));
+        visual_dispatcher.register("V", Command::ChangeMode(Mode::VisualLine));
+        visual_dispatcher.register("o", Command::SwapVisualAnchor);
+        let operate = |operator| Command::VisualOperate {
+            operator,
+            register: UNNAMED,
+        };
+        for operator in Operator::ALL {
+            visual_dispatcher.register(operator.name(), operate(operator));
+        }
+        visual_dispatcher.register("x", operate(Operator::Delete));
+        visual_dispatcher.register("s", operate(Operator::Change));
+        visual_dispatcher.register("u", operate(Operator::Lower));
+        visual_dispatcher.register("U", operate(Operator::Upper));
+        visual_dispatcher.register("~", operate(Operator::ToggleCase));
+        visual_dispatcher.register("I", Command::VisualInsert { append: false });
+        visual_dispatcher.register("A", Command::VisualInsert { append: true });
 
         let mut cmd_dispatcher = CmdDispatcher::new();
         cmd_dispatcher.register("e", Command::OpenFile(String::new()));
         cmd_dispatcher.register("w", Command::Save);
+        cmd_dispatcher.register("w!", Command::Overwrite);
         cmd_dispatcher.register("W", Command::SaveAndRestart);
-        cmd_dispatcher.register("q", Command::TerminateApp);
+        cmd_dispatcher.register("q", Command::Quit);
+        cmd_dispatcher.register("q!", Command::TerminateApp);
+        cmd_dispatcher.register("qa", Command::Quit);
+        cmd_dispatcher.register("qa!", Command::TerminateApp);
+        cmd_dispatcher.register("wq", Command::WriteQuit);
+        cmd_dispatcher.register("wq!", Command::ForceWriteQuit);
+        cmd_dispatcher.register("x", Command::WriteQuitIfModified);
+        cmd_dispatcher.register("wqa", Command::WriteAllQuit);
+        cmd_dispatcher.register("xa", Command::WriteAllQuit);
+        cmd_dispatcher.register("earlier", Command::Earlier(String::new()));
+        cmd_dispatcher.register("later", Command::Later(String::new()));
+        cmd_dispatcher.register("undotree", Command::ToggleUndoTree);
+        cmd_dispatcher.register("set", Command::SetOption(String::new()));
+        cmd_dispatcher.register("ls", Command::ListBuffers);
+        cmd_dispatcher.register("b", Command::SwitchBuffer(String::new()));
+        cmd_dispatcher.register("bn", Command::CycleBuffer(1));
+        cmd_dispatcher.register("bp", Command::CycleBuffer(-1));
+        cmd_dispatcher.register("bd", Command::DeleteBuffer(String::new()));
+        cmd_dispatcher.register("registers", Command::ShowRegisters);
+        cmd_dispatcher.register("reg", Command::ShowRegisters);
+        cmd_dispatcher.register("display", Command::ShowRegisters);
+        cmd_dispatcher.register("di", Command::ShowRegisters);
+        cmd_dispatcher.register("nohlsearch", Command::ClearSearchHighlight);
+        cmd_dispatcher.register("noh", Command::ClearSearchHighlight);
+        let substitute = Command::Substitute {
+            range: String::new(),
+            args: String::new(),
+        };
+        cmd_dispatcher.register("s", substitute.clone());
+        cmd_dispatcher.register("substitute", substitute);
+        let repeat = |flags: &str| Command::RepeatSubstitute(String::new(), flags.to_string());
+        cmd_dispatcher.register("&", repeat(""));
+        cmd_dispatcher.register("&&", repeat("&"));
+        let delete = Command::DeleteLines {
+            range: String::new(),
+            register: UNNAMED,
+        };
+        cmd_dispatcher.register("d", delete.clone());
+        cmd_dispatcher.register("delete", delete);
+        cmd_dispatcher.register("norm", Command::Normal(String::new()));
+        cmd_dispatcher.register("normal", Command::Normal(String::new()));
+        let global = |invert| Command::Global {
+            range: String::new(),
+            args: String::new(),
+            invert,
+        };
+        cmd_dispatcher.register("g", global(false));
+        cmd_dispatcher.register("global", global(false));
+        cmd_dispatcher.register("g!", global(true));
+        cmd_dispatcher.register("global!", global(true));
+        cmd_dispatcher.register("v", global(true));
+        cmd_dispatcher.register("vglobal", global(true));
 
         Logger::log(format!("Event Handler Created"));
 
         Self {
             normal_dispatcher,
+            visual_dispatcher,
             cmd_dispatcher,
+            register: None,
+            awaiting_register: false,
+            ex_line: None,
+            search_line: String::new(),
+            search_backward: false,
+            search_history: Vec::new(),
+            history_index: None,
         }
     }
 
     pub fn get_cmd_buffer(&self, mode: Mode) -> String {
         match mode {
             Mode::Cmd => self.cmd_dispatcher.get_query(),
-            Mode::Normal => self.normal_dispatcher.get_query(),
-            Mode::Edit => String::new(),
+            Mode::Normal | Mode::Visual | Mode::VisualLine | Mode::VisualBlock => {
+                let register = match (self.awaiting_register, self.register) {
+                    (true, _) => String::from("\""),
+                    (false, Some(name)) => format!("\"{name}"),
+                    (false, None) => String::new(),
+                };
+                let dispatcher = if mode.is_visual() {
+                    &self.visual_dispatcher
+                } else {
+                    &self.normal_dispatcher
+                };
+                register + &dispatcher.get_query()
+            }
+            Mode::Search => {
+                let prefix = if self.search_backward { '?' } else { '/' };
+                format!("{prefix}{}", self.search_line)
+            }
+            Mode::Edit | Mode::Prompt => String::new(),
         }
     }
 
     pub fn handle(&mut self, event: Event, mode: Mode) -> Command {
         Logger::log(format!("Event: {:?}", event));
 
+        // Coming back from another program is when files tend to have changed.
+        if matches!(event, Event::FocusGained) && !matches!(mode, Mode::Prompt | Mode::Search) {
+            return Command::CheckDisk;
+        }
+
         match mode {
             Mode::Edit => Self::handle_edit_event(event),
             Mode::Normal => self.handle_normal_event(event),
             Mode::Cmd => self.handle_cmd_event(event),
+            Mode::Prompt => Self::handle_prompt_event(event),
+            Mode::Search => self.handle_search_event(event),
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => {
+                self.handle_visual_event(event, mode)
+            }
+        }
+    }
+
+    fn handle_prompt_event(event: Event) -> Command {
+        match event {
+            Event::Key(key) => match key.code {
+                KeyCode::Char(ch) => Command::AnswerPrompt(ch),
+                // Prompts that can be cancelled take Esc as a choice.
+                KeyCode::Esc => Command::AnswerPrompt(ESC),
+                _ => Command::DoNothing,
+            },
+            _ => Command::DoNothing,
         }
     }
 
@@ -84,14 +261,18 @@ impl EventHandler {
                 KeyCode::Down => Command::MoveCursor { dx: 0, dy: 1 },
                 _ => Command::DoNothing,
             },
-            Event::Mouse(mouse) => match mouse.kind {
-                MouseEventKind::ScrollUp => Command::ScrollUp,
-                MouseEventKind::ScrollDown => Command::ScrollDown,
-                MouseEventKind::Down(_) => Command::MoveCursorToMouse {
-                    row: mouse.row as usize,
-                    col: mouse.column as usize,
-                },
-                _ => Command::DoNothing,
+            Event::Mouse(mouse) => Self::handle_mouse_event(mouse),
+            _ => Command::DoNothing,
+        }
+    }
+
+    fn handle_mouse_event(mouse: MouseEvent) -> Command {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => Command::ScrollUp,
+            MouseEventKind::ScrollDown => Command::ScrollDown,
+            MouseEventKind::Down(_) => Command::MoveCursorToMouse {
+                row: mouse.row as usize,
+                col: mouse.column as usize,
             },
             _ => Command::DoNothing,
         }
@@ -100,26 +281,129 @@ impl EventHandler {
     fn handle_normal_event(&mut self, event: Event) -> Command {
         match event {
             Event::Key(key) => match key.code {
-                KeyCode::Char(ch) => {
-                    self.normal_dispatcher.push(ch);
-                    self.normal_dispatcher.get().unwrap_or(Command::DoNothing)
+                KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.normal_dispatcher.clear();
+                    Command::Redo
+                }
+                // Terminals send Ctrl-^ as Ctrl-6.
+                KeyCode::Char('^' | '6') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.normal_dispatcher.clear();
+                    Command::AlternateBuffer
+                }
+                KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.normal_dispatcher.clear();
+                    Command::ChangeMode(Mode::VisualBlock)
+                }
+                KeyCode::Char(ch) if self.awaiting_register => {
+                    self.awaiting_register = false;
+                    self.register = Registers::is_valid(ch).then_some(ch);
+                    Command::DoNothing
+                }
+                KeyCode::Char('"') if self.normal_dispatcher.get_query().is_empty() => {
+                    self.awaiting_register = true;
+                    Command::DoNothing
+                }
+                KeyCode::Char(ch @ ('/' | '?'))
+                    if self.normal_dispatcher.get_query().is_empty() =>
+                {
+                    self.search_backward = ch == '?';
+                    self.search_line.clear();
+                    self.history_index = None;
+                    Command::ChangeMode(Mode::Search)
+                }
+                KeyCode::Char(ch) => self.dispatch(ch, false),
+                // Esc cancels a pending operator or count before it quits.
+                KeyCode::Esc
+                    if !self.normal_dispatcher.get_query().is_empty()
+                        || self.register.is_some()
+                        || self.awaiting_register =>
+                {
+                    self.normal_dispatcher.clear();
+                    self.register = None;
+                    self.awaiting_register = false;
+                    Command::DoNothing
                 }
-                KeyCode::Esc => Command::TerminateApp,
+                KeyCode::Esc => Command::Quit,
                 _ => Command::DoNothing,
             },
-            Event::Mouse(mouse) => match mouse.kind {
-                MouseEventKind::ScrollUp => Command::ScrollUp,
-                MouseEventKind::ScrollDown => Command::ScrollDown,
-                MouseEventKind::Down(_) => Command::MoveCursorToMouse {
-                    row: mouse.row as usize,
-                    col: mouse.column as usize,
-                },
-                _ => Command::DoNothing,
+            Event::Mouse(mouse) => Self::handle_mouse_event(mouse),
+            _ => Command::DoNothing,
+        }
+    }
+
+    fn handle_visual_event(&mut self, event: Event, mode: Mode) -> Command {
+        let key = match event {
+            Event::Key(key) => key,
+            Event::Mouse(mouse) => return Self::handle_mouse_event(mouse),
+            _ => return Command::DoNothing,
+        };
+
+        match key.code {
+            KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.visual_dispatcher.clear();
+                Self::switch_visual(mode, Mode::VisualBlock)
+            }
+            KeyCode::Char(ch) if self.awaiting_register => {
+                self.awaiting_register = false;
+                self.register = Registers::is_valid(ch).then_some(ch);
+                Command::DoNothing
+            }
+            KeyCode::Char('"') if self.visual_dispatcher.get_query().is_empty() => {
+                self.awaiting_register = true;
+                Command::DoNothing
+            }
+            // Ex commands from visual mode apply to the selected lines.
+            KeyCode::Char(':') if self.visual_dispatcher.get_query().is_empty() => {
+                self.register = None;
+                self.cmd_dispatcher.clear();
+                "'<,'>".chars().for_each(|ch| self.cmd_dispatcher.push(ch));
+                Command::ChangeMode(Mode::Cmd)
+            }
+            KeyCode::Char(ch) => match self.dispatch(ch, true) {
+                Command::ChangeMode(target) => Self::switch_visual(mode, target),
+                cmd => cmd,
             },
+            KeyCode::Esc => {
+                self.visual_dispatcher.clear();
+                self.register = None;
+                self.awaiting_register = false;
+                Command::ChangeMode(Mode::Normal)
+            }
             _ => Command::DoNothing,
         }
     }
 
+    /// Feeds `ch` to the normal or visual mode commands and fills in the
+    /// register named before them.
+    fn dispatch(&mut self, ch: char, visual: bool) -> Command {
+        let dispatcher = if visual {
+            &mut self.visual_dispatcher
+        } else {
+            &mut self.normal_dispatcher
+        };
+        dispatcher.push(ch);
+        match dispatcher.get_keys(!visual) {
+            Some(cmd) => cmd.with_register(self.register.take().unwrap_or(UNNAMED)),
+            None => {
+                // An unknown sequence drops the register named for it.
+                if dispatcher.get_query().is_empty() {
+                    self.register = None;
+                }
+                Command::DoNothing
+            }
+        }
+    }
+
+    /// `v`, `V` or `Ctrl-V` typed in visual `mode`: switches to `target`, or
+    /// back to normal mode if already in it.
+    fn switch_visual(mode: Mode, target: Mode) -> Command {
+        if mode == target {
+            Command::ChangeMode(Mode::Normal)
+        } else {
+            Command::ChangeMode(target)
+        }
+    }
+
     fn handle_cmd_event(&mut self, event: Event) -> Command {
         match event {
             Event::Key(key) => match key.code {
@@ -127,22 +411,110 @@ impl EventHandler {
                     self.cmd_dispatcher.push(ch);
                     Command::DoNothing
                 }
-                KeyCode::Enter => self.cmd_dispatcher.get().unwrap_or(Command::DoNothing),
-                KeyCode::Esc => Command::ChangeMode(Mode::Normal),
+                KeyCode::Enter => {
+                    let line = self.cmd_dispatcher.get_query();
+                    let cmd = self.cmd_dispatcher.get_ex();
+                    if cmd.is_some() {
+                        self.ex_line = Some(line);
+                    }
+                    cmd.unwrap_or(Command::DoNothing)
+                }
+                KeyCode::Esc => {
+                    self.cmd_dispatcher.clear();
+                    Command::ChangeMode(Mode::Normal)
+                }
 
                 _ => Command::DoNothing,
             },
             _ => Command::DoNothing,
         }
     }
+
+    fn handle_search_event(&mut self, event: Event) -> Command {
+        let Event::Key(key) = event else {
+            return Command::DoNothing;
+        };
+
+        match key.code {
+            KeyCode::Char(ch) => self.search_line.push(ch),
+            // Backspace on an empty pattern gives up the search.
+            KeyCode::Backspace => {
+                if self.search_line.pop().is_none() {
+                    return Command::CancelSearch;
+                }
+            }
+            KeyCode::Up => self.recall_search(true),
+            KeyCode::Down => self.recall_search(false),
+            KeyCode::Enter => {
+                let pattern = std::mem::take(&mut self.search_line);
+                if !pattern.is_empty() {
+                    self.search_history.retain(|old| *old != pattern);
+                    self.search_history.push(pattern.clone());
+                    if self.search_history.len() > SEARCH_HISTORY_LEN {
+                        self.search_history.remove(0);
+                    }
+                }
+                return Command::Search {
+                    pattern,
+                    backward: self.search_backward,
+                };
+            }
+            KeyCode::Esc => return Command::CancelSearch,
+            _ => return Command::DoNothing,
+        }
+
+        Command::PreviewSearch {
+            pattern: self.search_line.clone(),
+            backward: self.search_backward,
+        }
+    }
+
+    /// Replaces the typed pattern with an `older` or newer one from the
+    /// history. Going past the newest clears it.
+    fn recall_search(&mut self, older: bool) {
+        let len = self.search_history.len();
+        self.history_index = match (self.history_index, older) {
+            (None, true) => len.checked_sub(1),
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < len => Some(i + 1),
+            (_, false) => None,
+        };
+        self.search_line = self
+            .history_index
+            .map(|i| self.search_history[i].clone())
+            .unwrap_or_default();
+    }
+
+    /// Looks up the ex command `line` as if it was typed after `:`.
+    pub fn ex_command(&mut self, line: &str) -> Option<Command> {
+        self.cmd_dispatcher.clear();
+        line.chars().for_each(|ch| self.cmd_dispatcher.push(ch));
+        self.cmd_dispatcher.get_ex()
+    }
+
+    /// Forgets the keys of a command that was typed only in part.
+    fn clear_keys(&mut self) {
+        self.normal_dispatcher.clear();
+        self.visual_dispatcher.clear();
+        self.cmd_dispatcher.clear();
+        self.register = None;
+        self.awaiting_register = false;
+        self.search_line.clear();
+    }
+
+    /// Takes the command line of the ex command `handle` last returned.
+    pub fn take_ex_line(&mut self) -> Option<String> {
+        self.ex_line.take()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Command {
     DoNothing,
-    MoveCursor { dx: i32, dy: i32 },
-    MoveCursorSOF,
-    MoveCursorEOF,
+    MoveCursor {
+        dx: i32,
+        dy: i32,
+    },
     InsertChar(char),
     InsertTab,
     RemoveChar,
@@ -150,18 +522,1142 @@ pub enum Command {
     InsertEmptyLineBelow,
     InsertEmptyLineAbove,
     MoveCursorToLineEnd,
-    MoveCursorToMouse { row: usize, col: usize },
+    MoveCursorToMouse {
+        row: usize,
+        col: usize,
+    },
     ScrollUp,
     ScrollDown,
     ChangeMode(Mode),
     TerminateApp,
+    Quit,
+    WriteQuit,
+    ForceWriteQuit,
+    WriteQuitIfModified,
+    WriteAllQuit,
     Save,
     SaveAndRestart,
     OpenFile(String),
     Undo,
+    Redo,
+    HistoryStep(isize),
+    Earlier(String),
+    Later(String),
+    ToggleUndoTree,
+    SetOption(String),
+    AnswerPrompt(char),
+    RecoverSwap,
+    DiscardSwap,
+    ShowSwapDiff,
+    IgnoreSwapOwner,
+    CheckDisk,
+    Overwrite,
+    ReloadFromDisk,
+    KeepMine,
+    MergeWithDisk,
+    ShowDiskDiff {
+        saving: bool,
+    },
+    SetMark(char),
+    JumpToMark {
+        name: char,
+        exact: bool,
+    },
+    ListBuffers,
+    SwitchBuffer(String),
+    CycleBuffer(isize),
+    AlternateBuffer,
+    DeleteBuffer(String),
+    DeleteChar {
+        register: char,
+    },
+    Put {
+        register: char,
+        before: bool,
+    },
+    ShowRegisters,
+    Search {
+        pattern: String,
+        backward: bool,
+    },
+    SearchNext {
+        reverse: bool,
+    },
+    SearchWord {
+        backward: bool,
+    },
+    ClearSearchHighlight,
+    PreviewSearch {
+        pattern: String,
+        backward: bool,
+    },
+    CancelSearch,
+    Substitute {
+        range: String,
+        args: String,
+    },
+    /// Runs the last `:s` again on a range with new flags, for `:&` and `&`.
+    RepeatSubstitute(String, String),
+    ConfirmSubstitute(char),
+    DeleteLines {
+        range: String,
+        register: char,
+    },
+    /// Runs the keys as typed in normal mode, for `:normal`.
+    Normal(String),
+    /// Runs an ex command on every line matching a pattern, or not matching
+    /// it if `invert`, for `:g` and `:v`.
+    Global {
+        range: String,
+        args: String,
+        invert: bool,
+    },
+    /// Moves the cursor to the other end of the selection, `o`.
+    SwapVisualAnchor,
+    /// A motion typed without an operator, `3j`.
+    Move {
+        motion: Motion,
+        count: Option<usize>,
+    },
+    /// An operator applied from the cursor to where `motion` goes, `d2j`.
+    Operate {
+        operator: Operator,
+        motion: Motion,
+        count: Option<usize>,
+        register: char,
+    },
+    /// An operator applied to the visual selection.
+    VisualOperate {
+        operator: Operator,
+        register: char,
+    },
+    /// Block `I` and `A`: insert before or after the block on every row.
+    VisualInsert {
+        append: bool,
+    },
+}
+
+/// Asks what to do about a swap file found when opening the buffer's file.
+pub fn swap_prompt(buffer: &Buffer) -> Option<Prompt> {
+    if let Some(pid) = buffer.recoverable_swap() {
+        Some(Prompt {
+            text: format!(
+                "Found unsaved edits of a crashed session (pid {pid}). (r)ecover, (d)iscard, show di(f)f?"
+            ),
+            choices: vec![
+                ('r', Command::RecoverSwap),
+                ('d', Command::DiscardSwap),
+                ('f', Command::ShowSwapDiff),
+            ],
+        })
+    } else {
+        buffer.swap_in_use_by().map(|pid| Prompt {
+            text: format!(
+                "Another hoditor (pid {pid}) is editing this file. (e)dit anyway, (q)uit?"
+            ),
+            choices: vec![('e', Command::IgnoreSwapOwner), ('q', Command::Quit)],
+        })
+    }
+}
+
+/// Asks what to do about the file having changed on disk. `saving` adds the
+/// choice to overwrite it, for when the change was noticed by `:w`.
+pub fn disk_prompt(file_name: &str, modified_here: bool, saving: bool) -> Prompt {
+    let mut text = format!("{file_name} changed on disk. (r)eload, (k)eep mine");
+    let mut choices = vec![('r', Command::ReloadFromDisk), ('k', Command::KeepMine)];
+    if modified_here {
+        text.push_str(", (m)erge");
+        choices.push(('m', Command::MergeWithDisk));
+    }
+    if saving {
+        text.push_str(", (o)verwrite");
+        choices.push(('o', Command::Overwrite));
+    }
+    text.push_str(", show di(f)f?");
+    choices.push(('f', Command::ShowDiskDiff { saving }));
+
+    Prompt { text, choices }
 }
 
 impl Command {
+    /// Fills the argument of an ex command, e.g. the file name of `:e`.
+    pub fn with_arg(self, arg: &str) -> Command {
+        match self {
+            Command::OpenFile(_) => Command::OpenFile(arg.to_string()),
+            Command::Earlier(_) => Command::Earlier(arg.to_string()),
+            Command::Later(_) => Command::Later(arg.to_string()),
+            Command::SetOption(_) => Command::SetOption(arg.to_string()),
+            Command::SwitchBuffer(_) => Command::SwitchBuffer(arg.to_string()),
+            Command::DeleteBuffer(_) => Command::DeleteBuffer(arg.to_string()),
+            Command::Substitute { range, .. } => Command::Substitute {
+                range,
+                args: arg.to_string(),
+            },
+            // `:&&` keeps the flags and adds any given after it.
+            Command::RepeatSubstitute(range, flags) => {
+                Command::RepeatSubstitute(range, flags + arg)
+            }
+            Command::DeleteLines { range, .. } => Command::DeleteLines {
+                range,
+                register: arg.chars().next().unwrap_or(UNNAMED),
+            },
+            Command::Normal(_) => Command::Normal(arg.to_string()),
+            Command::Global { range, invert, .. } => Command::Global {
+                range,
+                args: arg.to_string(),
+                invert,
+            },
+            cmd => cmd,
+        }
+    }
+
+    /// Fills the line range typed before an ex command, e.g. the `%` of `:%s`.
+    pub fn with_range(self, range: &str) -> Command {
+        match self {
+            Command::Substitute { args, .. } => Command::Substitute {
+                range: range.to_string(),
+                args,
+            },
+            Command::RepeatSubstitute(_, flags) => {
+                Command::RepeatSubstitute(range.to_string(), flags)
+            }
+            Command::DeleteLines { register, .. } => Command::DeleteLines {
+                range: range.to_string(),
+                register,
+            },
+            Command::Global { args, invert, .. } => Command::Global {
+                range: range.to_string(),
+                args,
+                invert,
+            },
+            cmd => cmd,
+        }
+    }
+
+    /// Fills the register named with `"x` before a normal-mode command.
+    pub fn with_register(self, name: char) -> Command {
+        match self {
+            Command::DeleteChar { .. } => Command::DeleteChar { register: name },
+            Command::Put { before, .. } => Command::Put {
+                register: name,
+                before,
+            },
+            Command::Operate {
+                operator,
+                motion,
+                count,
+                ..
+            } => Command::Operate {
+                operator,
+                motion,
+                count,
+                register: name,
+            },
+            Command::VisualOperate { operator, .. } => Command::VisualOperate {
+                operator,
+                register: name,
+            },
+            cmd => cmd,
+        }
+    }
+
+    fn jump_in_history(context: &mut Context, pos: Option<Position>, message: &str) {
+        match pos {
+            Some(pos) => {
+                context.cursor.move_to(pos.row, pos.col);
+                context.viewport.follow(pos.row);
+                context
+                    .app_state
+                    .set_message(format!("seq {}", context.buffer.history_seq()));
+            }
+            None => context.app_state.set_message(message.to_string()),
+        }
+        context.app_state.set_mode(Mode::Normal);
+        context.cursor.set_style(CursorStyle::Block);
+        context.app_state.set_should_render(true);
+    }
+
+    /// Shows buffer `id` and checks whether its file changed while hidden.
+    fn show_buffer(context: &mut Context, id: usize) {
+        if context.switch_buffer(id) {
+            Command::CheckDisk.execute(&mut Some(context.reborrow()));
+        } else if id != context.buffers.current_id() {
+            context
+                .app_state
+                .set_message(format!("Buffer {id} does not exist"));
+        }
+    }
+
+    /// Finds the buffer meant by the argument of `:b` or `:bd`: a number, a
+    /// file name, or the current buffer if empty.
+    fn buffer_id(context: &Context, arg: &str) -> Option<usize> {
+        let arg = arg.trim();
+        if arg.is_empty() || arg == context.file_name.as_str() {
+            Some(context.buffers.current_id())
+        } else if let Ok(id) = arg.parse() {
+            Some(id)
+        } else {
+            context.buffers.find(arg)
+        }
+    }
+
+    /// Saves the shown buffer. Read-only buffers are refused, and if the file
+    /// changed on disk this asks what to do instead. Returns whether the file
+    /// was written.
+    fn save(context: &mut Context) -> bool {
+        if context.buffer.is_read_only() {
+            context
+                .app_state
+                .set_message(String::from("'readonly' option is set (add ! to override)"));
+            return false;
+        }
+        if let DiskChange::Changed { modified_here } = context.buffer.check_disk(context.file_name)
+        {
+            let prompt = disk_prompt(context.file_name, modified_here, true);
+            context.app_state.set_prompt(prompt);
+            return false;
+        }
+
+        Self::overwrite(context)
+    }
+
+    /// Saves the shown buffer without checking the file on disk or the
+    /// read-only flag first, for `:w!`.
+    fn overwrite(context: &mut Context) -> bool {
+        let backup = context.app_state.options().backup;
+        match context.buffer.write_to(context.file_name, backup) {
+            Ok(()) => {
+                context.buffer.save_undo(context.file_name);
+                context.buffer.reset_swap();
+                true
+            }
+            Err(e) => {
+                context.app_state.set_message(format!("{e:#}"));
+                false
+            }
+        }
+    }
+
+    /// Saves every modified buffer. Stops at the first one that can't be
+    /// written or whose file changed on disk.
+    fn save_all(context: &mut Context) -> bool {
+        if context.buffer.is_modified() && !Self::save(context) {
+            return false;
+        }
+
+        let backup = context.app_state.options().backup;
+        for open in context.buffers.parked_mut() {
+            if !open.buffer.is_modified() {
+                continue;
+            }
+            if open.buffer.is_read_only() {
+                context
+                    .app_state
+                    .set_message(format!("{} is read-only", open.file_name));
+                return false;
+            }
+            if let DiskChange::Changed { .. } = open.buffer.check_disk(&open.file_name) {
+                context.app_state.set_message(format!(
+                    "{} changed on disk. Switch to it to save",
+                    open.file_name
+                ));
+                return false;
+            }
+            if let Err(e) = open.buffer.write_to(&open.file_name, backup) {
+                context.app_state.set_message(format!("{e:#}"));
+                return false;
+            }
+            open.buffer.save_undo(&open.file_name);
+            open.buffer.reset_swap();
+        }
+
+        true
+    }
+
+    /// Exits unless a buffer has unsaved changes.
+    fn quit(context: &mut Context) {
+        if context.buffer.is_modified() {
+            context.app_state.set_message(String::from(
+                "No write since last change (add ! to override)",
+            ));
+        } else if let Some((id, open)) = context.buffers.iter().find_map(|(id, parked)| {
+            parked
+                .filter(|open| open.buffer.is_modified())
+                .map(|open| (id, open))
+        }) {
+            context.app_state.set_message(format!(
+                "No write since last change for buffer {id} \"{}\" (add ! to override)",
+                open.file_name
+            ));
+        } else {
+            context.app_state.terminate_app();
+            return;
+        }
+
+        context.app_state.set_mode(Mode::Normal);
+        context.app_state.set_should_render(true);
+    }
+
+    /// Keeps the cursor inside the buffer after its text was replaced.
+    fn clamp_cursor(context: &mut Context) {
+        let row = context.cursor.row().min(context.buffer.len() - 1);
+        let col = context.cursor.col().min(context.buffer.len_of(row));
+        context.cursor.move_to(row, col);
+        context.viewport.follow(row);
+    }
+
+    /// Stores `register` in register `name` as yanked, or as deleted if
+    /// `deleted`. `"+` and `"*`, and `""` with `clipboard=unnamedplus`, also
+    /// go to the system clipboard. Returns false if `name` can't be written.
+    fn store(context: &mut Context, name: char, register: Register, deleted: bool) -> bool {
+        let unnamedplus = context.app_state.options().unnamedplus;
+        let registers = context.app_state.registers_mut();
+        let stored = if deleted {
+            registers.delete(name, register.clone())
+        } else {
+            registers.yank(name, register.clone())
+        };
+        if let Err(e) = stored {
+            context.app_state.set_message(format!("{e:#}"));
+            return false;
+        }
+
+        let selection = match name {
+            '+' | '*' => name,
+            UNNAMED if unnamedplus => {
+                let _ = registers.yank('+', register.clone());
+                '+'
+            }
+            _ => return true,
+        };
+        let text = register.to_text();
+        context
+            .app_state
+            .send_to_terminal(clipboard::osc52(selection, &text));
+        if let Err(e) = context.app_state.clipboard().copy(&text) {
+            context.app_state.set_message(format!("{e:#}"));
+        }
+
+        true
+    }
+
+    /// Contents of register `name`, reading `"+` and `"*` (and `""` with
+    /// `clipboard=unnamedplus`) from the system clipboard when a helper can.
+    fn fetch(context: &mut Context, name: char) -> Option<Register> {
+        let name = if name == UNNAMED && context.app_state.options().unnamedplus {
+            '+'
+        } else {
+            name
+        };
+        let local = context.app_state.registers().get(name, context.file_name);
+        if !matches!(name, '+' | '*') {
+            return local;
+        }
+
+        match context.app_state.clipboard().paste() {
+            // Text copied from here keeps its kind, e.g. blockwise.
+            Ok(Some(text)) if local.as_ref().is_some_and(|r| r.to_text() == text) => local,
+            Ok(Some(text)) if !text.is_empty() => Some(Register::from_text(&text)),
+            Ok(_) => local,
+            Err(e) => {
+                context.app_state.set_message(format!("{e:#}"));
+                local
+            }
+        }
+    }
+
+    /// Searches for `pattern`, or the last pattern if it is empty. Case is
+    /// ignored as `ignorecase` says, and `smart` applies `smartcase`.
+    fn start_search(context: &mut Context, pattern: &str, backward: bool, smart: bool) {
+        let pattern = if pattern.is_empty() {
+            context.app_state.search().pattern().to_string()
+        } else {
+            pattern.to_string()
+        };
+        if pattern.is_empty() {
+            context
+                .app_state
+                .set_message(String::from("No previous regular expression"));
+            return;
+        }
+
+        let options = context.app_state.options();
+        let ignore_case =
+            search::ignores_case(&pattern, options.ignorecase, smart && options.smartcase);
+        if let Err(e) = context
+            .app_state
+            .search_mut()
+            .set(&pattern, backward, ignore_case)
+        {
+            context.app_state.set_message(format!("{e:#}"));
+            return;
+        }
+        context.app_state.registers_mut().set_last_search(pattern);
+        Self::jump_to_match(context, backward);
+    }
+
+    /// Moves to the next match of the last search, or the previous one if
+    /// `backward`, centering it if it is out of view.
+    fn jump_to_match(context: &mut Context, backward: bool) {
+        let search = context.app_state.search();
+        if search.regex().is_none() {
+            context
+                .app_state
+                .set_message(String::from("No previous regular expression"));
+            return;
+        }
+
+        let Some((pos, wrapped)) = search.find(context.buffer, context.cursor.pos(), backward)
+        else {
+            let message = format!("Pattern not found: {}", search.pattern());
+            context.app_state.set_message(message);
+            return;
+        };
+
+        context.marks.set(PREVIOUS_JUMP, context.cursor.pos());
+        context.cursor.move_to(pos.row, pos.col);
+        context.viewport.center(pos.row);
+        if wrapped {
+            let message = if backward {
+                "search hit TOP, continuing at BOTTOM"
+            } else {
+                "search hit BOTTOM, continuing at TOP"
+            };
+            context.app_state.set_message(String::from(message));
+        }
+    }
+
+    /// Leaves visual mode and returns what was selected.
+    fn end_visual(context: &mut Context) -> Option<Selection> {
+        let anchor = context.app_state.visual_anchor();
+        let selection = Selection::new(context.app_state.mode(), anchor, context.cursor.pos());
+        Command::ChangeMode(Mode::Normal).execute(&mut Some(context.reborrow()));
+        selection
+    }
+
+    /// Moves the cursor as a motion typed without an operator.
+    fn move_cursor(context: &mut Context, motion: Motion, count: Option<usize>) {
+        let step = match motion {
+            Motion::Left => Command::MoveCursor { dx: -1, dy: 0 },
+            Motion::Right => Command::MoveCursor { dx: 1, dy: 0 },
+            Motion::Up => Command::MoveCursor { dx: 0, dy: -1 },
+            Motion::Down => Command::MoveCursor { dx: 0, dy: 1 },
+            Motion::Mark { name, exact } => {
+                Command::JumpToMark { name, exact }.execute(&mut Some(context.reborrow()));
+                return;
+            }
+            // In visual mode, an object becomes the selection.
+            Motion::Object { object, around } => {
+                let Some(selection) = Self::object_selection(context, object, around, count) else {
+                    return;
+                };
+                let mode = match selection.kind {
+                    SelectionKind::Line => Mode::VisualLine,
+                    _ => Mode::Visual,
+                };
+                if context.app_state.mode() != mode {
+                    Command::ChangeMode(mode).execute(&mut Some(context.reborrow()));
+                }
+                context.app_state.set_visual_anchor(selection.start);
+                context.cursor.move_to(selection.end.row, selection.end.col);
+                context.viewport.follow(selection.end.row);
+                context.app_state.set_should_render(true);
+                return;
+            }
+            _ => {
+                context.app_state.set_should_render(true);
+                let pos = context.cursor.pos();
+                let Some(target) = Self::motion_target(context, motion, count) else {
+                    return;
+                };
+                if motion.is_jump() {
+                    context.marks.set(PREVIOUS_JUMP, pos);
+                }
+                context.cursor.move_to(target.row, target.col);
+                // `$` keeps to the ends of lines when moving up and down.
+                if motion == Motion::LineEnd {
+                    context.cursor.keep_at_line_end();
+                }
+                context.viewport.follow(target.row);
+                return;
+            }
+        };
+
+        // Steps past the end of the line or the text would do nothing.
+        let limit = match motion {
+            Motion::Left | Motion::Right => context.buffer.len_of(context.cursor.row()),
+            _ => context.buffer.len(),
+        };
+        for _ in 0..count.unwrap_or(1).min(limit.max(1)) {
+            step.clone().execute(&mut Some(context.reborrow()));
+        }
+    }
+
+    /// Where `motion` goes from the cursor, with the words of the file's
+    /// language and the rows on screen.
+    fn motion_target(context: &Context, motion: Motion, count: Option<usize>) -> Option<Position> {
+        let screen = Screen {
+            top: context.viewport.offset,
+            height: context.viewport.height,
+        };
+        let words = WordChars::for_file(context.file_name);
+        motion.target(context.buffer, words, screen, context.cursor.pos(), count)
+    }
+
+    /// What the text object `object` takes at the cursor, in the words and
+    /// syntax of the file's language.
+    fn object_selection(
+        context: &Context,
+        object: TextObject,
+        around: bool,
+        count: Option<usize>,
+    ) -> Option<Selection> {
+        let words = WordChars::for_file(context.file_name);
+        let syntax = Syntax::for_file(context.file_name);
+        object.select(
+            context.buffer,
+            words,
+            syntax,
+            context.cursor.pos(),
+            around,
+            count,
+        )
+    }
+
+    /// The text `motion` moves over from the cursor, as `operator` takes it,
+    /// or `None` if it can't move.
+    fn motion_selection(
+        context: &mut Context,
+        operator: Operator,
+        motion: Motion,
+        count: Option<usize>,
+    ) -> Option<Selection> {
+        if let Motion::Object { object, around } = motion {
+            return Self::object_selection(context, object, around, count);
+        }
+        let pos = context.cursor.pos();
+        let line = context.buffer.get(pos.row);
+        let on_blank = line[pos.col.min(line.len())..]
+            .chars()
+            .next()
+            .is_none_or(char::is_whitespace);
+        let (mut target, mut kind) = match motion {
+            Motion::Mark { name, .. } => {
+                context.marks.sync(context.buffer);
+                let name = if name == '`' { PREVIOUS_JUMP } else { name };
+                let Some(mark) = context.marks.get(name) else {
+                    context.app_state.set_message(String::from("Mark not set"));
+                    return None;
+                };
+                let row = mark.row.min(context.buffer.len() - 1);
+                let col = mark.col.min(context.buffer.len_of(row));
+                (Position { row, col }, motion.kind())
+            }
+            // `cw` changes up to the end of the word, like `ce`.
+            Motion::WordForward { big } if operator == Operator::Change && !on_blank => {
+                let words = WordChars::for_file(context.file_name);
+                let end = motion::change_word_end(context.buffer, words, pos, count, big);
+                (end, MotionKind::Inclusive)
+            }
+            // `}` stopping on the last character of the text takes it too.
+            Motion::ParagraphForward => {
+                let target = Self::motion_target(context, motion, count)?;
+                let kind = match context.buffer.len_of(target.row) {
+                    0 => motion.kind(),
+                    _ => MotionKind::Inclusive,
+                };
+                (target, kind)
+            }
+            _ => (Self::motion_target(context, motion, count)?, motion.kind()),
+        };
+
+        // `w` after an operator stops at the end of the line of the last word
+        // it moves over.
+        if matches!(motion, Motion::WordForward { .. })
+            && kind == MotionKind::Exclusive
+            && target.row > pos.row
+            && context.buffer.get(target.row)[..target.col]
+                .trim()
+                .is_empty()
+        {
+            let row = target.row - 1;
+            target = Position {
+                row,
+                col: context.buffer.len_of(row),
+            };
+        }
+
+        let (start, mut end) = if (pos.row, pos.col) <= (target.row, target.col) {
+            (pos, target)
+        } else {
+            (target, pos)
+        };
+        if kind == MotionKind::Exclusive {
+            if start == end {
+                return None;
+            }
+            // Exclusive motions stop before the end. Ending at the start of a
+            // line, they stop at the end of the line before instead, and take
+            // whole lines if they started before any text on theirs.
+            if end.col == 0 {
+                let first_line = context.buffer.get(start.row);
+                let indent = first_line.len() - first_line.trim_start().len();
+                end.row -= 1;
+                if start.col <= indent && end.row >= start.row {
+                    kind = MotionKind::Linewise;
+                } else {
+                    let line = context.buffer.get(end.row);
+                    end.col = line.char_indices().last().map_or(0, |(col, _)| col);
+                }
+            } else {
+                let line = context.buffer.get(end.row);
+                end.col = line[..end.col.min(line.len())]
+                    .char_indices()
+                    .last()
+                    .map_or(0, |(col, _)| col);
+            }
+        }
+        // Nothing to take from an empty line.
+        if kind == MotionKind::Inclusive && start == end && context.buffer.len_of(end.row) == 0 {
+            return None;
+        }
+
+        let kind = match kind {
+            MotionKind::Linewise => SelectionKind::Line,
+            MotionKind::Exclusive | MotionKind::Inclusive => SelectionKind::Char,
+        };
+        Some(Selection { kind, start, end })
+    }
+
+    /// Applies `operator` to `selection`, storing text it yanks or deletes in
+    /// `register`.
+    fn operate(context: &mut Context, operator: Operator, selection: Selection, register: char) {
+        context.app_state.set_should_render(true);
+        let (first, last) = (selection.start.row, selection.end.row);
+        match operator {
+            Operator::Yank => {
+                let yanked = context.buffer.yank_selection(&selection);
+                Self::store(context, register, yanked, false);
+                let col = match selection.kind {
+                    SelectionKind::Line => context.cursor.col(),
+                    _ => selection.start.col,
+                };
+                context
+                    .cursor
+                    .move_to(first, col.min(context.buffer.len_of(first)));
+                context.viewport.follow(first);
+            }
+            Operator::Delete => {
+                if Self::delete_selection(context, &selection, register) {
+                    context.buffer.end_change(context.cursor.pos());
+                    if selection.kind == SelectionKind::Line && last - first + 1 > 2 {
+                        let message = format!("{} fewer lines", last - first + 1);
+                        context.app_state.set_message(message);
+                    }
+                }
+            }
+            Operator::Change => {
+                if !Self::delete_selection(context, &selection, register) {
+                    return;
+                }
+
+                match selection.kind {
+                    // Deleting every line already leaves the one to type on.
+                    SelectionKind::Line
+                        if context.buffer.len() > 1 || !context.buffer.get(0).is_empty() =>
+                    {
+                        context.buffer.insert(first, "");
+                        context.cursor.move_to(first, 0);
+                    }
+                    SelectionKind::Block => context.app_state.set_block_insert(BlockInsert {
+                        start: context.cursor.pos(),
+                        last_row: last,
+                        pad: false,
+                    }),
+                    _ => {}
+                }
+                // Typing goes into the same undo step as the deletion.
+                Command::ChangeMode(Mode::Edit).execute(&mut Some(context.reborrow()));
+            }
+            Operator::ShiftRight | Operator::ShiftLeft | Operator::Reindent => {
+                context.buffer.begin_change(context.cursor.pos());
+                match operator {
+                    Operator::Reindent => context.buffer.reindent_lines(first, last),
+                    _ => context
+                        .buffer
+                        .shift_lines(first, last, operator == Operator::ShiftLeft),
+                }
+                let line = context.buffer.get(first);
+                context
+                    .cursor
+                    .move_to(first, line.len() - line.trim_start().len());
+                context.buffer.end_change(context.cursor.pos());
+                context.viewport.follow(first);
+            }
+            Operator::Lower | Operator::Upper | Operator::ToggleCase => {
+                context.buffer.begin_change(context.cursor.pos());
+                context.buffer.change_case(&selection, operator);
+                let col = match selection.kind {
+                    SelectionKind::Line => context.cursor.col(),
+                    _ => selection.start.col,
+                };
+                context
+                    .cursor
+                    .move_to(first, col.min(context.buffer.len_of(first)));
+                context.buffer.end_change(context.cursor.pos());
+                context.viewport.follow(first);
+            }
+        }
+    }
+
+    /// Stores `selection` in `register` as deleted and removes it, leaving
+    /// the undo step open. Returns false if the register can't be written.
+    fn delete_selection(context: &mut Context, selection: &Selection, register: char) -> bool {
+        let deleted = context.buffer.yank_selection(selection);
+        if !Self::store(context, register, deleted, true) {
+            return false;
+        }
+
+        context.buffer.begin_change(context.cursor.pos());
+        let pos = context.buffer.delete_selection(selection);
+        context.cursor.move_to(pos.row, pos.col);
+        context.viewport.follow(pos.row);
+        true
+    }
+
+    /// Repeats what was typed on the first row of a block `I` or `A` on the
+    /// rows below, unless the cursor left that row.
+    fn finish_block_insert(context: &mut Context, insert: BlockInsert) {
+        let (start, cursor) = (insert.start, context.cursor.pos());
+        if cursor.row != start.row || cursor.col <= start.col {
+            return;
+        }
+
+        let text = context.buffer.get(start.row)[start.col..cursor.col].to_string();
+        context.buffer.insert_block(
+            start.row + 1..=insert.last_row,
+            start.col,
+            &text,
+            insert.pad,
+        );
+        context.cursor.move_to(start.row, start.col);
+    }
+
+    /// Rows of the ex line `range`, or `default` if it is empty. Shows the
+    /// error and returns `None` if it is invalid.
+    fn resolve_range(
+        context: &mut Context,
+        range: &str,
+        default: (usize, usize),
+    ) -> Option<(usize, usize)> {
+        context.marks.sync(context.buffer);
+        let (row, last) = (context.cursor.row(), context.buffer.len() - 1);
+        match ex::resolve(range, row, last, |name| {
+            context.marks.get(name).map(|pos| pos.row)
+        }) {
+            Ok(rows) => Some(rows.unwrap_or(default)),
+            Err(e) => {
+                context.app_state.set_message(format!("{e:#}"));
+                None
+            }
+        }
+    }
+
+    /// Runs `keys` as typed in normal mode with `handler`.
+    fn normal(context: &mut Context, handler: &mut EventHandler, keys: &str) {
+        context.app_state.set_mode(Mode::Normal);
+        context.app_state.set_should_render(true);
+        handler.clear_keys();
+        for ch in keys.chars() {
+            let key = Event::Key(KeyEvent::new(KeyCode::Char(ch), KeyModifiers::NONE));
+            let cmd = handler.handle(key, context.app_state.mode());
+            cmd.execute(&mut Some(context.reborrow()));
+            if context.app_state.should_terminate() {
+                return;
+            }
+        }
+
+        // Unfinished commands end as with Esc, e.g. leaving insert mode.
+        match context.app_state.mode() {
+            Mode::Normal | Mode::Prompt => {}
+            Mode::Search => Command::CancelSearch.execute(&mut Some(context.reborrow())),
+            Mode::Cmd | Mode::Edit | Mode::Visual | Mode::VisualLine | Mode::VisualBlock => {
+                Command::ChangeMode(Mode::Normal).execute(&mut Some(context.reborrow()))
+            }
+        }
+    }
+
+    /// Runs the ex command in `args`, as in `/pattern/command`, on every line
+    /// of `range` that matches the pattern, or doesn't if `invert`. Lines are
+    /// marked first and visited as they move, skipping those deleted on the
+    /// way. Everything is one undo step.
+    fn global(context: &mut Context, range: &str, args: &str, invert: bool) {
+        context.app_state.set_mode(Mode::Normal);
+        context.app_state.set_should_render(true);
+        if context.marks.is_marking_lines() {
+            context
+                .app_state
+                .set_message(String::from("Cannot do :global recursive"));
+            return;
+        }
+
+        let (pattern, command) = match ex::split_pattern(args) {
+            Ok(split) => split,
+            Err(e) => {
+                context.app_state.set_message(format!("{e:#}"));
+                return;
+            }
+        };
+        let pattern = if pattern.is_empty() {
+            context.app_state.search().pattern().to_string()
+        } else {
+            pattern
+        };
+        if pattern.is_empty() {
+            context
+                .app_state
+                .set_message(String::from("No previous regular expression"));
+            return;
+        }
+        let last = context.buffer.len() - 1;
+        let Some((first, last)) = Self::resolve_range(context, range, (0, last)) else {
+            return;
+        };
+
+        let options = context.app_state.options();
+        let ignore_case = search::ignores_case(&pattern, options.ignorecase, options.smartcase);
+        let backward = context.app_state.search().is_backward();
+        if let Err(e) = context
+            .app_state
+            .search_mut()
+            .set(&pattern, backward, ignore_case)
+        {
+            context.app_state.set_message(format!("{e:#}"));
+            return;
+        }
+        context
+            .app_state
+            .registers_mut()
+            .set_last_search(pattern.clone());
+
+        let Some(regex) = context.app_state.search().regex().cloned() else {
+            return;
+        };
+        let rows: Vec<usize> = (first..=last)
+            .filter(|&row| regex.is_match(&context.buffer.get(row)) != invert)
+            .collect();
+        if rows.is_empty() {
+            let message = if invert {
+                format!("Pattern found in every line: {pattern}")
+            } else {
+                format!("Pattern not found: {pattern}")
+            };
+            context.app_state.set_message(message);
+            return;
+        }
+
+        // Without a command the lines are listed.
+        if command.trim().is_empty() {
+            let lines = rows
+                .iter()
+                .map(|&row| format!("{:>4} {}", row + 1, context.buffer.get(row)))
+                .collect();
+            context.app_state.set_panel(Panel::Text(lines));
+            return;
+        }
+
+        // The command is read once, and `:normal` keys go through one handler.
+        let mut handler = EventHandler::new();
+        let Some(cmd) = handler.ex_command(command) else {
+            context
+                .app_state
+                .set_message(format!("Not an editor command: {}", command.trim()));
+            return;
+        };
+
+        let len = context.buffer.len();
+        let mut asked = false;
+        context.marks.mark_lines(rows);
+        context.buffer.begin_group(context.cursor.pos());
+        loop {
+            context.marks.sync(context.buffer);
+            let Some(row) = context.marks.next_marked_line() else {
+                break;
+            };
+            context.cursor.move_to(row, 0);
+            match &cmd {
+                Command::Normal(keys) => Self::normal(context, &mut handler, keys),
+                cmd => cmd.execute(&mut Some(context.reborrow())),
+            }
+            // Questions, e.g. from `s///c`, can't be answered for each line.
+            if matches!(context.app_state.mode(), Mode::Prompt) {
+                context.app_state.take_prompt();
+                context.app_state.take_substitution();
+                context.app_state.set_mode(Mode::Normal);
+                asked = true;
+                break;
+            }
+        }
+        context.marks.clear_marked_lines();
+        context.buffer.end_group(context.cursor.pos());
+        context.viewport.follow(context.cursor.row());
+
+        let new_len = context.buffer.len();
+        if asked {
+            context
+                .app_state
+                .set_message(String::from("Cannot ask for confirmation in :global"));
+        } else if new_len < len {
+            context
+                .app_state
+                .set_message(format!("{} fewer lines", len - new_len));
+        } else if new_len > len {
+            context
+                .app_state
+                .set_message(format!("{} more lines", new_len - len));
+        }
+    }
+
+    /// Runs `substitute` on the lines of `range`, the cursor line if empty.
+    /// All its replacements are one undo step; with the `c` flag this asks
+    /// about each match first.
+    fn substitute(context: &mut Context, range: &str, substitute: Substitute) {
+        context.app_state.set_mode(Mode::Normal);
+        context.app_state.set_should_render(true);
+        if context.buffer.is_binary() {
+            context.app_state.set_message(String::from(
+                "Bytes of a binary file can only be overwritten",
+            ));
+            return;
+        }
+
+        let row = context.cursor.row();
+        let Some((first, last)) = Self::resolve_range(context, range, (row, row)) else {
+            return;
+        };
+
+        // The pattern is also the one `n` searches for next.
+        let backward = context.app_state.search().is_backward();
+        let ignore_case = substitute
+            .flags
+            .ignore_case
+            .unwrap_or(context.app_state.options().ignorecase);
+        let pattern = substitute.pattern.clone();
+        let _ = context
+            .app_state
+            .search_mut()
+            .set(&pattern, backward, ignore_case);
+        context.app_state.registers_mut().set_last_search(pattern);
+        context.app_state.set_last_substitute(substitute.clone());
+
+        context.buffer.begin_change(context.cursor.pos());
+        let session = Session::new(substitute, first, last);
+        if session.substitute().flags.confirm {
+            Self::confirm_next(context, session);
+        } else {
+            Self::finish_substitute(context, session);
+        }
+    }
+
+    /// Asks about the next match of a `:s///c`, or finishes it if none is left.
+    fn confirm_next(context: &mut Context, mut session: Session) {
+        if !session.advance(context.buffer) {
+            Self::finish_substitute(context, session);
+            return;
+        }
+
+        if let Some((row, cols)) = session.current() {
+            context.cursor.move_to(row, cols.start);
+            context.viewport.center(row);
+        }
+        let prompt = Prompt {
+            text: format!(
+                "replace with {} (y/n/a/q/l)?",
+                session.substitute().replacement
+            ),
+            choices: ['y', 'n', 'a', 'q', 'l']
+                .into_iter()
+                .map(|ch| (ch, Command::ConfirmSubstitute(ch)))
+                .chain([(ESC, Command::ConfirmSubstitute('q'))])
+                .collect(),
+        };
+        context.app_state.set_substitution(session);
+        context.app_state.set_prompt(prompt);
+    }
+
+    /// Replaces the matches left, closes the undo step and reports the count.
+    fn finish_substitute(context: &mut Context, mut session: Session) {
+        context.app_state.set_mode(Mode::Normal);
+        context.app_state.set_should_render(true);
+        if !session.substitute().flags.confirm {
+            session.replace_all(context.buffer);
+        }
+
+        if let Some(row) = session.last_changed_row() {
+            let line = context.buffer.get(row);
+            context
+                .cursor
+                .move_to(row, line.len() - line.trim_start().len());
+            context.viewport.follow(row);
+        }
+        context.buffer.end_change(context.cursor.pos());
+
+        let substitute = session.substitute();
+        if session.substitutions() > 0 {
+            context.app_state.set_message(session.summary());
+        } else if !substitute.flags.no_error {
+            let message = format!("Pattern not found: {}", substitute.pattern);
+            context.app_state.set_message(message);
+        }
+    }
+
+    /// Handles `:set {option}={value}`.
+    fn set_option(context: &mut Context, arg: &str) {
+        let (option, value) = arg.split_once('=').unwrap_or((arg, ""));
+        match option.trim() {
+            "fileformat" | "ff" => match LineEnding::parse(value.trim()) {
+                Some(line_ending) => context.buffer.set_line_ending(line_ending),
+                None => context
+                    .app_state
+                    .set_message(format!("Invalid fileformat: {value}")),
+            },
+            "fileencoding" | "fenc" => match FileFormat::parse_encoding(value) {
+                Some(encoding) => context.buffer.set_encoding(encoding),
+                None => context
+                    .app_state
+                    .set_message(format!("Invalid fileencoding: {value}")),
+            },
+            "readonly" | "ro" => context.buffer.set_read_only(true),
+            "noreadonly" | "noro" => context.buffer.set_read_only(false),
+            "backup" | "bk" => context.app_state.options_mut().backup = true,
+            "nobackup" | "nobk" => context.app_state.options_mut().backup = false,
+            "ignorecase" | "ic" => context.app_state.options_mut().ignorecase = true,
+            "noignorecase" | "noic" => context.app_state.options_mut().ignorecase = false,
+            "smartcase" | "scs" => context.app_state.options_mut().smartcase = true,
+            "nosmartcase" | "noscs" => context.app_state.options_mut().smartcase = false,
+            "clipboard" | "cb" => match value.trim() {
+                "unnamedplus" => context.app_state.options_mut().unnamedplus = true,
+                "" => context.app_state.options_mut().unnamedplus = false,
+                _ => context
+                    .app_state
+                    .set_message(format!("Invalid clipboard: {value}")),
+            },
+            "clipcopy" => {
+                let value = value.trim();
+                context.app_state.clipboard_mut().copy_cmd =
+                    (!value.is_empty()).then(|| value.to_string());
+            }
+            "clippaste" => {
+                let value = value.trim();
+                context.app_state.clipboard_mut().paste_cmd =
+                    (!value.is_empty()).then(|| value.to_string());
+            }
+            _ => context
+                .app_state
+                .set_message(format!("Unknown option: {option}")),
+        }
+    }
+
     pub fn execute(&self, context: &mut Option<Context>) {
         if let Some(context) = context {
             match self {
@@ -169,6 +1665,7 @@ impl Command {
                 Command::MoveCursor { dx, dy } => {
                     let cursor = &mut context.cursor;
                     let buffer = &mut context.buffer;
+                    let past_end = context.app_state.mode() == Mode::Edit;
 
                     // Handle horizontal movement
                     if *dx < 0 && cursor.col() > 0 {
@@ -179,20 +1676,16 @@ impl Command {
 
                     // Handle vertical movement
                     if *dy < 0 && cursor.row() > 0 {
-                        cursor.move_up(dy.abs() as usize);
-                        if cursor.col() > buffer.len_of(cursor.row()) {
-                            cursor.set_col(buffer.len_of(cursor.row()));
-                        }
+                        let row = cursor.row().saturating_sub(dy.unsigned_abs() as usize);
+                        cursor.move_vertically(row, &buffer.get(row), past_end);
                         if cursor.row() < context.viewport.offset + SCROLL_HEIGHT
                             && context.viewport.offset > 0
                         {
                             context.viewport.offset -= 1;
                         }
                     } else if *dy > 0 && cursor.row() < buffer.len() - 1 {
-                        cursor.move_down(*dy as usize);
-                        if cursor.col() > buffer.len_of(cursor.row()) {
-                            cursor.set_col(buffer.len_of(cursor.row()));
-                        }
+                        let row = (cursor.row() + *dy as usize).min(buffer.len() - 1);
+                        cursor.move_vertically(row, &buffer.get(row), past_end);
                         if cursor.row()
                             >= context.viewport.offset + context.viewport.height - SCROLL_HEIGHT
                             && context.viewport.offset + context.viewport.height < buffer.len()
@@ -202,42 +1695,81 @@ impl Command {
                     }
                     context.app_state.set_should_render(true);
                 }
-                Command::MoveCursorSOF => {
-                    context.viewport.offset = 0;
-                    context.cursor.set_row(0);
+                Command::InsertNewLine
+                | Command::InsertTab
+                | Command::InsertEmptyLineBelow
+                | Command::InsertEmptyLineAbove
+                | Command::DeleteChar { .. }
+                | Command::Put { .. }
+                | Command::VisualInsert { .. }
+                    if context.buffer.is_binary() =>
+                {
+                    context.app_state.set_message(String::from(
+                        "Bytes of a binary file can only be overwritten",
+                    ));
                     context.app_state.set_should_render(true);
                 }
-                Command::MoveCursorEOF => {
-                    context.viewport.offset = context.buffer.len() - context.viewport.height;
-                    context.cursor.set_row(context.buffer.len() - 1);
+                Command::Operate { operator, .. } | Command::VisualOperate { operator, .. }
+                    if operator.changes_text() && context.buffer.is_binary() =>
+                {
+                    context.app_state.set_message(String::from(
+                        "Bytes of a binary file can only be overwritten",
+                    ));
+                    context.app_state.set_should_render(true);
+                }
+                Command::InsertChar(ch) if context.buffer.is_binary() => {
+                    context.buffer.begin_change(context.cursor.pos());
+                    match context.buffer.overwrite_hex(
+                        context.cursor.row(),
+                        context.cursor.col(),
+                        *ch,
+                    ) {
+                        Some(col) => context.cursor.move_to_col(col),
+                        None => context
+                            .app_state
+                            .set_message(format!("Can't put {ch:?} here")),
+                    }
                     context.app_state.set_should_render(true);
                 }
                 Command::InsertChar(ch) => {
+                    context.buffer.begin_change(context.cursor.pos());
                     context
                         .buffer
                         .insert_char(context.cursor.row(), context.cursor.col(), *ch);
                     context.cursor.move_right(1);
+                    context.app_state.registers_mut().typed(&ch.to_string());
                     context.app_state.set_should_render(true);
                 }
                 Command::InsertTab => {
+                    context.buffer.begin_change(context.cursor.pos());
                     context.buffer.insert_string(
                         context.cursor.row(),
                         context.cursor.col(),
                         &String::from("    "),
                     );
                     context.cursor.move_right(4);
+                    context.app_state.registers_mut().typed("    ");
+                    context.app_state.set_should_render(true);
+                }
+                // Bytes are overwritten in place, so backspace only moves back.
+                Command::RemoveChar if context.buffer.is_binary() => {
+                    if context.cursor.col() > 0 {
+                        context.cursor.move_left(1);
+                    }
                     context.app_state.set_should_render(true);
                 }
                 Command::RemoveChar => {
                     let cursor = &mut context.cursor;
                     let buffer = &mut context.buffer;
+                    buffer.begin_change(cursor.pos());
 
+                    context.app_state.registers_mut().untyped();
                     if cursor.col() > 0 {
                         buffer.remove_char(cursor.row(), cursor.col() - 1);
                         cursor.move_left(1);
                     } else if cursor.row() > 0 {
                         let next_col = buffer.len_of(cursor.row() - 1);
-                        let cur_line = buffer.get(cursor.row()).clone();
+                        let cur_line = buffer.get(cursor.row());
                         buffer.insert_string(cursor.row() - 1, next_col, &cur_line);
                         buffer.remove(cursor.row());
                         cursor.move_up(1);
@@ -248,6 +1780,7 @@ impl Command {
                 Command::InsertNewLine => {
                     let cursor = &mut context.cursor;
                     let buffer = &mut context.buffer;
+                    buffer.begin_change(cursor.pos());
 
                     let rear = buffer.get_string(
                         cursor.row(),
@@ -258,12 +1791,12 @@ impl Command {
                     buffer.insert(cursor.row() + 1, &rear);
                     cursor.move_down(1);
                     cursor.move_to_col(0);
+                    context.app_state.registers_mut().typed("\n");
                     context.app_state.set_should_render(true);
                 }
                 Command::InsertEmptyLineBelow => {
-                    context
-                        .buffer
-                        .insert(context.cursor.row() + 1, &String::new());
+                    context.buffer.begin_change(context.cursor.pos());
+                    context.buffer.insert(context.cursor.row() + 1, "");
                     context.cursor.move_down(1);
                     context.cursor.move_to_col(0);
                     context.app_state.set_mode(Mode::Edit);
@@ -271,7 +1804,8 @@ impl Command {
                     context.app_state.set_should_render(true);
                 }
                 Command::InsertEmptyLineAbove => {
-                    context.buffer.insert(context.cursor.row(), &String::new());
+                    context.buffer.begin_change(context.cursor.pos());
+                    context.buffer.insert(context.cursor.row(), "");
                     context.cursor.move_to_col(0);
                     context.app_state.set_mode(Mode::Edit);
                     context.cursor.set_style(CursorStyle::Bar);
@@ -281,12 +1815,14 @@ impl Command {
                     context
                         .cursor
                         .move_to_col(context.buffer.len_of(context.cursor.row()));
+                    context.buffer.begin_change(context.cursor.pos());
                     context.app_state.set_mode(Mode::Edit);
                     context.cursor.set_style(CursorStyle::Bar);
                     context.app_state.set_should_render(true);
                 }
                 Command::MoveCursorToMouse { row, col } => {
-                    let line_num_len = (context.buffer.len() - 1).ilog10() as usize + 1;
+                    let line_num_len =
+                        (context.buffer.len() - 1).checked_ilog10().unwrap_or(0) as usize + 1;
 
                     let actual_col = if *col <= line_num_len {
                         0
@@ -328,46 +1864,110 @@ impl Command {
                     context.app_state.set_should_render(true);
                 }
                 Command::ChangeMode(mode) => {
+                    let from = context.app_state.mode();
+                    if matches!(from, Mode::Edit) && !matches!(mode, Mode::Edit) {
+                        if let Some(insert) = context.app_state.take_block_insert() {
+                            Self::finish_block_insert(context, insert);
+                        }
+                        context.marks.set(LAST_INSERT, context.cursor.pos());
+                        context.app_state.registers_mut().finish_insert();
+                    }
+                    if mode.is_visual() && !from.is_visual() {
+                        context.app_state.set_visual_anchor(context.cursor.pos());
+                    }
+                    // The last selection stays in `'<` and `'>`.
+                    let anchor = context.app_state.visual_anchor();
+                    if let Some(selection) = Selection::new(from, anchor, context.cursor.pos())
+                        && !mode.is_visual()
+                    {
+                        context.marks.set('<', selection.start);
+                        context.marks.set('>', selection.end);
+                    }
+                    if matches!(mode, Mode::Search) {
+                        context
+                            .app_state
+                            .search_mut()
+                            .begin_preview(context.cursor.pos(), context.viewport.offset);
+                    }
                     context.app_state.set_mode(*mode);
                     match mode {
-                        Mode::Cmd | Mode::Normal => context.cursor.set_style(CursorStyle::Block),
-                        Mode::Edit => context.cursor.set_style(CursorStyle::Bar),
+                        Mode::Cmd
+                        | Mode::Normal
+                        | Mode::Prompt
+                        | Mode::Search
+                        | Mode::Visual
+                        | Mode::VisualLine
+                        | Mode::VisualBlock => {
+                            // Leaving insert mode closes its undo step.
+                            context.buffer.end_change(context.cursor.pos());
+                            context.cursor.set_style(CursorStyle::Block);
+                        }
+                        Mode::Edit => {
+                            context.buffer.begin_change(context.cursor.pos());
+                            context.cursor.set_style(CursorStyle::Bar);
+                        }
                     }
                     context.app_state.set_should_render(true);
                 }
                 Command::TerminateApp => {
                     context.app_state.terminate_app();
                 }
+                Command::Quit => Self::quit(context),
+                Command::WriteQuit => {
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                    if Self::save(context) {
+                        Self::quit(context);
+                    }
+                }
+                Command::ForceWriteQuit => {
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                    if Self::overwrite(context) {
+                        Self::quit(context);
+                    }
+                }
+                Command::WriteQuitIfModified => {
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                    if !context.buffer.is_modified() || Self::save(context) {
+                        Self::quit(context);
+                    }
+                }
+                Command::WriteAllQuit => {
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                    if Self::save_all(context) {
+                        Self::quit(context);
+                    }
+                }
                 Command::Save => {
-                    let f_write = File::create(&context.file_name).unwrap();
-                    let mut buf_writer = BufWriter::new(f_write);
-                    for i in 0..context.buffer.len() {
-                        buf_writer
-                            .write_all(context.buffer.get(i).as_bytes())
-                            .unwrap();
-                        buf_writer.write_all(b"\n").unwrap();
-                    }
-                    buf_writer.flush().unwrap();
                     context.app_state.set_mode(Mode::Normal);
                     context.app_state.set_should_render(true);
+                    Self::save(context);
+                }
+                Command::Overwrite => {
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                    Self::overwrite(context);
                 }
                 Command::SaveAndRestart => {
-
-                    // Save the file first
-                    let f_write = File::create(&context.file_name).unwrap();
-                    let mut buf_writer = BufWriter::new(f_write);
-                    for i in 0..context.buffer.len() {
-                        buf_writer
-                            .write_all(context.buffer.get(i).as_bytes())
-                            .unwrap();
-                        buf_writer.write_all(b"\n").unwrap();
+                    // Save the file first, as `:w` would.
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                    if !Self::save(context) {
+                        return;
+                    }
+                    context.buffer.close_swap();
+                    for open in context.buffers.parked_mut() {
+                        open.buffer.close_swap();
                     }
-                    buf_writer.flush().unwrap();
 
                     let _ = crossterm::terminal::disable_raw_mode();
                     let _ = crossterm::execute!(
                         std::io::stdout(),
                         crossterm::event::DisableMouseCapture,
+                        crossterm::event::DisableFocusChange,
                         crossterm::terminal::LeaveAlternateScreen
                     );
 
@@ -393,15 +1993,633 @@ impl Command {
                 }
                 Command::OpenFile(file) => {
                     Logger::log(format!("{file}"));
-                    context.file_name.clear();
-                    context.file_name.push_str(file);
-                    context.buffer.replace(file);
-                    context.cursor.move_to(0, 0);
-                    context.viewport.offset = 0;
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                    if file.is_empty() || file == context.file_name.as_str() {
+                        return;
+                    }
+
+                    match context.buffers.find(file) {
+                        Some(id) => Self::show_buffer(context, id),
+                        None => match context.open_buffer(file) {
+                            Ok(()) => {
+                                if let Some(prompt) = swap_prompt(context.buffer) {
+                                    context.app_state.set_prompt(prompt);
+                                }
+                            }
+                            Err(e) => context.app_state.set_message(format!("{e:#}")),
+                        },
+                    }
+                }
+                Command::SetMark(name) => {
+                    // `` m` `` is the same mark as `m'`.
+                    let name = if *name == '`' { PREVIOUS_JUMP } else { *name };
+                    context.marks.sync(context.buffer);
+                    if Marks::is_global(name) {
+                        context.buffers.clear_global_mark(name);
+                    }
+                    context.marks.set(name, context.cursor.pos());
+                }
+                Command::JumpToMark { name, exact } => {
+                    let name = if *name == '`' { PREVIOUS_JUMP } else { *name };
+                    context.app_state.set_should_render(true);
+                    context.marks.sync(context.buffer);
+                    let (from, from_buffer) = (context.cursor.pos(), context.buffers.current_id());
+
+                    if context.marks.get(name).is_none() && Marks::is_global(name) {
+                        match context.buffers.find_global_mark(name) {
+                            Some(GlobalMark::Parked(id)) => Self::show_buffer(context, id),
+                            Some(GlobalMark::Closed(file_name, pos)) => {
+                                if let Err(e) = context.open_buffer(&file_name) {
+                                    context.app_state.set_message(format!("{e:#}"));
+                                    return;
+                                }
+                                context.buffers.clear_global_mark(name);
+                                context.marks.set(name, pos);
+                            }
+                            None => {}
+                        }
+                    }
+
+                    let Some(pos) = context.marks.get(name) else {
+                        context.app_state.set_message(String::from("Mark not set"));
+                        return;
+                    };
+                    if context.buffers.current_id() == from_buffer {
+                        context.marks.set(PREVIOUS_JUMP, from);
+                    }
+
+                    // Marks can outlive the text they were on, e.g. after undo.
+                    let row = pos.row.min(context.buffer.len() - 1);
+                    let line = context.buffer.get(row);
+                    let col = if *exact {
+                        pos.col.min(line.len())
+                    } else {
+                        line.len() - line.trim_start().len()
+                    };
+                    context.cursor.move_to(row, col);
+                    context.viewport.follow(row);
+                }
+                Command::ListBuffers => {
+                    context.app_state.toggle_buffer_list();
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                }
+                Command::SwitchBuffer(arg) => {
+                    context.app_state.set_mode(Mode::Normal);
                     context.app_state.set_should_render(true);
+                    match Self::buffer_id(context, arg) {
+                        Some(id) => Self::show_buffer(context, id),
+                        None => context
+                            .app_state
+                            .set_message(format!("No matching buffer for {arg}")),
+                    }
+                }
+                Command::CycleBuffer(delta) => {
+                    let id = context.buffers.id_after(*delta);
+                    Self::show_buffer(context, id);
                     context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                }
+                Command::AlternateBuffer => {
+                    match context.buffers.alternate_id() {
+                        Some(id) => Self::show_buffer(context, id),
+                        None => context
+                            .app_state
+                            .set_message(String::from("No alternate file")),
+                    }
+                    context.app_state.set_should_render(true);
+                }
+                Command::DeleteBuffer(arg) => {
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                    let Some(id) = Self::buffer_id(context, arg) else {
+                        context
+                            .app_state
+                            .set_message(format!("No matching buffer for {arg}"));
+                        return;
+                    };
+                    if context.buffers.len() == 1 {
+                        context
+                            .app_state
+                            .set_message(String::from("Can't delete the last buffer"));
+                        return;
+                    }
+
+                    let current = id == context.buffers.current_id();
+                    let modified = if current {
+                        context.buffer.is_modified()
+                    } else {
+                        match context.buffers.get(id) {
+                            Some(open) => open.buffer.is_modified(),
+                            None => {
+                                context
+                                    .app_state
+                                    .set_message(format!("Buffer {id} does not exist"));
+                                return;
+                            }
+                        }
+                    };
+                    if modified {
+                        context
+                            .app_state
+                            .set_message(format!("No write since last change for buffer {id}"));
+                        return;
+                    }
+
+                    if current {
+                        let next = context
+                            .buffers
+                            .alternate_id()
+                            .unwrap_or_else(|| context.buffers.id_after(1));
+                        Self::show_buffer(context, next);
+                    }
+                    if let Some(mut open) = context.buffers.remove(id) {
+                        open.marks.sync(&mut open.buffer);
+                        context.buffers.keep_global_marks(&open);
+                        open.buffer.save_undo(&open.file_name);
+                        open.buffer.close_swap();
+                        open.marks.release(&mut open.buffer);
+                    }
                 }
                 Command::Undo => {
+                    let pos = context.buffer.undo(context.cursor.pos());
+                    Self::jump_in_history(context, pos, "Already at oldest change");
+                }
+                Command::Redo => {
+                    let pos = context.buffer.redo(context.cursor.pos());
+                    Self::jump_in_history(context, pos, "Already at newest change");
+                }
+                Command::HistoryStep(delta) => {
+                    let pos = context.buffer.step_history(context.cursor.pos(), *delta);
+                    let message = if *delta < 0 {
+                        "Already at oldest change"
+                    } else {
+                        "Already at newest change"
+                    };
+                    Self::jump_in_history(context, pos, message);
+                }
+                Command::Earlier(arg) | Command::Later(arg) => {
+                    let Some(travel) = Travel::parse(arg) else {
+                        context
+                            .app_state
+                            .set_message(format!("Invalid argument: {arg}"));
+                        context.app_state.set_mode(Mode::Normal);
+                        context.app_state.set_should_render(true);
+                        return;
+                    };
+
+                    let (pos, message) = if matches!(self, Command::Earlier(_)) {
+                        (
+                            context.buffer.earlier(context.cursor.pos(), travel),
+                            "Already at oldest change",
+                        )
+                    } else {
+                        (
+                            context.buffer.later(context.cursor.pos(), travel),
+                            "Already at newest change",
+                        )
+                    };
+                    Self::jump_in_history(context, pos, message);
+                }
+                Command::SetOption(arg) => {
+                    Self::set_option(context, arg);
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                }
+                Command::AnswerPrompt(ch) => {
+                    let Some(prompt) = context.app_state.take_prompt() else {
+                        context.app_state.set_mode(Mode::Normal);
+                        return;
+                    };
+
+                    match prompt.choices.iter().find(|(key, _)| key == ch) {
+                        Some((_, cmd)) => {
+                            let cmd = cmd.clone();
+                            context.app_state.set_mode(Mode::Normal);
+                            cmd.execute(&mut Some(context.reborrow()));
+                        }
+                        // Any other key leaves the question open.
+                        None => context.app_state.set_prompt(prompt),
+                    }
+                    context.app_state.set_should_render(true);
+                }
+                Command::RecoverSwap => {
+                    if let Err(e) = context
+                        .buffer
+                        .recover_swap(context.file_name, context.cursor.pos())
+                    {
+                        context.app_state.set_message(format!("{e:#}"));
+                        context.buffer.discard_swap(context.file_name);
+                    }
+                    context.app_state.set_panel(Panel::None);
+                    context.app_state.set_should_render(true);
+                }
+                Command::DiscardSwap => {
+                    context.buffer.discard_swap(context.file_name);
+                    context.app_state.set_panel(Panel::None);
+                    context.app_state.set_should_render(true);
+                }
+                Command::ShowSwapDiff => {
+                    context
+                        .app_state
+                        .set_panel(Panel::Text(context.buffer.swap_diff()));
+                    if let Some(prompt) = swap_prompt(context.buffer) {
+                        context.app_state.set_prompt(prompt);
+                    }
+                    context.app_state.set_should_render(true);
+                }
+                Command::IgnoreSwapOwner => {
+                    context.buffer.ignore_swap_owner();
+                    context.app_state.set_should_render(true);
+                }
+                Command::CheckDisk => {
+                    if let DiskChange::Changed { modified_here } =
+                        context.buffer.check_disk(context.file_name)
+                    {
+                        context.buffer.end_change(context.cursor.pos());
+                        context.cursor.set_style(CursorStyle::Block);
+                        let prompt = disk_prompt(context.file_name, modified_here, false);
+                        context.app_state.set_prompt(prompt);
+                        context.app_state.set_should_render(true);
+                    }
+                }
+                Command::ReloadFromDisk => {
+                    match context
+                        .buffer
+                        .reload_from_disk(context.file_name, context.cursor.pos())
+                    {
+                        Ok(()) => context
+                            .app_state
+                            .set_message(String::from("Reloaded from disk")),
+                        Err(e) => context.app_state.set_message(format!("{e:#}")),
+                    }
+                    Self::clamp_cursor(context);
+                    context.app_state.set_panel(Panel::None);
+                    context.app_state.set_should_render(true);
+                }
+                Command::KeepMine => {
+                    if let Err(e) = context.buffer.keep_mine(context.file_name) {
+                        context.app_state.set_message(format!("{e:#}"));
+                    }
+                    context.app_state.set_panel(Panel::None);
+                    context.app_state.set_should_render(true);
+                }
+                Command::MergeWithDisk => {
+                    match context
+                        .buffer
+                        .merge_with_disk(context.file_name, context.cursor.pos())
+                    {
+                        Ok(0) => context
+                            .app_state
+                            .set_message(String::from("Merged changes from disk")),
+                        Ok(conflicts) => context
+                            .app_state
+                            .set_message(format!("Merged with {conflicts} conflict(s)")),
+                        Err(e) => context.app_state.set_message(format!("{e:#}")),
+                    }
+                    Self::clamp_cursor(context);
+                    context.app_state.set_panel(Panel::None);
+                    context.app_state.set_should_render(true);
+                }
+                Command::ShowDiskDiff { saving } => {
+                    context
+                        .app_state
+                        .set_panel(Panel::Text(context.buffer.disk_diff(context.file_name)));
+                    if let DiskChange::Changed { modified_here } =
+                        context.buffer.check_disk(context.file_name)
+                    {
+                        let prompt = disk_prompt(context.file_name, modified_here, *saving);
+                        context.app_state.set_prompt(prompt);
+                    }
+                    context.app_state.set_should_render(true);
+                }
+                Command::DeleteChar { register } => {
+                    context.app_state.set_should_render(true);
+                    let (row, col) = (context.cursor.row(), context.cursor.col());
+                    let Some(ch) = context.buffer.get(row)[col..].chars().next() else {
+                        return;
+                    };
+                    let deleted = Register::charwise(&ch.to_string());
+                    if !Self::store(context, *register, deleted, true) {
+                        return;
+                    }
+
+                    context.buffer.begin_change(context.cursor.pos());
+                    context.buffer.remove_char(row, col);
+                    context.buffer.end_change(context.cursor.pos());
+                }
+                Command::Put { register, before } => {
+                    context.app_state.set_should_render(true);
+                    let Some(text) = Self::fetch(context, *register) else {
+                        context
+                            .app_state
+                            .set_message(format!("Nothing in register {register}"));
+                        return;
+                    };
+
+                    context.buffer.begin_change(context.cursor.pos());
+                    let pos = context.buffer.put(context.cursor.pos(), &text, *before);
+                    context.cursor.move_to(pos.row, pos.col);
+                    context.buffer.end_change(pos);
+                    context.viewport.follow(pos.row);
+                }
+                Command::ShowRegisters => {
+                    context.app_state.toggle_registers();
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                }
+                Command::Search { pattern, backward } => {
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                    // Search from where the pattern was started, not the preview.
+                    if let Some((origin, offset)) = context.app_state.search_mut().end_preview() {
+                        context.cursor.move_to(origin.row, origin.col);
+                        context.viewport.offset = offset;
+                    }
+                    Self::start_search(context, pattern, *backward, true);
+                }
+                Command::SearchNext { reverse } => {
+                    context.app_state.set_should_render(true);
+                    let backward = context.app_state.search().is_backward() != *reverse;
+                    context.app_state.search_mut().set_highlight(true);
+                    Self::jump_to_match(context, backward);
+                }
+                Command::SearchWord { backward } => {
+                    context.app_state.set_should_render(true);
+                    let line = context.buffer.get(context.cursor.row());
+                    let words = WordChars::for_file(context.file_name);
+                    let Some(word) = search::word_at(&line, context.cursor.col(), words) else {
+                        context
+                            .app_state
+                            .set_message(String::from("No string under cursor"));
+                        return;
+                    };
+
+                    // Searching from the start of the word skips this occurrence both ways.
+                    let from = context.cursor.pos();
+                    context.cursor.move_to_col(word.start);
+                    let pattern = search::word_pattern(&line[word]);
+                    Self::start_search(context, &pattern, *backward, false);
+                    context.marks.set(PREVIOUS_JUMP, from);
+                }
+                Command::ClearSearchHighlight => {
+                    context.app_state.search_mut().set_highlight(false);
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                }
+                Command::PreviewSearch { pattern, backward } => {
+                    context.app_state.set_should_render(true);
+                    let Some((origin, offset)) = context.app_state.search().preview_origin() else {
+                        return;
+                    };
+
+                    let options = context.app_state.options();
+                    let ignore_case =
+                        search::ignores_case(pattern, options.ignorecase, options.smartcase);
+                    let search = context.app_state.search_mut();
+                    search.set_preview(pattern, ignore_case);
+
+                    context.viewport.offset = offset;
+                    match search.find_preview(context.buffer, *backward) {
+                        Some(pos) => {
+                            context.cursor.move_to(pos.row, pos.col);
+                            context.viewport.center(pos.row);
+                        }
+                        None => context.cursor.move_to(origin.row, origin.col),
+                    }
+                }
+                Command::CancelSearch => {
+                    if let Some((origin, offset)) = context.app_state.search_mut().end_preview() {
+                        context.cursor.move_to(origin.row, origin.col);
+                        context.viewport.offset = offset;
+                    }
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                }
+                Command::Substitute { range, args } => {
+                    // `:s` without a pattern repeats the last one with new flags.
+                    if args.is_empty()
+                        || args.starts_with(|ch: char| ch == '&' || ch.is_alphabetic())
+                    {
+                        let repeat = Command::RepeatSubstitute(range.clone(), args.clone());
+                        repeat.execute(&mut Some(context.reborrow()));
+                        return;
+                    }
+
+                    let parsed =
+                        substitute::split_args(args).and_then(|(pattern, replacement, flags)| {
+                            let previous = context.app_state.last_substitute();
+                            let flags = Flags::parse(
+                                flags,
+                                previous.map_or_else(Flags::default, |s| s.flags),
+                            )?;
+                            // An empty pattern is the last search pattern.
+                            let pattern = if pattern.is_empty() {
+                                context.app_state.search().pattern().to_string()
+                            } else {
+                                pattern
+                            };
+                            if pattern.is_empty() {
+                                anyhow::bail!("No previous regular expression");
+                            }
+                            let options = context.app_state.options();
+                            let ignore_case = search::ignores_case(
+                                &pattern,
+                                options.ignorecase,
+                                options.smartcase,
+                            );
+                            Substitute::new(&pattern, &replacement, flags, ignore_case)
+                        });
+                    match parsed {
+                        Ok(parsed) => Self::substitute(context, range, parsed),
+                        Err(e) => {
+                            context.app_state.set_message(format!("{e:#}"));
+                            context.app_state.set_mode(Mode::Normal);
+                            context.app_state.set_should_render(true);
+                        }
+                    }
+                }
+                Command::RepeatSubstitute(range, flags) => {
+                    let repeated = match context.app_state.last_substitute() {
+                        Some(last) => Flags::parse(flags, last.flags).and_then(|flags| {
+                            let options = context.app_state.options();
+                            let ignore_case = search::ignores_case(
+                                &last.pattern,
+                                options.ignorecase,
+                                options.smartcase,
+                            );
+                            last.with_flags(flags, ignore_case)
+                        }),
+                        None => Err(anyhow::anyhow!("No previous substitute regular expression")),
+                    };
+                    match repeated {
+                        Ok(repeated) => Self::substitute(context, range, repeated),
+                        Err(e) => {
+                            context.app_state.set_message(format!("{e:#}"));
+                            context.app_state.set_mode(Mode::Normal);
+                            context.app_state.set_should_render(true);
+                        }
+                    }
+                }
+                Command::DeleteLines { range, register } => {
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
+                    if context.buffer.is_binary() {
+                        context.app_state.set_message(String::from(
+                            "Bytes of a binary file can only be overwritten",
+                        ));
+                        return;
+                    }
+                    let row = context.cursor.row();
+                    let Some((first, last)) = Self::resolve_range(context, range, (row, row))
+                    else {
+                        return;
+                    };
+                    let deleted = context.buffer.yank_lines(first, last);
+                    if !Self::store(context, *register, deleted, true) {
+                        return;
+                    }
+
+                    context.buffer.begin_change(context.cursor.pos());
+                    for _ in first..=last {
+                        context.buffer.remove(first);
+                    }
+                    let row = first.min(context.buffer.len() - 1);
+                    let line = context.buffer.get(row);
+                    context
+                        .cursor
+                        .move_to(row, line.len() - line.trim_start().len());
+                    context.buffer.end_change(context.cursor.pos());
+                    context.viewport.follow(row);
+                    if last - first >= 2 {
+                        context
+                            .app_state
+                            .set_message(format!("{} fewer lines", last - first + 1));
+                    }
+                }
+                Command::Normal(keys) => Self::normal(context, &mut EventHandler::new(), keys),
+                Command::Global {
+                    range,
+                    args,
+                    invert,
+                } => Self::global(context, range, args, *invert),
+                Command::SwapVisualAnchor => {
+                    let anchor = context.app_state.visual_anchor();
+                    context.app_state.set_visual_anchor(context.cursor.pos());
+                    context.cursor.move_to(anchor.row, anchor.col);
+                    context.viewport.follow(anchor.row);
+                    context.app_state.set_should_render(true);
+                }
+                Command::Move { motion, count } => Self::move_cursor(context, *motion, *count),
+                Command::Operate {
+                    operator,
+                    motion,
+                    count,
+                    register,
+                } => {
+                    context.app_state.set_should_render(true);
+                    let selection = Self::motion_selection(context, *operator, *motion, *count);
+                    if let Some(selection) = selection {
+                        Self::operate(context, *operator, selection, *register);
+                    }
+                }
+                Command::VisualOperate { operator, register } => {
+                    if let Some(selection) = Self::end_visual(context) {
+                        Self::operate(context, *operator, selection, *register);
+                    }
+                }
+                Command::VisualInsert { append } => {
+                    let Some(selection) = Self::end_visual(context) else {
+                        return;
+                    };
+                    context.buffer.begin_change(context.cursor.pos());
+                    let (start, end) = (selection.start, selection.end);
+                    let pos = match (selection.kind, append) {
+                        (SelectionKind::Block, false) => {
+                            let line = context.buffer.get(start.row);
+                            Position {
+                                row: start.row,
+                                col: selection.cols(start.row, &line).start,
+                            }
+                        }
+                        // Past the block even where the first line is shorter.
+                        (SelectionKind::Block, true) => {
+                            let line = context.buffer.get(start.row);
+                            let col = if line.len() > end.col {
+                                selection.cols(start.row, &line).end
+                            } else {
+                                end.col + 1
+                            };
+                            if line.len() < col {
+                                let padding = " ".repeat(col - line.len());
+                                context
+                                    .buffer
+                                    .insert_string(start.row, line.len(), &padding);
+                            }
+                            Position {
+                                row: start.row,
+                                col,
+                            }
+                        }
+                        (SelectionKind::Line, false) => Position {
+                            row: start.row,
+                            col: 0,
+                        },
+                        (SelectionKind::Line, true) => Position {
+                            row: end.row,
+                            col: context.buffer.len_of(end.row),
+                        },
+                        (SelectionKind::Char, false) => start,
+                        (SelectionKind::Char, true) => {
+                            let line = context.buffer.get(end.row);
+                            Position {
+                                row: end.row,
+                                col: selection.cols(end.row, &line).end,
+                            }
+                        }
+                    };
+
+                    if selection.kind == SelectionKind::Block {
+                        context.app_state.set_block_insert(BlockInsert {
+                            start: pos,
+                            last_row: end.row,
+                            pad: *append,
+                        });
+                    }
+                    context.cursor.move_to(pos.row, pos.col);
+                    context.viewport.follow(pos.row);
+                    Command::ChangeMode(Mode::Edit).execute(&mut Some(context.reborrow()));
+                }
+                Command::ConfirmSubstitute(answer) => {
+                    let Some(mut session) = context.app_state.take_substitution() else {
+                        return;
+                    };
+                    match answer {
+                        'y' => {
+                            session.replace(context.buffer);
+                            Self::confirm_next(context, session);
+                        }
+                        'n' => {
+                            session.skip();
+                            Self::confirm_next(context, session);
+                        }
+                        'a' => {
+                            session.replace_all(context.buffer);
+                            Self::finish_substitute(context, session);
+                        }
+                        // `l` replaces this one as the last.
+                        'l' => {
+                            session.replace(context.buffer);
+                            Self::finish_substitute(context, session);
+                        }
+                        _ => Self::finish_substitute(context, session),
+                    }
+                }
+                Command::ToggleUndoTree => {
+                    context.app_state.toggle_undo_tree();
+                    context.app_state.set_mode(Mode::Normal);
+                    context.app_state.set_should_render(true);
                 }
             }
         }
@@ -1,11 +1,31 @@
 mod app;
 mod buffer;
+mod buffer_list;
+mod change_log;
+mod clipboard;
 mod cmd_dispatcher;
 mod cursor;
+mod diff;
+mod ex;
+mod file_format;
+mod file_io;
+mod hex;
+mod history;
 mod input_handler;
 mod logger;
+mod marks;
+mod motion;
+mod operator;
+mod registers;
 mod renderer;
+mod search;
 mod state;
+mod substitute;
+mod swap_file;
+mod syntax;
+mod text_object;
+mod undo_file;
+mod visual;
 
 use app::Application;
 use logger::Logger;
@@ -0,0 +1,171 @@
+use crate::{
+    buffer::Buffer,
+    change_log::{SubscriberId, TextChange},
+    cursor::Position,
+};
+use std::collections::{HashMap, VecDeque};
+
+/// Mark of the position before the latest jump, `''`.
+pub const PREVIOUS_JUMP: char = '\'';
+/// Mark of the latest change, `'.`.
+pub const LAST_CHANGE: char = '.';
+/// Mark of where insert mode was last left, `'^`.
+pub const LAST_INSERT: char = '^';
+
+/// Named positions in one buffer that stay on the same text as it is edited.
+/// `a-z` are local to the buffer; `A-Z` are global, so at most one buffer
+/// holds each of them.
+pub struct Marks {
+    subscriber: SubscriberId,
+    positions: HashMap<char, Position>,
+    /// Lines `:g` is still to visit. They move like the marks and are
+    /// dropped when deleted, so a command can delete lines yet to come.
+    marked_lines: Option<VecDeque<Position>>,
+}
+
+impl Marks {
+    pub fn new(buffer: &mut Buffer) -> Self {
+        Self {
+            subscriber: buffer.subscribe(),
+            positions: HashMap::new(),
+            marked_lines: None,
+        }
+    }
+
+    /// Stops following the changes of `buffer`, the one they were made for.
+    pub fn release(self, buffer: &mut Buffer) {
+        buffer.unsubscribe(self.subscriber);
+    }
+
+    pub fn is_global(name: char) -> bool {
+        name.is_ascii_uppercase()
+    }
+
+    pub fn get(&self, name: char) -> Option<Position> {
+        self.positions.get(&name).copied()
+    }
+
+    pub fn set(&mut self, name: char, pos: Position) {
+        self.positions.insert(name, pos);
+    }
+
+    pub fn remove(&mut self, name: char) -> Option<Position> {
+        self.positions.remove(&name)
+    }
+
+    /// Marks `rows` for `:g` to visit in order.
+    pub fn mark_lines(&mut self, rows: impl IntoIterator<Item = usize>) {
+        let lines = rows.into_iter().map(|row| Position { row, col: 0 });
+        self.marked_lines = Some(lines.collect());
+    }
+
+    /// Whether a `:g` is going through its marked lines.
+    pub fn is_marking_lines(&self) -> bool {
+        self.marked_lines.is_some()
+    }
+
+    /// Takes the next marked line that still exists. Call `sync` first.
+    pub fn next_marked_line(&mut self) -> Option<usize> {
+        Some(self.marked_lines.as_mut()?.pop_front()?.row)
+    }
+
+    pub fn clear_marked_lines(&mut self) {
+        self.marked_lines = None;
+    }
+
+    /// Global marks held by this buffer.
+    pub fn globals(&self) -> impl Iterator<Item = (char, Position)> + '_ {
+        self.positions
+            .iter()
+            .filter(|(name, _)| Self::is_global(**name))
+            .map(|(name, pos)| (*name, *pos))
+    }
+
+    /// Moves the marks along with the changes made to `buffer` since the last
+    /// call, and points `'.` at the latest of them.
+    pub fn sync(&mut self, buffer: &mut Buffer) {
+        for change in buffer.drain_changes(self.subscriber) {
+            self.positions = self
+                .positions
+                .drain()
+                .filter_map(|(name, pos)| Some((name, Self::shift(&pos, &change)?)))
+                .collect();
+            if let Some(lines) = self.marked_lines.as_mut() {
+                *lines = lines
+                    .drain(..)
+                    .filter_map(|pos| Self::shift(&pos, &change))
+                    .collect();
+            }
+            self.positions.insert(LAST_CHANGE, change.start);
+        }
+    }
+
+    /// Where `pos` ends up after `change`, or `None` if its line was deleted.
+    /// Marks inside text removed from a line move to the start of the removal.
+    fn shift(pos: &Position, change: &TextChange) -> Option<Position> {
+        let key = |p: &Position| (p.row, p.col);
+        if key(pos) < key(&change.start) {
+            return Some(*pos);
+        }
+
+        if key(pos) < key(&change.old_end) {
+            let whole_line = change.old_end.row > change.start.row
+                && (pos.row > change.start.row || change.start.col == 0);
+            return (!whole_line).then_some(change.start);
+        }
+
+        if pos.row == change.old_end.row {
+            Some(Position {
+                row: change.new_end.row,
+                col: change.new_end.col + pos.col - change.old_end.col,
+            })
+        } else {
+            Some(Position {
+                row: pos.row - change.old_end.row + change.new_end.row,
+                col: pos.col,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(
+        start: (usize, usize),
+        old_end: (usize, usize),
+        new_end: (usize, usize),
+    ) -> TextChange {
+        let pos = |(row, col)| Position { row, col };
+        TextChange {
+            revision: 0,
+            start_byte: 0,
+            old_end_byte: 0,
+            new_end_byte: 0,
+            start: pos(start),
+            old_end: pos(old_end),
+            new_end: pos(new_end),
+            text: String::new(),
+        }
+    }
+
+    #[test]
+    fn marks_follow_inserted_and_removed_text() {
+        let mark = Position { row: 2, col: 3 };
+        let shifted = |c| Marks::shift(&mark, &c).map(|p| (p.row, p.col));
+
+        // Two lines inserted above.
+        assert_eq!(shifted(change((0, 0), (0, 0), (2, 0))), Some((4, 3)));
+        // Text typed earlier on the same line.
+        assert_eq!(shifted(change((2, 1), (2, 1), (2, 3))), Some((2, 5)));
+        // The line above joined onto the previous one.
+        assert_eq!(shifted(change((0, 4), (1, 0), (0, 4))), Some((1, 3)));
+        // Changes after the mark don't move it.
+        assert_eq!(shifted(change((2, 5), (3, 0), (2, 5))), Some((2, 3)));
+        // Text around the mark removed within its line.
+        assert_eq!(shifted(change((2, 1), (2, 6), (2, 1))), Some((2, 1)));
+        // Its whole line deleted.
+        assert_eq!(shifted(change((2, 0), (3, 0), (2, 0))), None);
+    }
+}
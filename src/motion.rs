@@ -0,0 +1,702 @@
+use crate::{buffer::Buffer, cursor::Position, text_object::TextObject};
+use std::path::Path;
+
+/// Where the cursor goes, on its own or after an operator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// `w` / `W`: the start of the next word, or WORD if `big`.
+    WordForward {
+        big: bool,
+    },
+    /// `b` / `B`: the start of this or the previous word.
+    WordBackward {
+        big: bool,
+    },
+    /// `e` / `E`: the end of this or the next word.
+    WordEnd {
+        big: bool,
+    },
+    /// `ge` / `gE`: the end of the previous word.
+    WordEndBackward {
+        big: bool,
+    },
+    /// `0`
+    LineStart,
+    /// `^`: the first non-blank character.
+    FirstNonBlank,
+    /// `$`: the last character, `count - 1` lines down.
+    LineEnd,
+    /// `)` / `(`: the start of the next or this sentence.
+    SentenceForward,
+    SentenceBackward,
+    /// `}` / `{`: the empty line after or before the paragraph.
+    ParagraphForward,
+    ParagraphBackward,
+    /// `H`, `M` and `L`: the top, middle and bottom lines on screen.
+    ScreenTop,
+    ScreenMiddle,
+    ScreenBottom,
+    /// `gg`: the first line, or line `count`.
+    FirstLine,
+    /// `G`: the last line, or line `count`.
+    LastLine,
+    /// An operator typed twice, as in `dd` or `gUU`: `count` lines from the
+    /// cursor's.
+    Lines,
+    /// `'x` or `` `x ``: the line of a mark, or its exact position.
+    Mark {
+        name: char,
+        exact: bool,
+    },
+    /// `iw`, `a(` and the like: a text object, with what is around it if
+    /// `around`. Only after an operator or in visual mode.
+    Object {
+        object: TextObject,
+        around: bool,
+    },
+}
+
+/// How much of the text between the cursor and the target an operator takes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionKind {
+    /// Up to the target, leaving it out.
+    Exclusive,
+    /// Up to the target, taking it in.
+    Inclusive,
+    /// Every line from the cursor's to the target's.
+    Linewise,
+}
+
+/// Result of reading keys typed so far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keys<T> {
+    /// More keys could still make them valid.
+    Incomplete,
+    Invalid,
+    Complete(T),
+}
+
+/// Which characters make up a word, as Vim's `iskeyword`: letters, digits and
+/// `_`, and whatever else the language of the file allows in names.
+#[derive(Debug, Clone, Copy)]
+pub struct WordChars {
+    extra: &'static str,
+}
+
+impl WordChars {
+    pub fn for_file(file_name: &str) -> Self {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("");
+        let extra = match extension {
+            "css" | "scss" | "sass" | "less" | "html" | "htm" | "xml" | "svg" => "-",
+            "lisp" | "el" | "clj" | "cljs" | "scm" | "rkt" => "-*!?+<>=/",
+            "php" | "pl" | "pm" => "$",
+            _ => "",
+        };
+        Self { extra }
+    }
+
+    pub fn contains(self, ch: char) -> bool {
+        ch.is_alphanumeric() || ch == '_' || self.extra.contains(ch)
+    }
+
+    /// Blanks, keyword characters and other characters are each their own
+    /// class; a word is a run of one class. WORDs only tell blanks apart.
+    pub fn class(self, ch: char, big: bool) -> u8 {
+        if ch.is_whitespace() {
+            0
+        } else if big || self.contains(ch) {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+/// Rows on screen, for `H`, `M` and `L`.
+#[derive(Debug, Clone, Copy)]
+pub struct Screen {
+    pub top: usize,
+    pub height: usize,
+}
+
+impl Motion {
+    /// The motion `keys` name, all of them.
+    pub fn parse(keys: &str) -> Keys<Motion> {
+        let motion = match keys {
+            "" | "g" | "'" | "`" | "i" | "a" => return Keys::Incomplete,
+            "h" => Motion::Left,
+            "l" => Motion::Right,
+            "k" => Motion::Up,
+            "j" => Motion::Down,
+            "w" | "W" => Motion::WordForward { big: keys == "W" },
+            "b" | "B" => Motion::WordBackward { big: keys == "B" },
+            "e" | "E" => Motion::WordEnd { big: keys == "E" },
+            "ge" | "gE" => Motion::WordEndBackward { big: keys == "gE" },
+            "0" => Motion::LineStart,
+            "^" => Motion::FirstNonBlank,
+            "$" => Motion::LineEnd,
+            ")" => Motion::SentenceForward,
+            "(" => Motion::SentenceBackward,
+            "}" => Motion::ParagraphForward,
+            "{" => Motion::ParagraphBackward,
+            "H" => Motion::ScreenTop,
+            "M" => Motion::ScreenMiddle,
+            "L" => Motion::ScreenBottom,
+            "gg" => Motion::FirstLine,
+            "G" => Motion::LastLine,
+            _ => {
+                let mut chars = keys.chars();
+                match (chars.next(), chars.next(), chars.next()) {
+                    (Some(kind @ ('\'' | '`')), Some(name), None) => Motion::Mark {
+                        name,
+                        exact: kind == '`',
+                    },
+                    (Some(kind @ ('i' | 'a')), Some(name), None) => match TextObject::parse(name) {
+                        Some(object) => Motion::Object {
+                            object,
+                            around: kind == 'a',
+                        },
+                        None => return Keys::Invalid,
+                    },
+                    _ => return Keys::Invalid,
+                }
+            }
+        };
+        Keys::Complete(motion)
+    }
+
+    pub fn kind(self) -> MotionKind {
+        match self {
+            Motion::Left
+            | Motion::Right
+            | Motion::WordForward { .. }
+            | Motion::WordBackward { .. }
+            | Motion::LineStart
+            | Motion::FirstNonBlank
+            | Motion::SentenceForward
+            | Motion::SentenceBackward
+            | Motion::ParagraphForward
+            | Motion::ParagraphBackward
+            | Motion::Mark { exact: true, .. } => MotionKind::Exclusive,
+            // Objects say what they take; see `TextObject::select`.
+            Motion::WordEnd { .. }
+            | Motion::WordEndBackward { .. }
+            | Motion::LineEnd
+            | Motion::Object { .. } => MotionKind::Inclusive,
+            Motion::Up
+            | Motion::Down
+            | Motion::ScreenTop
+            | Motion::ScreenMiddle
+            | Motion::ScreenBottom
+            | Motion::FirstLine
+            | Motion::LastLine
+            | Motion::Lines
+            | Motion::Mark { exact: false, .. } => MotionKind::Linewise,
+        }
+    }
+
+    /// Whether the position before it is kept in the `` ` `` mark.
+    pub fn is_jump(self) -> bool {
+        matches!(
+            self,
+            Motion::SentenceForward
+                | Motion::SentenceBackward
+                | Motion::ParagraphForward
+                | Motion::ParagraphBackward
+                | Motion::ScreenTop
+                | Motion::ScreenMiddle
+                | Motion::ScreenBottom
+                | Motion::FirstLine
+                | Motion::LastLine
+        )
+    }
+
+    /// Where the motion goes from `pos` in `buffer`, or `None` if it can't
+    /// move. Marks are looked up by the caller, and objects aren't targets.
+    pub fn target(
+        self,
+        buffer: &Buffer,
+        words: WordChars,
+        screen: Screen,
+        pos: Position,
+        count: Option<usize>,
+    ) -> Option<Position> {
+        let last_row = buffer.len() - 1;
+        let n = count.unwrap_or(1);
+        let at_row = |row: usize| {
+            let line = buffer.get(row);
+            Position {
+                row,
+                col: line.len() - line.trim_start().len(),
+            }
+        };
+        let repeat = |step: &dyn Fn(&mut Walker)| {
+            let mut walker = Walker::new(buffer, pos);
+            for _ in 0..n {
+                let before = walker.pos();
+                step(&mut walker);
+                if walker.pos() == before {
+                    break;
+                }
+            }
+            Some(walker.pos())
+        };
+        let screen_bottom = (screen.top + screen.height.max(1) - 1).min(last_row);
+
+        match self {
+            Motion::Left => {
+                let line = buffer.get(pos.row);
+                let col = line[..pos.col.min(line.len())]
+                    .char_indices()
+                    .rev()
+                    .nth(n - 1)
+                    .map_or(0, |(col, _)| col);
+                Some(Position { row: pos.row, col })
+            }
+            Motion::Right => {
+                let line = buffer.get(pos.row);
+                let col = line[pos.col.min(line.len())..]
+                    .char_indices()
+                    .nth(n)
+                    .map_or(line.len(), |(col, _)| pos.col + col);
+                Some(Position { row: pos.row, col })
+            }
+            Motion::Up if pos.row > 0 => Some(at_row(pos.row.saturating_sub(n))),
+            Motion::Down if pos.row < last_row => {
+                Some(at_row(pos.row.saturating_add(n).min(last_row)))
+            }
+            Motion::Up | Motion::Down => None,
+            Motion::WordForward { big } => repeat(&|w| word_forward(w, words, big)),
+            Motion::WordBackward { big } => repeat(&|w| word_backward(w, words, big)),
+            Motion::WordEnd { big } => repeat(&|w| word_end(w, words, big, false)),
+            Motion::WordEndBackward { big } => repeat(&|w| word_end_backward(w, words, big)),
+            Motion::LineStart => Some(Position {
+                row: pos.row,
+                col: 0,
+            }),
+            Motion::FirstNonBlank => Some(at_row(pos.row)),
+            Motion::LineEnd => {
+                let row = pos.row.saturating_add(n - 1).min(last_row);
+                let line = buffer.get(row);
+                let col = line.char_indices().last().map_or(0, |(col, _)| col);
+                Some(Position { row, col })
+            }
+            Motion::SentenceForward => repeat(&sentence_forward),
+            Motion::SentenceBackward => {
+                let mut pos = pos;
+                for _ in 0..n {
+                    let before = pos;
+                    pos = sentence_backward(buffer, pos);
+                    if pos == before {
+                        break;
+                    }
+                }
+                Some(pos)
+            }
+            Motion::ParagraphForward => {
+                let mut row = pos.row;
+                for _ in 0..n.min(buffer.len()) {
+                    row = paragraph_forward(buffer, row);
+                }
+                // At the end of the text, on its last character.
+                let line = buffer.get(row);
+                let col = line.char_indices().last().map_or(0, |(col, _)| col);
+                Some(Position { row, col })
+            }
+            Motion::ParagraphBackward => {
+                let mut row = pos.row;
+                for _ in 0..n.min(buffer.len()) {
+                    row = paragraph_backward(buffer, row);
+                }
+                Some(Position { row, col: 0 })
+            }
+            Motion::ScreenTop => Some(at_row(screen.top.saturating_add(n - 1).min(screen_bottom))),
+            Motion::ScreenMiddle => Some(at_row(screen.top + (screen_bottom - screen.top) / 2)),
+            Motion::ScreenBottom => {
+                Some(at_row(screen_bottom.saturating_sub(n - 1).max(screen.top)))
+            }
+            Motion::FirstLine => Some(at_row(count.map_or(0, |n| n - 1).min(last_row))),
+            Motion::LastLine => Some(at_row(count.map_or(last_row, |n| n - 1).min(last_row))),
+            Motion::Lines => Some(at_row(pos.row.saturating_add(n - 1).min(last_row))),
+            Motion::Mark { .. } | Motion::Object { .. } => None,
+        }
+    }
+}
+
+/// Where `cw` ends: the end of the word under the cursor, not of the next one
+/// as `ce` would when the cursor is already on the last character.
+pub fn change_word_end(
+    buffer: &Buffer,
+    words: WordChars,
+    pos: Position,
+    count: Option<usize>,
+    big: bool,
+) -> Position {
+    let mut walker = Walker::new(buffer, pos);
+    word_end(&mut walker, words, big, true);
+    for _ in 1..count.unwrap_or(1) {
+        word_end(&mut walker, words, big, false);
+    }
+    walker.pos()
+}
+
+/// Steps through the text a character at a time, taking each line break as a
+/// `\n` at the end of its line.
+#[derive(Clone)]
+struct Walker<'a> {
+    buffer: &'a Buffer,
+    row: usize,
+    line: String,
+    col: usize,
+}
+
+impl<'a> Walker<'a> {
+    fn new(buffer: &'a Buffer, pos: Position) -> Self {
+        let line = buffer.get(pos.row);
+        let col = pos.col.min(line.len());
+        let col = (0..=col)
+            .rev()
+            .find(|&i| line.is_char_boundary(i))
+            .unwrap_or(0);
+        Self {
+            buffer,
+            row: pos.row,
+            line,
+            col,
+        }
+    }
+
+    fn pos(&self) -> Position {
+        Position {
+            row: self.row,
+            col: self.col,
+        }
+    }
+
+    fn ch(&self) -> char {
+        self.line[self.col..].chars().next().unwrap_or('\n')
+    }
+
+    fn at_empty_line(&self) -> bool {
+        self.line.is_empty()
+    }
+
+    /// Moves to the next character, unless at the end of the text.
+    fn next(&mut self) -> bool {
+        if self.col < self.line.len() {
+            self.col += self.ch().len_utf8();
+        } else if self.row + 1 < self.buffer.len() {
+            self.row += 1;
+            self.line = self.buffer.get(self.row);
+            self.col = 0;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Moves to the previous character, unless at the start of the text.
+    fn prev(&mut self) -> bool {
+        if self.col > 0 {
+            self.col = self.line[..self.col]
+                .char_indices()
+                .last()
+                .map_or(0, |(col, _)| col);
+        } else if self.row > 0 {
+            self.row -= 1;
+            self.line = self.buffer.get(self.row);
+            self.col = self.line.len();
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// The class of the next character, if there is one.
+    fn peek_class(&self, words: WordChars, big: bool) -> Option<u8> {
+        let mut next = self.clone();
+        next.next().then(|| words.class(next.ch(), big))
+    }
+}
+
+/// `w`: past the rest of the word and the blanks after it. Empty lines count
+/// as words.
+fn word_forward(walker: &mut Walker, words: WordChars, big: bool) {
+    let class = words.class(walker.ch(), big);
+    if class != 0 {
+        while words.class(walker.ch(), big) == class {
+            if !walker.next() {
+                return;
+            }
+        }
+    } else if !walker.next() {
+        return;
+    }
+    while words.class(walker.ch(), big) == 0 && !walker.at_empty_line() {
+        if !walker.next() {
+            return;
+        }
+    }
+}
+
+/// `b`: back over blanks to the start of the word before them.
+fn word_backward(walker: &mut Walker, words: WordChars, big: bool) {
+    if !walker.prev() {
+        return;
+    }
+    while words.class(walker.ch(), big) == 0 && !walker.at_empty_line() {
+        if !walker.prev() {
+            return;
+        }
+    }
+    if walker.at_empty_line() {
+        return;
+    }
+
+    let class = words.class(walker.ch(), big);
+    loop {
+        let before = walker.clone();
+        if !walker.prev() || words.class(walker.ch(), big) != class {
+            *walker = before;
+            return;
+        }
+    }
+}
+
+/// `e`: over blanks to the end of the next word, or of the word under the
+/// cursor if `stay` and it doesn't end there.
+fn word_end(walker: &mut Walker, words: WordChars, big: bool, stay: bool) {
+    if !stay || words.class(walker.ch(), big) == 0 {
+        if !walker.next() {
+            return;
+        }
+        while words.class(walker.ch(), big) == 0 {
+            if !walker.next() {
+                return;
+            }
+        }
+    }
+
+    let class = words.class(walker.ch(), big);
+    while walker.peek_class(words, big) == Some(class) {
+        walker.next();
+    }
+}
+
+/// `ge`: back out of the word under the cursor and over blanks. Empty lines
+/// count as words.
+fn word_end_backward(walker: &mut Walker, words: WordChars, big: bool) {
+    let class = words.class(walker.ch(), big);
+    if class != 0 {
+        while words.class(walker.ch(), big) == class {
+            if !walker.prev() {
+                return;
+            }
+        }
+    } else if !walker.prev() {
+        return;
+    }
+    while words.class(walker.ch(), big) == 0 && !walker.at_empty_line() {
+        if !walker.prev() {
+            return;
+        }
+    }
+}
+
+/// `)`: to the first character after the end of a sentence, `.`, `!` or `?`
+/// followed by closing brackets or quotes and a blank. The first empty line
+/// after text is a sentence too.
+fn sentence_forward(walker: &mut Walker) {
+    enum State {
+        InSentence,
+        Ended,
+        Gap,
+    }
+
+    let mut was_empty = walker.at_empty_line();
+    let mut state = if was_empty {
+        State::Gap
+    } else {
+        State::InSentence
+    };
+    while walker.next() {
+        let ch = walker.ch();
+        if walker.at_empty_line() {
+            if !was_empty {
+                return;
+            }
+            continue;
+        }
+        was_empty = false;
+        match state {
+            State::InSentence if ".!?".contains(ch) => state = State::Ended,
+            State::InSentence => {}
+            State::Ended if ch.is_whitespace() => state = State::Gap,
+            State::Ended if ".!?)]\"'".contains(ch) => {}
+            State::Ended => state = State::InSentence,
+            State::Gap if !ch.is_whitespace() => return,
+            State::Gap => {}
+        }
+    }
+}
+
+/// `(`: to the start of the sentence the cursor is in, or of the one before
+/// if it's already there.
+fn sentence_backward(buffer: &Buffer, pos: Position) -> Position {
+    let before = |a: Position, b: Position| (a.row, a.col) < (b.row, b.col);
+    let mut top = pos.row;
+    loop {
+        // Sentences are found from the start of a paragraph.
+        if !buffer.get(top).is_empty() {
+            while top > 0 && !buffer.get(top - 1).is_empty() {
+                top -= 1;
+            }
+        }
+
+        let mut walker = Walker::new(buffer, Position { row: top, col: 0 });
+        while !walker.at_empty_line() && walker.ch().is_whitespace() && walker.next() {}
+        let mut start = None;
+        while before(walker.pos(), pos) {
+            start = Some(walker.pos());
+            let from = walker.pos();
+            sentence_forward(&mut walker);
+            if walker.pos() == from {
+                break;
+            }
+        }
+
+        match start {
+            Some(start) => return start,
+            None if top == 0 => return Position { row: 0, col: 0 },
+            None => top -= 1,
+        }
+    }
+}
+
+/// `}`: the next empty line after a non-empty one, or the end of the text.
+fn paragraph_forward(buffer: &Buffer, row: usize) -> usize {
+    let last_row = buffer.len() - 1;
+    let mut row = row;
+    while row < last_row && buffer.get(row).is_empty() {
+        row += 1;
+    }
+    while row < last_row {
+        row += 1;
+        if buffer.get(row).is_empty() {
+            break;
+        }
+    }
+    row
+}
+
+/// `{`: the previous empty line before a non-empty one, or the first line.
+fn paragraph_backward(buffer: &Buffer, row: usize) -> usize {
+    let mut row = row;
+    while row > 0 && buffer.get(row).is_empty() {
+        row -= 1;
+    }
+    while row > 0 {
+        row -= 1;
+        if buffer.get(row).is_empty() {
+            break;
+        }
+    }
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::test_util::buffer_of;
+
+    fn at(row: usize, col: usize) -> Position {
+        Position { row, col }
+    }
+
+    /// Where `keys` go from `pos`, with a 3-line screen on top.
+    fn go(buffer: &Buffer, keys: &str, pos: Position, count: Option<usize>) -> Position {
+        let Keys::Complete(motion) = Motion::parse(keys) else {
+            panic!("not a motion: {keys}");
+        };
+        let screen = Screen { top: 0, height: 3 };
+        let words = WordChars::for_file("main.rs");
+        motion.target(buffer, words, screen, pos, count).unwrap()
+    }
+
+    #[test]
+    fn moves_by_words() {
+        let buffer = buffer_of("fn foo(a_b, c) {\n\n    x.y\n}");
+        assert_eq!(go(&buffer, "w", at(0, 0), None), at(0, 3));
+        assert_eq!(go(&buffer, "w", at(0, 3), None), at(0, 6));
+        assert_eq!(go(&buffer, "w", at(0, 3), Some(3)), at(0, 10));
+        assert_eq!(go(&buffer, "W", at(0, 3), None), at(0, 12));
+        assert_eq!(go(&buffer, "w", at(0, 15), None), at(1, 0));
+        assert_eq!(go(&buffer, "w", at(1, 0), None), at(2, 4));
+        assert_eq!(go(&buffer, "e", at(0, 0), None), at(0, 1));
+        assert_eq!(go(&buffer, "e", at(0, 1), None), at(0, 5));
+        assert_eq!(go(&buffer, "E", at(0, 7), None), at(0, 10));
+        assert_eq!(go(&buffer, "b", at(2, 4), None), at(1, 0));
+        assert_eq!(go(&buffer, "b", at(0, 9), None), at(0, 7));
+        assert_eq!(go(&buffer, "B", at(0, 13), None), at(0, 12));
+        assert_eq!(go(&buffer, "ge", at(0, 7), None), at(0, 6));
+        assert_eq!(go(&buffer, "gE", at(2, 4), None), at(1, 0));
+        assert_eq!(
+            change_word_end(&buffer, WordChars::for_file(""), at(0, 4), None, false),
+            at(0, 5)
+        );
+
+        let css = buffer_of("font-size: 1em");
+        let words = WordChars::for_file("a.css");
+        let screen = Screen { top: 0, height: 1 };
+        let end = Motion::WordEnd { big: false }.target(&css, words, screen, at(0, 0), None);
+        assert_eq!(end, Some(at(0, 8)));
+    }
+
+    #[test]
+    fn moves_by_lines_sentences_and_paragraphs() {
+        let buffer = buffer_of("  One. Two!  Three\nfour.\n\n\nFive?\nsix\n");
+        assert_eq!(go(&buffer, "0", at(0, 4), None), at(0, 0));
+        assert_eq!(go(&buffer, "^", at(0, 4), None), at(0, 2));
+        assert_eq!(go(&buffer, "$", at(0, 0), None), at(0, 17));
+        assert_eq!(go(&buffer, "$", at(0, 0), Some(2)), at(1, 4));
+
+        assert_eq!(go(&buffer, ")", at(0, 2), None), at(0, 7));
+        assert_eq!(go(&buffer, ")", at(0, 7), None), at(0, 13));
+        assert_eq!(go(&buffer, ")", at(0, 13), None), at(2, 0));
+        assert_eq!(go(&buffer, ")", at(2, 0), None), at(4, 0));
+        assert_eq!(go(&buffer, "(", at(0, 15), None), at(0, 13));
+        assert_eq!(go(&buffer, "(", at(0, 13), None), at(0, 7));
+        assert_eq!(go(&buffer, "(", at(4, 0), None), at(3, 0));
+
+        assert_eq!(go(&buffer, "}", at(0, 3), None), at(2, 0));
+        assert_eq!(go(&buffer, "}", at(2, 0), None), at(6, 0));
+        let last = buffer_of("one\n\ntwo é");
+        assert_eq!(go(&last, "}", at(2, 0), None), at(2, 4));
+        assert_eq!(go(&buffer, "{", at(5, 1), None), at(3, 0));
+        assert_eq!(go(&buffer, "{", at(1, 1), None), at(0, 0));
+
+        assert_eq!(go(&buffer, "H", at(5, 0), Some(2)), at(1, 0));
+        assert_eq!(go(&buffer, "M", at(5, 0), None), at(1, 0));
+        assert_eq!(go(&buffer, "L", at(0, 0), None), at(2, 0));
+        assert_eq!(go(&buffer, "G", at(0, 0), Some(5)), at(4, 0));
+        assert_eq!(go(&buffer, "gg", at(5, 0), None), at(0, 2));
+
+        // Huge counts stop at the ends of the text.
+        for keys in ["j", "$", "}", "w"] {
+            assert_eq!(
+                go(&buffer, keys, at(1, 0), Some(usize::MAX)).row,
+                6,
+                "{keys}"
+            );
+        }
+        assert_eq!(go(&buffer, "(", at(4, 0), Some(usize::MAX)), at(0, 0));
+        assert_eq!(go(&buffer, "H", at(1, 0), Some(usize::MAX)), at(2, 0));
+    }
+}
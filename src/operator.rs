@@ -0,0 +1,239 @@
+use crate::motion::{Keys, Motion};
+
+/// Edits the text a motion moves over, or a visual selection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    /// `d`
+    Delete,
+    /// `c`
+    Change,
+    /// `y`
+    Yank,
+    /// `>`
+    ShiftRight,
+    /// `<`
+    ShiftLeft,
+    /// `=`
+    Reindent,
+    /// `gu`
+    Lower,
+    /// `gU`
+    Upper,
+    /// `g~`
+    ToggleCase,
+}
+
+/// Normal or visual mode keys read as `[count]operator[count]motion` or
+/// `[count]motion`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Parsed {
+    Move {
+        motion: Motion,
+        count: Option<usize>,
+    },
+    Operate {
+        operator: Operator,
+        motion: Motion,
+        count: Option<usize>,
+    },
+}
+
+impl Operator {
+    pub const ALL: [Operator; 9] = [
+        Operator::Delete,
+        Operator::Change,
+        Operator::Yank,
+        Operator::ShiftRight,
+        Operator::ShiftLeft,
+        Operator::Reindent,
+        Operator::Lower,
+        Operator::Upper,
+        Operator::ToggleCase,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Operator::Delete => "d",
+            Operator::Change => "c",
+            Operator::Yank => "y",
+            Operator::ShiftRight => ">",
+            Operator::ShiftLeft => "<",
+            Operator::Reindent => "=",
+            Operator::Lower => "gu",
+            Operator::Upper => "gU",
+            Operator::ToggleCase => "g~",
+        }
+    }
+
+    /// Whether it edits the text, unlike `y`.
+    pub fn changes_text(self) -> bool {
+        self != Operator::Yank
+    }
+
+    /// Whether `keys` after the operator repeat it to act on whole lines:
+    /// `dd`, or `gUU` as well as `gUgU`.
+    fn is_doubled(self, keys: &str) -> bool {
+        let name = self.name();
+        keys == name || (name.len() > 1 && keys == &name[name.len() - 1..])
+    }
+
+    /// Changes the case of `text` as `gu`, `gU` or `g~`; other operators
+    /// leave it alone.
+    pub fn change_case(self, text: &str) -> String {
+        match self {
+            Operator::Lower => text.to_lowercase(),
+            Operator::Upper => text.to_uppercase(),
+            Operator::ToggleCase => text
+                .chars()
+                .flat_map(|ch| {
+                    if ch.is_uppercase() {
+                        ch.to_lowercase().collect::<Vec<_>>()
+                    } else {
+                        ch.to_uppercase().collect()
+                    }
+                })
+                .collect(),
+            _ => text.to_string(),
+        }
+    }
+}
+
+/// Reads the keys typed so far. Operators are only read if `operators`, as
+/// visual mode applies them to the selection at once.
+pub fn parse(keys: &str, operators: bool) -> Keys<Parsed> {
+    let (count, keys) = split_count(keys);
+    if keys.is_empty() {
+        return Keys::Incomplete;
+    }
+
+    let operator = Operator::ALL
+        .into_iter()
+        .filter(|_| operators)
+        .find(|operator| keys.starts_with(operator.name()));
+    let Some(operator) = operator else {
+        // Text objects need an operator outside visual mode.
+        if operators && keys.starts_with(['i', 'a']) {
+            return Keys::Invalid;
+        }
+        return match Motion::parse(keys) {
+            Keys::Complete(motion) => Keys::Complete(Parsed::Move { motion, count }),
+            Keys::Incomplete => Keys::Incomplete,
+            Keys::Invalid => Keys::Invalid,
+        };
+    };
+
+    // `2d3j` deletes six lines down.
+    let (motion_count, keys) = split_count(&keys[operator.name().len()..]);
+    let count = match (count, motion_count) {
+        (Some(a), Some(b)) => Some(a.saturating_mul(b)),
+        (a, b) => a.or(b),
+    };
+    if operator.is_doubled(keys) {
+        return Keys::Complete(Parsed::Operate {
+            operator,
+            motion: Motion::Lines,
+            count,
+        });
+    }
+
+    match Motion::parse(keys) {
+        Keys::Complete(motion) => Keys::Complete(Parsed::Operate {
+            operator,
+            motion,
+            count,
+        }),
+        Keys::Incomplete => Keys::Incomplete,
+        Keys::Invalid => Keys::Invalid,
+    }
+}
+
+/// A leading count and the keys after it. A count can't start with `0`.
+fn split_count(keys: &str) -> (Option<usize>, &str) {
+    let digits = keys
+        .char_indices()
+        .find(|&(i, ch)| !ch.is_ascii_digit() || (i == 0 && ch == '0'))
+        .map_or(keys.len(), |(i, _)| i);
+    match keys[..digits].parse() {
+        Ok(count) => (Some(count), &keys[digits..]),
+        Err(_) => (None, keys),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_object::TextObject;
+
+    fn operate(operator: Operator, motion: Motion, count: Option<usize>) -> Keys<Parsed> {
+        Keys::Complete(Parsed::Operate {
+            operator,
+            motion,
+            count,
+        })
+    }
+
+    #[test]
+    fn reads_operators_counts_and_motions() {
+        assert_eq!(
+            parse("dj", true),
+            operate(Operator::Delete, Motion::Down, None)
+        );
+        assert_eq!(
+            parse("3dd", true),
+            operate(Operator::Delete, Motion::Lines, Some(3))
+        );
+        assert_eq!(
+            parse("2y3k", true),
+            operate(Operator::Yank, Motion::Up, Some(6))
+        );
+        assert_eq!(
+            parse("gUU", true),
+            operate(Operator::Upper, Motion::Lines, None)
+        );
+        assert_eq!(
+            parse("g~g~", true),
+            operate(Operator::ToggleCase, Motion::Lines, None)
+        );
+        assert_eq!(
+            parse("guG", true),
+            operate(Operator::Lower, Motion::LastLine, None)
+        );
+        let mark = Motion::Mark {
+            name: 'a',
+            exact: true,
+        };
+        assert_eq!(parse("c`a", true), operate(Operator::Change, mark, None));
+
+        for keys in ["", "2", "d", "d2", "gU", "gUg", ">g", "d'"] {
+            assert_eq!(parse(keys, true), Keys::Incomplete, "{keys}");
+        }
+        assert_eq!(parse("dx", true), Keys::Invalid);
+        assert_eq!(parse("0d", true), Keys::Invalid);
+
+        let move_down = Parsed::Move {
+            motion: Motion::Down,
+            count: Some(12),
+        };
+        assert_eq!(parse("12j", false), Keys::Complete(move_down));
+        assert_eq!(parse("d", false), Keys::Invalid);
+
+        let word = Motion::Object {
+            object: TextObject::Word { big: false },
+            around: true,
+        };
+        assert_eq!(
+            parse("2daw", true),
+            operate(Operator::Delete, word, Some(2))
+        );
+        assert_eq!(parse("ci", true), Keys::Incomplete);
+        assert_eq!(parse("diq", true), Keys::Invalid);
+        assert_eq!(parse("aw", true), Keys::Invalid);
+        assert_eq!(
+            parse("aw", false),
+            Keys::Complete(Parsed::Move {
+                motion: word,
+                count: None
+            })
+        );
+    }
+}
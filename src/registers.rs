@@ -0,0 +1,322 @@
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+
+/// The register used when none is named, `""`.
+pub const UNNAMED: char = '"';
+/// Register that discards whatever is written to it, `"_`.
+pub const BLACK_HOLE: char = '_';
+/// Deletions within one line, `"-`.
+const SMALL_DELETE: char = '-';
+/// The latest yank, `"0`.
+const LAST_YANK: char = '0';
+
+/// How the text of a register is put back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterKind {
+    /// A run of characters, possibly across line breaks, put inside a line.
+    Charwise,
+    /// Whole lines, put above or below the cursor line.
+    Linewise,
+    /// A rectangle, put at the same column on consecutive lines.
+    Blockwise,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Register {
+    pub kind: RegisterKind,
+    /// The text split at line breaks, so charwise `ab\ncd` is `["ab", "cd"]`.
+    pub lines: Vec<String>,
+}
+
+impl Register {
+    pub fn new(kind: RegisterKind, lines: Vec<String>) -> Self {
+        Self { kind, lines }
+    }
+
+    pub fn charwise(text: &str) -> Self {
+        Self::new(
+            RegisterKind::Charwise,
+            text.split('\n').map(String::from).collect(),
+        )
+    }
+
+    /// Reads text from outside the editor, e.g. the system clipboard. Text
+    /// ending in a line break is taken as whole lines.
+    pub fn from_text(text: &str) -> Self {
+        match text.strip_suffix('\n') {
+            Some(lines) => Self::new(
+                RegisterKind::Linewise,
+                lines.split('\n').map(String::from).collect(),
+            ),
+            None => Self::charwise(text),
+        }
+    }
+
+    /// The text as handed outside the editor; whole lines end in a line break.
+    pub fn to_text(&self) -> String {
+        let mut text = self.lines.join("\n");
+        if self.kind == RegisterKind::Linewise {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Adds `other` after this text, as `"A` does. Anything appended to or
+    /// from whole lines becomes whole lines.
+    fn append(&mut self, other: Register) {
+        match (self.kind, other.kind) {
+            (RegisterKind::Charwise, RegisterKind::Charwise) => {
+                let mut lines = other.lines.into_iter();
+                if let (Some(last), Some(first)) = (self.lines.last_mut(), lines.next()) {
+                    last.push_str(&first);
+                }
+                self.lines.extend(lines);
+            }
+            (RegisterKind::Linewise, _) | (_, RegisterKind::Linewise) => {
+                self.kind = RegisterKind::Linewise;
+                self.lines.extend(other.lines);
+            }
+            _ => self.lines.extend(other.lines),
+        }
+    }
+
+    /// The text on one line, with line breaks shown as `^J`.
+    fn summary(&self) -> String {
+        let mut summary = self.lines.join("^J");
+        if self.kind == RegisterKind::Linewise {
+            summary.push_str("^J");
+        }
+        summary
+    }
+}
+
+/// Text kept for putting back, shared by all buffers.
+///
+/// - `""` refers to the register written last.
+/// - `"0` holds the latest yank; `"1`-`"9` the latest deletions of whole or
+///   several lines, newest first; `"-` the latest deletion within one line.
+/// - `"a`-`"z` are written only when named; `"A`-`"Z` append to them.
+/// - `"+` and `"*` keep what was last copied to the system clipboard.
+/// - `"_` discards what is written to it.
+/// - `".`, `":`, `"/` and `"%` are read-only: the last inserted text, the
+///   last ex command line, the last search pattern and the current file name.
+pub struct Registers {
+    stored: HashMap<char, Register>,
+    /// Register `""` refers to.
+    unnamed: char,
+    /// Text typed since insert mode was entered.
+    inserting: String,
+    last_insert: String,
+    last_command: String,
+    last_search: String,
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Self {
+            stored: HashMap::new(),
+            unnamed: LAST_YANK,
+            inserting: String::new(),
+            last_insert: String::new(),
+            last_command: String::new(),
+            last_search: String::new(),
+        }
+    }
+
+    /// Whether `name` can follow `"` in normal mode.
+    pub fn is_valid(name: char) -> bool {
+        name.is_ascii_alphanumeric() || "\"-_.:/%+*".contains(name)
+    }
+
+    /// Contents of register `name`, or `None` if it is empty. `"%` reads
+    /// `file_name`.
+    pub fn get(&self, name: char, file_name: &str) -> Option<Register> {
+        let text = |text: &str| (!text.is_empty()).then(|| Register::charwise(text));
+        match name {
+            UNNAMED => self.stored.get(&self.unnamed).cloned(),
+            '.' => text(&self.last_insert),
+            ':' => text(&self.last_command),
+            '/' => text(&self.last_search),
+            '%' => text(file_name),
+            name => self.stored.get(&name.to_ascii_lowercase()).cloned(),
+        }
+    }
+
+    /// Stores yanked text in `name`, or in `"0` for the unnamed register.
+    pub fn yank(&mut self, name: char, register: Register) -> Result<()> {
+        if name == UNNAMED {
+            self.stored.insert(LAST_YANK, register);
+            self.unnamed = LAST_YANK;
+            Ok(())
+        } else {
+            self.write(name, register)
+        }
+    }
+
+    /// Stores deleted text in `name`. Unnamed deletions within one line go
+    /// to `"-`, larger ones push `"1`-`"8` down to `"2`-`"9` and go to `"1`.
+    pub fn delete(&mut self, name: char, register: Register) -> Result<()> {
+        if name != UNNAMED {
+            return self.write(name, register);
+        }
+
+        if register.kind == RegisterKind::Charwise && register.lines.len() == 1 {
+            self.stored.insert(SMALL_DELETE, register);
+            self.unnamed = SMALL_DELETE;
+        } else {
+            for n in (1..9).rev() {
+                if let Some(older) = self.stored.remove(&digit(n)) {
+                    self.stored.insert(digit(n + 1), older);
+                }
+            }
+            self.stored.insert(digit(1), register);
+            self.unnamed = digit(1);
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, name: char, register: Register) -> Result<()> {
+        match name {
+            BLACK_HOLE => {}
+            'A'..='Z' => {
+                let name = name.to_ascii_lowercase();
+                match self.stored.get_mut(&name) {
+                    Some(stored) => stored.append(register),
+                    None => {
+                        self.stored.insert(name, register);
+                    }
+                }
+                self.unnamed = name;
+            }
+            'a'..='z' | '0'..='9' | SMALL_DELETE | '+' | '*' => {
+                self.stored.insert(name, register);
+                self.unnamed = name;
+            }
+            '.' | ':' | '/' | '%' => bail!("Register {name} is read-only"),
+            _ => bail!("Invalid register name: {name}"),
+        }
+
+        Ok(())
+    }
+
+    /// Records text typed in insert mode, for `".`.
+    pub fn typed(&mut self, text: &str) {
+        self.inserting.push_str(text);
+    }
+
+    /// Takes back the last typed character after a backspace.
+    pub fn untyped(&mut self) {
+        self.inserting.pop();
+    }
+
+    /// Makes the text typed since entering insert mode the contents of `".`.
+    pub fn finish_insert(&mut self) {
+        if !self.inserting.is_empty() {
+            self.last_insert = std::mem::take(&mut self.inserting);
+        }
+    }
+
+    pub fn set_last_command(&mut self, command_line: String) {
+        self.last_command = command_line;
+    }
+
+    pub fn set_last_search(&mut self, pattern: String) {
+        self.last_search = pattern;
+    }
+
+    /// `:registers` listing: kind (`c`, `l` or `b`), name and contents of
+    /// every register that holds something.
+    pub fn lines(&self, file_name: &str) -> Vec<String> {
+        let names = [UNNAMED]
+            .into_iter()
+            .chain('0'..='9')
+            .chain('a'..='z')
+            .chain(['-', '.', ':', '/', '%', '+', '*']);
+
+        let mut lines = vec![String::from("Type Name Content")];
+        lines.extend(names.filter_map(|name| {
+            let register = self.get(name, file_name)?;
+            let kind = match register.kind {
+                RegisterKind::Charwise => 'c',
+                RegisterKind::Linewise => 'l',
+                RegisterKind::Blockwise => 'b',
+            };
+            Some(format!("  {kind}  \"{name}   {}", register.summary()))
+        }));
+        lines
+    }
+}
+
+fn digit(n: u32) -> char {
+    char::from_digit(n, 10).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Register {
+        Register::new(
+            RegisterKind::Linewise,
+            text.iter().map(|s| s.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn deletions_fill_numbered_registers() {
+        let mut registers = Registers::new();
+        registers.yank(UNNAMED, lines(&["kept"])).unwrap();
+        for n in 1..=10 {
+            registers.delete(UNNAMED, lines(&[&n.to_string()])).unwrap();
+        }
+        registers.delete(UNNAMED, Register::charwise("x")).unwrap();
+
+        assert_eq!(registers.get('1', ""), Some(lines(&["10"])));
+        assert_eq!(registers.get('9', ""), Some(lines(&["2"])));
+        assert_eq!(registers.get('0', ""), Some(lines(&["kept"])));
+        assert_eq!(registers.get(UNNAMED, ""), Some(Register::charwise("x")));
+    }
+
+    #[test]
+    fn named_registers_append_and_black_hole_discards() {
+        let mut registers = Registers::new();
+        registers.yank('a', Register::charwise("foo")).unwrap();
+        registers.yank('A', Register::charwise("bar\nbaz")).unwrap();
+        assert_eq!(
+            registers.get('a', ""),
+            Some(Register::charwise("foobar\nbaz"))
+        );
+
+        registers.yank('A', lines(&["line"])).unwrap();
+        assert_eq!(
+            registers.get(UNNAMED, ""),
+            Some(lines(&["foobar", "baz", "line"]))
+        );
+
+        registers.delete(BLACK_HOLE, lines(&["gone"])).unwrap();
+        assert_eq!(registers.get('1', ""), None);
+        assert_eq!(registers.get(UNNAMED, "").unwrap().lines.len(), 3);
+    }
+
+    #[test]
+    fn text_round_trips_with_its_kind() {
+        assert_eq!(lines(&["a", "b"]).to_text(), "a\nb\n");
+        assert_eq!(Register::from_text("a\nb\n"), lines(&["a", "b"]));
+        assert_eq!(Register::from_text("a\nb"), Register::charwise("a\nb"));
+    }
+
+    #[test]
+    fn read_only_registers() {
+        let mut registers = Registers::new();
+        registers.typed("hi");
+        registers.untyped();
+        registers.finish_insert();
+        registers.set_last_command(String::from("w"));
+
+        assert_eq!(registers.get('.', ""), Some(Register::charwise("h")));
+        assert_eq!(registers.get(':', ""), Some(Register::charwise("w")));
+        assert_eq!(registers.get('%', "a.rs"), Some(Register::charwise("a.rs")));
+        assert!(registers.yank('%', Register::charwise("x")).is_err());
+    }
+}
@@ -2,16 +2,20 @@ use crate::{
     app::Context,
     cursor::{CursorStyle, Position},
     logger::Logger,
+    search,
+    state::Panel,
+    substitute::Session,
+    visual::{Selection, SelectionKind},
 };
 use anyhow::Context as AnyhowContext;
 use crossterm::{
     cursor::{self, SetCursorStyle},
-    event::EnableMouseCapture,
+    event::{EnableFocusChange, EnableMouseCapture},
     execute, queue,
     style::{self, Print, SetBackgroundColor, SetForegroundColor},
     terminal::{self, Clear, ClearType},
 };
-use std::io::Write;
+use std::{io::Write, ops::Range};
 
 pub struct WindowSize {
     pub width: usize,
@@ -26,7 +30,35 @@ pub struct Renderer<W: Write> {
     last_viewport_offset: usize,
 }
 
+/// Tokyo Night background.
+const BG_COLOR: style::Color = style::Color::Rgb {
+    r: 0x1a,
+    g: 0x1b,
+    b: 0x26,
+};
+/// Background of search matches.
+const SEARCH_BG_COLOR: style::Color = style::Color::Rgb {
+    r: 0x3d,
+    g: 0x59,
+    b: 0xa1,
+};
+/// Background of the text selected in visual mode.
+const VISUAL_BG_COLOR: style::Color = style::Color::Rgb {
+    r: 0x28,
+    g: 0x34,
+    b: 0x57,
+};
+
+/// Background of the match the cursor jumped to while typing a search, and of
+/// the one `:s///c` asks about.
+const INCSEARCH_BG_COLOR: style::Color = style::Color::Rgb {
+    r: 0xff,
+    g: 0x9e,
+    b: 0x64,
+};
+
 pub const STATUS_BAR_HEIGHT: usize = 2usize;
+pub const UNDO_TREE_WIDTH: usize = 32usize;
 impl<W: Write> Renderer<W> {
     pub fn new(writer: W, file_name: &str) -> Self {
         let (width, height) = crossterm::terminal::size()
@@ -59,7 +91,7 @@ impl<W: Write> Renderer<W> {
             .with_context(|| format!("Error While Enabling Raw Mode"))
             .unwrap();
         Logger::log(format!("Renderer Init 2"));
-        execute!(self.writer, EnableMouseCapture)
+        execute!(self.writer, EnableMouseCapture, EnableFocusChange)
             .with_context(|| format!("Error while Enabling Mouse Capture"))
             .unwrap();
         Logger::log(format!("Renderer Init 3"));
@@ -70,7 +102,7 @@ impl<W: Write> Renderer<W> {
 
         self.set_bg_color();
         Logger::log(format!("Renderer Init 5"));
-        self.line_num_width = (context.buffer.len() - 1).ilog10() as usize + 1;
+        self.line_num_width = (context.buffer.len() - 1).checked_ilog10().unwrap_or(0) as usize + 1;
 
         self.render(context);
         Logger::log(format!("Renderer Init 6"));
@@ -82,10 +114,16 @@ impl<W: Write> Renderer<W> {
             .with_context(|| format!("Error While Hiding Cursor"))
             .unwrap();
 
+        // Switching buffers can change the width of the line numbers.
+        let line_num_width = (context.buffer.len() - 1).checked_ilog10().unwrap_or(0) as usize + 1;
+        let redraw = line_num_width != self.line_num_width;
+        self.line_num_width = line_num_width;
+
         let scroll_delta = context.viewport.offset as i32 - self.last_viewport_offset as i32;
 
         Logger::log(format!("scroll : {scroll_delta}"));
-        if scroll_delta == 0 {
+        // A side panel does not move with the text, so it can't be scrolled.
+        if scroll_delta == 0 || redraw || !matches!(context.app_state.panel(), Panel::None) {
             self.draw_lines(context);
         } else if scroll_delta > 0 && scroll_delta < context.viewport.height as i32 {
             Logger::log(format!("Scroll Up"));
@@ -124,16 +162,19 @@ impl<W: Write> Renderer<W> {
             .unwrap();
     }
 
-    fn set_bg_color(&mut self) {
-        let tokyonight_bg = style::Color::Rgb {
-            r: 0x1a,
-            g: 0x1b,
-            b: 0x26,
-        };
+    /// Writes an escape sequence that draws nothing, e.g. OSC 52.
+    pub fn emit(&mut self, sequence: &str) {
+        self.writer
+            .write_all(sequence.as_bytes())
+            .and_then(|_| self.writer.flush())
+            .with_context(|| format!("Error While Writing Escape Sequence"))
+            .unwrap();
+    }
 
+    fn set_bg_color(&mut self) {
         execute!(
             self.writer,
-            SetBackgroundColor(tokyonight_bg),
+            SetBackgroundColor(BG_COLOR),
             Clear(ClearType::All)
         )
         .with_context(|| format!("Error While Setting BG Color"))
@@ -151,6 +192,18 @@ impl<W: Write> Renderer<W> {
             .unwrap();
 
         Logger::log(format!("Draw Lines Range 2"));
+        let (panel_lines, panel_width) = match context.app_state.panel() {
+            Panel::None => (Vec::new(), 0),
+            Panel::UndoTree => (context.buffer.undo_tree(), UNDO_TREE_WIDTH),
+            Panel::Buffers => (context.buffer_lines(), self.win_size.width / 2),
+            Panel::Registers => (
+                context.app_state.registers().lines(context.file_name),
+                self.win_size.width / 2,
+            ),
+            Panel::Text(lines) => (lines.clone(), self.win_size.width / 2),
+        };
+        let panel_col = self.win_size.width.saturating_sub(panel_width);
+
         for screen_row in screen_start..screen_end {
             let buffer_line = context.viewport.offset + screen_row;
             let mut line = format!(
@@ -159,16 +212,85 @@ impl<W: Write> Renderer<W> {
                 width = self.line_num_width
             );
 
+            let mut highlights = Vec::new();
             if buffer_line < context.buffer.len() {
-                line = line + " " + context.buffer.get(buffer_line);
+                let text = context.buffer.get(buffer_line);
+                let search = context.app_state.search();
+                if let Some(regex) = search.highlighted() {
+                    let start = line.len() + 1;
+                    let cursor = context.cursor.pos();
+                    highlights = search::matches_in(regex, &text)
+                        .into_iter()
+                        .map(|range| {
+                            let current = search.is_previewing()
+                                && cursor.row == buffer_line
+                                && cursor.col == range.start;
+                            let color = if current {
+                                INCSEARCH_BG_COLOR
+                            } else {
+                                SEARCH_BG_COLOR
+                            };
+                            (range.start + start..range.end + start, color)
+                        })
+                        .collect();
+                }
+                let start = line.len() + 1;
+                let selection = Selection::new(
+                    context.app_state.mode(),
+                    context.app_state.visual_anchor(),
+                    context.cursor.pos(),
+                );
+                if let Some(selection) = selection
+                    && selection.rows().contains(&buffer_line)
+                {
+                    let cols = selection.cols(buffer_line, &text);
+                    // Selected line breaks and empty lines show as a selected space.
+                    let shows_space = selection.takes_line_break(buffer_line, &text)
+                        || (selection.kind == SelectionKind::Line && text.is_empty());
+                    let end = cols.end + usize::from(shows_space);
+                    overlay(
+                        &mut highlights,
+                        cols.start + start..end + start,
+                        VISUAL_BG_COLOR,
+                    );
+                }
+                // The match `:s///c` is asking about.
+                if let Some((row, cols)) =
+                    context.app_state.substitution().and_then(Session::current)
+                    && row == buffer_line
+                {
+                    overlay(
+                        &mut highlights,
+                        cols.start + start..cols.end + start,
+                        INCSEARCH_BG_COLOR,
+                    );
+                }
+                line = line + " " + &text;
+                // Room for a selected line break or an empty selected line.
+                if highlights
+                    .last()
+                    .is_some_and(|(range, _)| range.end > line.len())
+                {
+                    line.push(' ');
+                }
             }
 
-            queue!(
-                self.writer,
-                Clear(terminal::ClearType::CurrentLine),
-                Print(&line)
-            )
-            .with_context(|| {
+            // Highlights stop where the panel starts.
+            let mut text_end = line.len();
+            if panel_width > 0 {
+                let panel_line = panel_lines.get(screen_row).map_or("", |l| l.as_str());
+                let text: String = line.chars().take(panel_col).collect();
+                text_end = text.len();
+                line = format!(
+                    "{text:<width$}│{panel}",
+                    width = panel_col,
+                    panel = panel_line.chars().take(panel_width - 1).collect::<String>()
+                );
+            }
+
+            queue!(self.writer, Clear(terminal::ClearType::CurrentLine))
+                .and_then(|_| self.print_highlighted(&line, &highlights, text_end))
+                .with_context(|| {
                 format!(
                     "Error While Printing Line. screen_row:{}, buffer_row:{}, line:{}, Context:{}",
                     screen_row, buffer_line, &line, context
@@ -193,16 +315,66 @@ impl<W: Write> Renderer<W> {
         Logger::log(format!("Draw Lines Range 3"));
     }
 
+    /// Prints `line` with the byte `ranges` that start before `end` on their
+    /// background colors.
+    fn print_highlighted(
+        &mut self,
+        line: &str,
+        ranges: &[(Range<usize>, style::Color)],
+        end: usize,
+    ) -> std::io::Result<()> {
+        let mut printed = 0;
+        for (range, color) in ranges.iter().filter(|(range, _)| range.start < end) {
+            let range_end = range.end.min(end);
+            queue!(
+                self.writer,
+                Print(&line[printed..range.start]),
+                SetBackgroundColor(*color),
+                Print(&line[range.start..range_end]),
+                SetBackgroundColor(BG_COLOR),
+            )?;
+            printed = range_end;
+        }
+
+        queue!(self.writer, Print(&line[printed..]))
+    }
+
     fn draw_status_bar(&mut self, context: &Context) {
+        let cmd_line = if let Some(prompt) = context.app_state.prompt() {
+            prompt.text.as_str()
+        } else if context.cmd_buffer.is_empty() {
+            context.app_state.message()
+        } else {
+            context.cmd_buffer.as_str()
+        };
+
+        let mut flags = String::new();
+        if context.buffer.is_new_file() {
+            flags.push_str(" [New]");
+        }
+        if context.buffer.is_read_only() {
+            flags.push_str(" [RO]");
+        }
+        if context.buffer.is_modified() {
+            flags.push_str(" [+]");
+        }
+
         queue!(
             self.writer,
             cursor::MoveTo(0, (self.win_size.height - STATUS_BAR_HEIGHT) as u16),
             Clear(ClearType::CurrentLine),
-            Print(format!("mode: {:?}", context.app_state.mode())),
+            Print(format!(
+                "mode: {:?}    {}{}    [{}] {}",
+                context.app_state.mode(),
+                context.file_name,
+                flags,
+                context.buffer.line_ending().name(),
+                context.buffer.encoding_name()
+            )),
             cursor::MoveDown(1),
             cursor::MoveToColumn(0),
             Clear(ClearType::CurrentLine),
-            Print(context.cmd_buffer),
+            Print(cmd_line),
         )
         .with_context(|| format!("Error While Drawing Status Bar"))
         .unwrap();
@@ -229,3 +401,14 @@ impl<W: Write> Renderer<W> {
         .unwrap();
     }
 }
+
+/// Adds `range` on `color` to `highlights`, in place of the ones it overlaps.
+fn overlay(
+    highlights: &mut Vec<(Range<usize>, style::Color)>,
+    range: Range<usize>,
+    color: style::Color,
+) {
+    highlights.retain(|(other, _)| other.end <= range.start || other.start >= range.end);
+    highlights.push((range, color));
+    highlights.sort_by_key(|(range, _)| range.start);
+}
@@ -0,0 +1,283 @@
+use crate::{buffer::Buffer, cursor::Position, motion::WordChars};
+use anyhow::{Context as AnyhowContext, Result};
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+
+/// The last search pattern, which `n`/`N` repeat and the renderer highlights.
+/// Patterns use Rust regex syntax and match within single lines.
+pub struct Search {
+    pattern: String,
+    regex: Option<Regex>,
+    backward: bool,
+    /// Matches are highlighted until `:nohlsearch`.
+    highlight: bool,
+    /// Set while a pattern is being typed.
+    preview: Option<Preview>,
+}
+
+/// Incremental search state: where `/` or `?` was typed, to search from and
+/// to go back to on Esc, and the pattern typed so far if it is valid.
+struct Preview {
+    cursor: Position,
+    viewport_offset: usize,
+    regex: Option<Regex>,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Self {
+            pattern: String::new(),
+            regex: None,
+            backward: false,
+            highlight: false,
+            preview: None,
+        }
+    }
+
+    /// Makes `pattern` the one searched for, case-insensitively if
+    /// `ignore_case`.
+    pub fn set(&mut self, pattern: &str, backward: bool, ignore_case: bool) -> Result<()> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .with_context(|| format!("Invalid pattern: {pattern}"))?;
+
+        self.pattern = pattern.to_string();
+        self.regex = Some(regex);
+        self.backward = backward;
+        self.highlight = true;
+        Ok(())
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_backward(&self) -> bool {
+        self.backward
+    }
+
+    pub fn regex(&self) -> Option<&Regex> {
+        self.regex.as_ref()
+    }
+
+    /// The pattern to highlight: the one being typed, or else the last one
+    /// unless turned off by `:nohlsearch`.
+    pub fn highlighted(&self) -> Option<&Regex> {
+        match &self.preview {
+            Some(preview) => preview.regex.as_ref(),
+            None => self.regex.as_ref().filter(|_| self.highlight),
+        }
+    }
+
+    pub fn is_previewing(&self) -> bool {
+        self.preview.is_some()
+    }
+
+    /// Starts previewing matches as a pattern is typed from `cursor`.
+    pub fn begin_preview(&mut self, cursor: Position, viewport_offset: usize) {
+        self.preview = Some(Preview {
+            cursor,
+            viewport_offset,
+            regex: None,
+        });
+    }
+
+    /// Previews `pattern`. Patterns that don't compile yet, e.g. with an
+    /// unclosed group, show no matches.
+    pub fn set_preview(&mut self, pattern: &str, ignore_case: bool) {
+        if let Some(preview) = self.preview.as_mut() {
+            preview.regex = (!pattern.is_empty())
+                .then(|| {
+                    RegexBuilder::new(pattern)
+                        .case_insensitive(ignore_case)
+                        .build()
+                        .ok()
+                })
+                .flatten();
+        }
+    }
+
+    /// The cursor and viewport offset the preview started from.
+    pub fn preview_origin(&self) -> Option<(Position, usize)> {
+        self.preview
+            .as_ref()
+            .map(|preview| (preview.cursor, preview.viewport_offset))
+    }
+
+    /// Closest match of the previewed pattern from where it started.
+    pub fn find_preview(&self, buffer: &Buffer, backward: bool) -> Option<Position> {
+        let preview = self.preview.as_ref()?;
+        let regex = preview.regex.as_ref()?;
+        find(
+            regex,
+            buffer.len(),
+            |row| buffer.get(row),
+            preview.cursor,
+            backward,
+        )
+        .map(|(pos, _)| pos)
+    }
+
+    /// Stops previewing and returns where it started.
+    pub fn end_preview(&mut self) -> Option<(Position, usize)> {
+        let origin = self.preview_origin();
+        self.preview = None;
+        origin
+    }
+
+    pub fn set_highlight(&mut self, highlight: bool) {
+        self.highlight = highlight;
+    }
+
+    /// Start of the closest match after `from`, or before it if `backward`,
+    /// wrapping around the end of the buffer. The flag tells if it wrapped.
+    pub fn find(
+        &self,
+        buffer: &Buffer,
+        from: Position,
+        backward: bool,
+    ) -> Option<(Position, bool)> {
+        let regex = self.regex.as_ref()?;
+        find(regex, buffer.len(), |row| buffer.get(row), from, backward)
+    }
+}
+
+/// Whether to ignore case for `pattern`: with `smartcase`, a pattern with an
+/// upper case letter is matched exactly.
+pub fn ignores_case(pattern: &str, ignorecase: bool, smartcase: bool) -> bool {
+    ignorecase && !(smartcase && pattern.chars().any(char::is_uppercase))
+}
+
+/// Pattern matching `word` as a whole word, for `*` and `#`.
+pub fn word_pattern(word: &str) -> String {
+    format!(r"\b{}\b", regex::escape(word))
+}
+
+/// Byte range of the keyword under or after `col` in `line`, as `*` picks it.
+pub fn word_at(line: &str, col: usize, words: WordChars) -> Option<Range<usize>> {
+    let is_word = |ch: char| words.contains(ch);
+    let col = col.min(line.len());
+    let start = if line[col..].starts_with(is_word) {
+        line[..col]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, ch)| is_word(ch))
+            .last()
+            .map_or(col, |(i, _)| i)
+    } else {
+        col
+    };
+    let start = start + line[start..].find(is_word)?;
+    let end = line[start..]
+        .find(|ch: char| !is_word(ch))
+        .map_or(line.len(), |len| start + len);
+    Some(start..end)
+}
+
+/// Byte ranges of the matches of `regex` in `line`.
+pub fn matches_in(regex: &Regex, line: &str) -> Vec<Range<usize>> {
+    regex
+        .find_iter(line)
+        .filter(|m| !m.is_empty())
+        .map(|m| m.range())
+        .collect()
+}
+
+/// Start of the closest match of `regex` after `from`, or before it if
+/// `backward`, in the `len` lines given by `line`. Wraps around the end of the
+/// text; the flag tells if it did.
+pub fn find(
+    regex: &Regex,
+    len: usize,
+    line: impl Fn(usize) -> String,
+    from: Position,
+    backward: bool,
+) -> Option<(Position, bool)> {
+    let starts =
+        |row: usize| -> Vec<usize> { regex.find_iter(&line(row)).map(|m| m.start()).collect() };
+
+    // Every row once from the cursor's, then the cursor's row again for the
+    // matches on the other side of the cursor.
+    for i in 0..=len {
+        let (row, wrapped) = if backward {
+            ((from.row + len - i % len) % len, i > from.row)
+        } else {
+            ((from.row + i) % len, from.row + i >= len)
+        };
+        let mut cols = starts(row).into_iter();
+        let col = if backward {
+            cols.rev().find(|&col| i > 0 || col < from.col)
+        } else {
+            cols.find(|&col| i > 0 || col > from.col)
+        };
+        if let Some(col) = col {
+            return Some((Position { row, col }, wrapped));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_matches_and_wraps_around() {
+        let lines = ["foo bar", "baz", "bar foo"];
+        let at = |row, col| Position { row, col };
+        let find = |pattern, from, backward| {
+            let regex = Regex::new(pattern).unwrap();
+            find(
+                &regex,
+                lines.len(),
+                |row| lines[row].to_string(),
+                from,
+                backward,
+            )
+        };
+
+        assert_eq!(find("ba", at(0, 0), false), Some((at(0, 4), false)));
+        assert_eq!(find("ba", at(0, 4), false), Some((at(1, 0), false)));
+        assert_eq!(find("ba", at(2, 0), false), Some((at(0, 4), true)));
+        assert_eq!(find("ba", at(1, 0), true), Some((at(0, 4), false)));
+        assert_eq!(find("ba", at(0, 4), true), Some((at(2, 0), true)));
+        assert_eq!(find("baz", at(1, 0), false), Some((at(1, 0), true)));
+        assert_eq!(find("nope", at(1, 0), false), None);
+    }
+
+    #[test]
+    fn preview_replaces_highlight_until_it_ends() {
+        let mut search = Search::new();
+        search.set("old", false, false).unwrap();
+        let origin = Position { row: 3, col: 1 };
+        search.begin_preview(origin, 2);
+
+        search.set_preview("new(", false);
+        assert!(search.highlighted().is_none());
+        search.set_preview("new", false);
+        assert_eq!(search.highlighted().map(Regex::as_str), Some("new"));
+
+        assert_eq!(search.end_preview(), Some((origin, 2)));
+        assert_eq!(search.highlighted().map(Regex::as_str), Some("old"));
+    }
+
+    #[test]
+    fn smartcase_and_words() {
+        assert!(ignores_case("foo", true, true));
+        assert!(!ignores_case("Foo", true, true));
+        assert!(ignores_case("Foo", true, false));
+        assert!(!ignores_case("foo", false, true));
+
+        let words = WordChars::for_file("main.rs");
+        assert_eq!(word_at("let x_1 = y;", 5, words), Some(4..7));
+        assert_eq!(word_at("let x_1 = y;", 7, words), Some(10..11));
+        assert_eq!(word_at("a = ", 2, words), None);
+        let css = WordChars::for_file("style.css");
+        assert_eq!(word_at("a { font-size: 1em }", 6, css), Some(4..13));
+
+        let regex = Regex::new(&word_pattern("x.y")).unwrap();
+        assert_eq!(matches_in(&regex, "x.y xzy x.yz x.y"), vec![0..3, 13..16]);
+    }
+}
@@ -1,7 +1,34 @@
+use crate::{
+    clipboard::Clipboard,
+    cursor::Position,
+    input_handler::Command,
+    registers::Registers,
+    search::Search,
+    substitute::{Session, Substitute},
+    visual::BlockInsert,
+};
+
 pub struct State {
     mode: Mode,
     should_render: bool,
     should_terminate: bool,
+    message: String,
+    panel: Panel,
+    prompt: Option<Prompt>,
+    options: Options,
+    registers: Registers,
+    clipboard: Clipboard,
+    search: Search,
+    /// The last `:s`, which `:&` repeats.
+    last_substitute: Option<Substitute>,
+    /// `:s///c` waiting for an answer about its current match.
+    substitution: Option<Session>,
+    /// Where the visual selection started.
+    visual_anchor: Position,
+    /// Block `I` or `A` waiting for insert mode to end.
+    block_insert: Option<BlockInsert>,
+    /// Escape sequences to write to the terminal after the next render.
+    terminal_output: Vec<String>,
 }
 
 impl State {
@@ -10,6 +37,18 @@ impl State {
             mode: Mode::Normal,
             should_render: true,
             should_terminate: false,
+            message: String::new(),
+            panel: Panel::None,
+            prompt: None,
+            options: Options::new(),
+            registers: Registers::new(),
+            clipboard: Clipboard::detect(),
+            search: Search::new(),
+            last_substitute: None,
+            substitution: None,
+            visual_anchor: Position { row: 0, col: 0 },
+            block_insert: None,
+            terminal_output: Vec::new(),
         }
     }
 
@@ -36,11 +75,197 @@ impl State {
     pub fn terminate_app(&mut self) {
         self.should_terminate = true;
     }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn set_message(&mut self, message: String) {
+        self.message = message;
+    }
+
+    pub fn clear_message(&mut self) {
+        self.message.clear();
+    }
+
+    pub fn panel(&self) -> &Panel {
+        &self.panel
+    }
+
+    pub fn set_panel(&mut self, panel: Panel) {
+        self.panel = panel;
+    }
+
+    pub fn toggle_undo_tree(&mut self) {
+        self.panel = match self.panel {
+            Panel::UndoTree => Panel::None,
+            _ => Panel::UndoTree,
+        };
+    }
+
+    pub fn toggle_buffer_list(&mut self) {
+        self.panel = match self.panel {
+            Panel::Buffers => Panel::None,
+            _ => Panel::Buffers,
+        };
+    }
+
+    pub fn toggle_registers(&mut self) {
+        self.panel = match self.panel {
+            Panel::Registers => Panel::None,
+            _ => Panel::Registers,
+        };
+    }
+
+    pub fn prompt(&self) -> Option<&Prompt> {
+        self.prompt.as_ref()
+    }
+
+    /// Shows `prompt` and waits for one of its keys in `Mode::Prompt`.
+    pub fn set_prompt(&mut self, prompt: Prompt) {
+        self.prompt = Some(prompt);
+        self.mode = Mode::Prompt;
+    }
+
+    pub fn take_prompt(&mut self) -> Option<Prompt> {
+        self.prompt.take()
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn clipboard(&self) -> &Clipboard {
+        &self.clipboard
+    }
+
+    pub fn clipboard_mut(&mut self) -> &mut Clipboard {
+        &mut self.clipboard
+    }
+
+    pub fn search(&self) -> &Search {
+        &self.search
+    }
+
+    pub fn search_mut(&mut self) -> &mut Search {
+        &mut self.search
+    }
+
+    pub fn last_substitute(&self) -> Option<&Substitute> {
+        self.last_substitute.as_ref()
+    }
+
+    pub fn set_last_substitute(&mut self, substitute: Substitute) {
+        self.last_substitute = Some(substitute);
+    }
+
+    pub fn substitution(&self) -> Option<&Session> {
+        self.substitution.as_ref()
+    }
+
+    pub fn set_substitution(&mut self, session: Session) {
+        self.substitution = Some(session);
+    }
+
+    pub fn take_substitution(&mut self) -> Option<Session> {
+        self.substitution.take()
+    }
+
+    pub fn visual_anchor(&self) -> Position {
+        self.visual_anchor
+    }
+
+    pub fn set_visual_anchor(&mut self, anchor: Position) {
+        self.visual_anchor = anchor;
+    }
+
+    pub fn set_block_insert(&mut self, insert: BlockInsert) {
+        self.block_insert = Some(insert);
+    }
+
+    pub fn take_block_insert(&mut self) -> Option<BlockInsert> {
+        self.block_insert.take()
+    }
+
+    /// Queues an escape sequence that draws nothing, e.g. OSC 52.
+    pub fn send_to_terminal(&mut self, sequence: String) {
+        self.terminal_output.push(sequence);
+    }
+
+    pub fn take_terminal_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.terminal_output)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mode {
     Normal,
     Cmd,
     Edit,
+    Prompt,
+    /// Typing a `/` or `?` search pattern.
+    Search,
+    /// Selecting characters (`v`), whole lines (`V`) or a block (`Ctrl-V`).
+    Visual,
+    VisualLine,
+    VisualBlock,
+}
+
+impl Mode {
+    pub fn is_visual(self) -> bool {
+        matches!(self, Mode::Visual | Mode::VisualLine | Mode::VisualBlock)
+    }
+}
+
+/// Side panel shown to the right of the text.
+pub enum Panel {
+    None,
+    UndoTree,
+    /// Open buffers, as listed by `:ls`.
+    Buffers,
+    /// Register contents, as listed by `:registers`.
+    Registers,
+    Text(Vec<String>),
+}
+
+/// A question answered with a single key, e.g. whether to recover a swap file.
+pub struct Prompt {
+    pub text: String,
+    pub choices: Vec<(char, Command)>,
+}
+
+/// Editor-wide settings changed through `:set`.
+pub struct Options {
+    /// Keep the previous contents of a file in `file~` when saving.
+    pub backup: bool,
+    /// Yank, delete and put with the system clipboard instead of `""`
+    /// (`clipboard=unnamedplus`).
+    pub unnamedplus: bool,
+    /// Searches ignore case...
+    pub ignorecase: bool,
+    /// ...unless the pattern has an upper case letter.
+    pub smartcase: bool,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self {
+            backup: false,
+            unnamedplus: false,
+            ignorecase: true,
+            smartcase: true,
+        }
+    }
 }
@@ -0,0 +1,345 @@
+use crate::{buffer::Buffer, ex};
+use anyhow::{Context as AnyhowContext, Result, bail};
+use regex::{Captures, Regex, RegexBuilder};
+use std::{collections::VecDeque, ops::Range};
+
+/// Flags after `:s/pat/rep/`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Flags {
+    /// `g`: every match in a line, not just the first.
+    pub global: bool,
+    /// `c`: ask before each substitution.
+    pub confirm: bool,
+    /// `i` / `I`: ignore or match case regardless of `ignorecase`.
+    pub ignore_case: Option<bool>,
+    /// `n`: only count the matches.
+    pub count_only: bool,
+    /// `e`: no error when nothing matches.
+    pub no_error: bool,
+}
+
+impl Flags {
+    /// Reads flags such as `gc`. A leading `&` keeps the flags of `previous`.
+    pub fn parse(text: &str, previous: Flags) -> Result<Self> {
+        let (mut flags, text) = match text.strip_prefix('&') {
+            Some(text) => (previous, text),
+            None => (Flags::default(), text),
+        };
+
+        for ch in text.trim().chars() {
+            match ch {
+                'g' => flags.global = !flags.global,
+                'c' => flags.confirm = true,
+                'i' => flags.ignore_case = Some(true),
+                'I' => flags.ignore_case = Some(false),
+                'n' => flags.count_only = true,
+                'e' => flags.no_error = true,
+                _ => bail!("Invalid flag: {ch}"),
+            }
+        }
+
+        Ok(flags)
+    }
+}
+
+/// A parsed `:s/pattern/replacement/flags`.
+#[derive(Debug, Clone)]
+pub struct Substitute {
+    pub pattern: String,
+    pub replacement: String,
+    pub flags: Flags,
+    regex: Regex,
+}
+
+/// The parts of `/pattern/replacement/flags`. Trailing delimiters can be left
+/// out.
+pub fn split_args(args: &str) -> Result<(String, String, &str)> {
+    let (pattern, rest) = ex::split_pattern(args)?;
+    let delimiter = args.chars().next().unwrap_or('/');
+    let (replacement, flags) = ex::take_until(rest, delimiter);
+    Ok((pattern, replacement, flags))
+}
+
+impl Substitute {
+    pub fn new(pattern: &str, replacement: &str, flags: Flags, ignore_case: bool) -> Result<Self> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(flags.ignore_case.unwrap_or(ignore_case))
+            .build()
+            .with_context(|| format!("Invalid pattern: {pattern}"))?;
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            flags,
+            regex,
+        })
+    }
+
+    /// The same substitution with other flags, for `:&`.
+    pub fn with_flags(&self, flags: Flags, ignore_case: bool) -> Result<Self> {
+        Self::new(&self.pattern, &self.replacement, flags, ignore_case)
+    }
+
+    /// Matches to replace in `line`, with their replacement text.
+    fn replacements(&self, line: &str) -> Vec<(Range<usize>, String)> {
+        let limit = if self.flags.global { usize::MAX } else { 1 };
+        self.regex
+            .captures_iter(line)
+            .take(limit)
+            .map(|caps| {
+                (
+                    caps.get(0).unwrap().range(),
+                    expand(&self.replacement, &caps),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Builds the replacement for one match: `&` and `\0` are the match, `\1`-`\9`
+/// its groups, `\r` and `\n` a line break, `\t` a tab. `\u` / `\l` change the
+/// case of the next character, `\U` / `\L` of everything up to `\E` or `\e`.
+pub fn expand(replacement: &str, caps: &Captures) -> String {
+    let mut expanded = String::new();
+    let (mut next_case, mut case) = (Case::Keep, Case::Keep);
+    let group = |n: usize| caps.get(n).map_or("", |m| m.as_str());
+
+    let mut chars = replacement.chars();
+    while let Some(ch) = chars.next() {
+        let mut buf = [0; 4];
+        let text = match ch {
+            '&' => group(0),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => group(digit as usize - '0' as usize),
+                Some('r' | 'n') => "\n",
+                Some('t') => "\t",
+                Some('u') => {
+                    next_case = Case::Upper;
+                    continue;
+                }
+                Some('l') => {
+                    next_case = Case::Lower;
+                    continue;
+                }
+                Some('U') => {
+                    case = Case::Upper;
+                    continue;
+                }
+                Some('L') => {
+                    case = Case::Lower;
+                    continue;
+                }
+                Some('E' | 'e') => {
+                    case = Case::Keep;
+                    continue;
+                }
+                Some(other) => other.encode_utf8(&mut buf),
+                None => "\\",
+            },
+            ch => ch.encode_utf8(&mut buf),
+        };
+
+        for ch in text.chars() {
+            match std::mem::replace(&mut next_case, Case::Keep) {
+                Case::Keep => case.push(&mut expanded, ch),
+                next => next.push(&mut expanded, ch),
+            }
+        }
+    }
+
+    expanded
+}
+
+/// Case change of `\u`, `\l`, `\U` and `\L`.
+#[derive(Clone, Copy)]
+enum Case {
+    Keep,
+    Upper,
+    Lower,
+}
+
+impl Case {
+    fn push(self, text: &mut String, ch: char) {
+        match self {
+            Case::Keep => text.push(ch),
+            Case::Upper => text.extend(ch.to_uppercase()),
+            Case::Lower => text.extend(ch.to_lowercase()),
+        }
+    }
+}
+
+/// A substitution being applied to a range of lines, one match at a time so
+/// that each can be confirmed. Matches are found in the original text of a
+/// line, so replacements are never matched again.
+pub struct Session {
+    substitute: Substitute,
+    /// Next row to look for matches in, and the last row of the range. Both
+    /// move down as replacements add line breaks.
+    next_row: usize,
+    last_row: usize,
+    /// Matches left in the current line, in its original byte offsets.
+    pending: VecDeque<(Range<usize>, String)>,
+    /// Where the current line's original offset `anchor` now is in the buffer.
+    row: usize,
+    anchor: usize,
+    anchor_col: usize,
+    substitutions: usize,
+    lines: usize,
+    line_changed: bool,
+    last_changed_row: Option<usize>,
+}
+
+impl Session {
+    pub fn new(substitute: Substitute, first_row: usize, last_row: usize) -> Self {
+        Self {
+            substitute,
+            next_row: first_row,
+            last_row,
+            pending: VecDeque::new(),
+            row: first_row,
+            anchor: 0,
+            anchor_col: 0,
+            substitutions: 0,
+            lines: 0,
+            line_changed: false,
+            last_changed_row: None,
+        }
+    }
+
+    pub fn substitute(&self) -> &Substitute {
+        &self.substitute
+    }
+
+    /// Moves on to the next match, if there is one left in the range.
+    pub fn advance(&mut self, buffer: &Buffer) -> bool {
+        while self.pending.is_empty() {
+            if self.next_row > self.last_row {
+                return false;
+            }
+
+            self.row = self.next_row;
+            self.next_row += 1;
+            self.pending = self.substitute.replacements(&buffer.get(self.row)).into();
+            (self.anchor, self.anchor_col, self.line_changed) = (0, 0, false);
+        }
+        true
+    }
+
+    /// Row and byte columns of the match `advance` moved to.
+    pub fn current(&self) -> Option<(usize, Range<usize>)> {
+        let (range, _) = self.pending.front()?;
+        let col = |offset: usize| self.anchor_col + offset - self.anchor;
+        Some((self.row, col(range.start)..col(range.end)))
+    }
+
+    /// Replaces the current match, or only counts it with the `n` flag.
+    pub fn replace(&mut self, buffer: &mut Buffer) {
+        let Some((row, cols)) = self.current() else {
+            return;
+        };
+        let Some((range, text)) = self.pending.pop_front() else {
+            return;
+        };
+
+        self.substitutions += 1;
+        if !self.line_changed {
+            self.lines += 1;
+            self.line_changed = true;
+        }
+        if self.substitute.flags.count_only {
+            return;
+        }
+
+        buffer.remove_string(row, cols.start, cols.len());
+        buffer.insert_string(row, cols.start, &text);
+        self.anchor = range.end;
+        match text.rfind('\n') {
+            Some(last_break) => {
+                let breaks = text.matches('\n').count();
+                self.row += breaks;
+                self.next_row += breaks;
+                self.last_row += breaks;
+                self.anchor_col = text.len() - last_break - 1;
+            }
+            None => self.anchor_col = cols.start + text.len(),
+        }
+        self.last_changed_row = Some(self.row);
+    }
+
+    /// Leaves the current match as it is.
+    pub fn skip(&mut self) {
+        self.pending.pop_front();
+    }
+
+    /// Replaces the current match and all after it.
+    pub fn replace_all(&mut self, buffer: &mut Buffer) {
+        while self.advance(buffer) {
+            self.replace(buffer);
+        }
+    }
+
+    pub fn substitutions(&self) -> usize {
+        self.substitutions
+    }
+
+    /// Last row something was replaced on.
+    pub fn last_changed_row(&self) -> Option<usize> {
+        self.last_changed_row
+    }
+
+    /// E.g. "12 substitutions on 5 lines".
+    pub fn summary(&self) -> String {
+        let plural =
+            |n: usize, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+        let what = if self.substitute.flags.count_only {
+            plural(self.substitutions, "match", "matches")
+        } else {
+            plural(self.substitutions, "substitution", "substitutions")
+        };
+        format!("{what} on {}", plural(self.lines, "line", "lines"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_with(pattern: &str, replacement: &str, text: &str) -> String {
+        let caps = Regex::new(pattern).unwrap().captures(text).unwrap();
+        expand(replacement, &caps)
+    }
+
+    #[test]
+    fn expands_groups_and_case() {
+        assert_eq!(
+            expand_with(r"(\w+) (\w+)", r"\2 \1", "hello world"),
+            "world hello"
+        );
+        assert_eq!(expand_with(r"\w+", r"<&> \& \\", "hi"), r"<hi> & \");
+        assert_eq!(expand_with(r"(\w+)", r"\u\1", "word"), "Word");
+        assert_eq!(expand_with(r"(\w+) (\w+)", r"\U\1\E \2", "ab cd"), "AB cd");
+        assert_eq!(expand_with(r"(\w+)", r"\L\uxYZ", "x"), "Xyz");
+        assert_eq!(expand_with(r"a", r"1\r2", "a"), "1\n2");
+    }
+
+    #[test]
+    fn splits_arguments_and_flags() {
+        let (pattern, replacement, flags) = split_args(r"#a\#b#c/d#gc").unwrap();
+        assert_eq!(
+            (pattern.as_str(), replacement.as_str(), flags),
+            ("a#b", "c/d", "gc")
+        );
+        let (pattern, replacement, flags) = split_args(r"/x\d/").unwrap();
+        assert_eq!(
+            (pattern.as_str(), replacement.as_str(), flags),
+            (r"x\d", "", "")
+        );
+        assert!(split_args("axbx").is_err());
+
+        let previous = Flags::parse("gi", Flags::default()).unwrap();
+        assert!(previous.global && previous.ignore_case == Some(true));
+        let kept = Flags::parse("&c", previous).unwrap();
+        assert!(kept.global && kept.confirm);
+        assert!(Flags::parse("z", previous).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{file_io, history::Edit, logger::Logger};
+use anyhow::{Context as AnyhowContext, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Seek, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+const VERSION: u32 = 1;
+
+/// How often appended edits are forced to disk.
+const SYNC_INTERVAL: Duration = Duration::from_secs(4);
+
+/// First line of a swap file. Every following line is one journaled `Edit`.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    pid: u32,
+    /// Hash of the text the journal starts from.
+    base_hash: u64,
+}
+
+/// Edits left behind by an editor that exited without cleaning up.
+pub struct Journal {
+    pub pid: u32,
+    pub base_hash: u64,
+    pub edits: Vec<Edit>,
+}
+
+/// What was found at a file's swap path when opening it.
+pub enum Existing {
+    None,
+    Orphan(Journal),
+    InUse(u32),
+}
+
+/// Journal of the unsaved edits of one buffer, so they can be recovered after
+/// a crash. It is reset on every save and removed on a clean exit.
+pub struct SwapFile {
+    path: PathBuf,
+    file: File,
+    last_sync: Instant,
+}
+
+impl SwapFile {
+    pub fn create(file_path: &str, base_hash: u64) -> Result<Self> {
+        let path = swap_path(file_path)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create swap directory. {dir:?}"))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create swap file. {path:?}"))?;
+
+        let mut swap_file = Self {
+            path,
+            file,
+            last_sync: Instant::now(),
+        };
+        swap_file.reset(base_hash)?;
+
+        Ok(swap_file)
+    }
+
+    /// Starts a new, empty journal from the text hashed as `base_hash`.
+    pub fn reset(&mut self, base_hash: u64) -> Result<()> {
+        let header = Header {
+            version: VERSION,
+            pid: std::process::id(),
+            base_hash,
+        };
+
+        self.file.set_len(0)?;
+        // The file is opened without append, so rewind before rewriting.
+        self.file.rewind()?;
+        serde_json::to_writer(&mut self.file, &header)?;
+        self.file.write_all(b"\n")?;
+        self.file.sync_data()?;
+        self.last_sync = Instant::now();
+
+        Ok(())
+    }
+
+    pub fn append(&mut self, edits: &[Edit]) -> Result<()> {
+        let mut lines = Vec::new();
+        for edit in edits {
+            serde_json::to_writer(&mut lines, edit)?;
+            lines.push(b'\n');
+        }
+        self.file
+            .write_all(&lines)
+            .with_context(|| format!("Failed to write swap file. {:?}", self.path))?;
+
+        if self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.file.sync_data()?;
+            self.last_sync = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            Logger::log(format!("[swap_file] Failed to remove {:?}: {e}", self.path));
+        }
+    }
+}
+
+/// Looks for a swap file of `file_path` left by another editor process.
+pub fn inspect(file_path: &str) -> Existing {
+    let Ok(path) = swap_path(file_path) else {
+        return Existing::None;
+    };
+    let Ok(f) = File::open(&path) else {
+        return Existing::None;
+    };
+
+    let mut lines = BufReader::new(f).lines();
+    let header: Header = match lines.next().and_then(|line| line.ok()) {
+        Some(line) => match serde_json::from_str(&line) {
+            Ok(header) => header,
+            Err(_) => return Existing::None,
+        },
+        None => return Existing::None,
+    };
+
+    if header.version != VERSION {
+        return Existing::None;
+    }
+
+    if header.pid != std::process::id() && is_running(header.pid) {
+        return Existing::InUse(header.pid);
+    }
+
+    // A crash can cut the last line short; everything before it is still valid.
+    let edits: Vec<Edit> = lines
+        .map_while(|line| line.ok())
+        .map_while(|line| serde_json::from_str(&line).ok())
+        .collect();
+
+    if edits.is_empty() {
+        let _ = fs::remove_file(&path);
+        return Existing::None;
+    }
+
+    Existing::Orphan(Journal {
+        pid: header.pid,
+        base_hash: header.base_hash,
+        edits,
+    })
+}
+
+/// Deletes a swap file found by `inspect` without recovering it.
+pub fn discard(file_path: &str) {
+    if let Ok(path) = swap_path(file_path) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn is_running(pid: u32) -> bool {
+    if Path::new("/proc/self").exists() {
+        return Path::new("/proc").join(pid.to_string()).exists();
+    }
+
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn swap_path(file_path: &str) -> Result<PathBuf> {
+    let mut path = file_io::state_path("swap", file_path)?.into_os_string();
+    path.push(".swp");
+
+    Ok(PathBuf::from(path))
+}
@@ -0,0 +1,163 @@
+use std::{ops::Range, path::Path};
+
+/// How a language writes comments and strings, so that brackets in them can
+/// be told apart from those in the code.
+#[derive(Debug, Clone, Copy)]
+pub struct Syntax {
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    /// Characters that open and close a string.
+    quotes: &'static str,
+    /// Whether `'x'` is a character, while a `'` on its own is not a quote,
+    /// as in Rust lifetimes.
+    char_literals: bool,
+}
+
+impl Syntax {
+    /// The syntax of the language of `file_name`, or `None` if it isn't known.
+    pub fn for_file(file_name: &str) -> Option<Self> {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("");
+        let c_like = |quotes| Syntax {
+            line_comment: Some("//"),
+            block_comment: Some(("/*", "*/")),
+            quotes,
+            char_literals: quotes == "\"",
+        };
+        let hash = Syntax {
+            line_comment: Some("#"),
+            block_comment: None,
+            quotes: "\"'",
+            char_literals: false,
+        };
+
+        let syntax =
+            match extension {
+                "rs" | "c" | "h" | "cc" | "cpp" | "hpp" | "java" | "cs" | "swift" | "kt"
+                | "scala" => c_like("\""),
+                "go" => c_like("\"`"),
+                "js" | "mjs" | "cjs" | "jsx" | "ts" | "tsx" | "scss" | "less" => c_like("\"'`"),
+                "css" => Syntax {
+                    line_comment: None,
+                    ..c_like("\"'")
+                },
+                "py" | "sh" | "bash" | "zsh" | "rb" | "pl" | "pm" | "toml" | "yaml" | "yml"
+                | "r" => hash,
+                "lua" | "sql" | "hs" => Syntax {
+                    line_comment: Some("--"),
+                    ..hash
+                },
+                _ => return None,
+            };
+        Some(syntax)
+    }
+
+    /// Byte ranges of the comments and strings in `text`, in order. Strings
+    /// end at the end of their line if they aren't closed.
+    pub fn literals(self, text: &str) -> Vec<Range<usize>> {
+        let mut literals = Vec::new();
+        let line_end = |from: usize| text[from..].find('\n').map_or(text.len(), |i| from + i);
+        let mut i = 0;
+        while i < text.len() {
+            let rest = &text[i..];
+            let Some(ch) = rest.chars().next() else {
+                break;
+            };
+
+            let end = if let Some(marker) = self.line_comment.filter(|m| rest.starts_with(m)) {
+                line_end(i + marker.len())
+            } else if let Some((open, close)) = self
+                .block_comment
+                .filter(|(open, _)| rest.starts_with(open))
+            {
+                let body = i + open.len();
+                text[body..]
+                    .find(close)
+                    .map_or(text.len(), |j| body + j + close.len())
+            } else if self.quotes.contains(ch) {
+                closing_quote(text, i + 1, ch, line_end(i + 1))
+            } else if self.char_literals && ch == '\'' {
+                match char_literal_end(rest) {
+                    Some(len) => i + len,
+                    None => {
+                        i += 1;
+                        continue;
+                    }
+                }
+            } else {
+                i += ch.len_utf8();
+                continue;
+            };
+
+            literals.push(i..end);
+            i = end;
+        }
+        literals
+    }
+}
+
+/// End of a string closed by `quote` after `from`, skipping escaped quotes,
+/// or `limit` if it isn't closed before.
+fn closing_quote(text: &str, from: usize, quote: char, limit: usize) -> usize {
+    let mut escaped = false;
+    for (i, ch) in text[from..limit].char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if ch == quote => return from + i + ch.len_utf8(),
+            _ => {}
+        }
+    }
+    limit
+}
+
+/// Length of the character literal `rest` starts with, such as `'x'` or
+/// `'\n'`, if it is one.
+fn char_literal_end(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1);
+    let (_, ch) = chars.next()?;
+    if ch == '\\' {
+        // Escapes such as `'\u{1F600}'` are short.
+        return rest[2..]
+            .char_indices()
+            .skip(1)
+            .take(10)
+            .find(|&(_, ch)| ch == '\'' || ch == '\n')
+            .filter(|&(_, ch)| ch == '\'')
+            .map(|(i, _)| 2 + i + 1);
+    }
+    match chars.next() {
+        Some((i, '\'')) if ch != '\'' => Some(i + 1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literals<'a>(file_name: &str, text: &'a str) -> Vec<&'a str> {
+        let syntax = Syntax::for_file(file_name).unwrap();
+        syntax
+            .literals(text)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect()
+    }
+
+    #[test]
+    fn finds_strings_and_comments() {
+        let rust = "f(\"(\\\"\", '(', b'\\'') // )\nfn g<'a>(x: &'a str) /* {\n} */";
+        assert_eq!(
+            literals("main.rs", rust),
+            vec!["\"(\\\"\"", "'('", "'\\''", "// )", "/* {\n} */"]
+        );
+        assert_eq!(
+            literals("a.py", "x = '(' # )\ny = \"unclosed\nz"),
+            vec!["'('", "# )", "\"unclosed"]
+        );
+        assert!(Syntax::for_file("notes.txt").is_none());
+    }
+}
@@ -0,0 +1,524 @@
+use crate::{
+    buffer::Buffer,
+    cursor::Position,
+    motion::{Motion, Screen, WordChars},
+    syntax::Syntax,
+    visual::{Selection, SelectionKind},
+};
+use regex::Regex;
+use std::ops::Range;
+
+/// What `i` or `a` selects after an operator or in visual mode: only the
+/// object, or the object with the blanks or delimiters around it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextObject {
+    /// `w` / `W`: a word, or WORD if `big`.
+    Word { big: bool },
+    /// `s`
+    Sentence,
+    /// `p`: lines up to an empty line.
+    Paragraph,
+    /// `"`, `'` or `` ` ``: a quoted string on the cursor's line.
+    Quote(char),
+    /// `(`, `{`, `[` or `<`, or their closing brackets, and `b` for `(` and
+    /// `B` for `{`.
+    Bracket { open: char, close: char },
+    /// `t`: between an XML or HTML tag and its closing tag.
+    Tag,
+}
+
+impl TextObject {
+    /// The object typed after `i` or `a`.
+    pub fn parse(name: char) -> Option<Self> {
+        let bracket = |open, close| TextObject::Bracket { open, close };
+        let object = match name {
+            'w' | 'W' => TextObject::Word { big: name == 'W' },
+            's' => TextObject::Sentence,
+            'p' => TextObject::Paragraph,
+            '"' | '\'' | '`' => TextObject::Quote(name),
+            '(' | ')' | 'b' => bracket('(', ')'),
+            '{' | '}' | 'B' => bracket('{', '}'),
+            '[' | ']' => bracket('[', ']'),
+            '<' | '>' => bracket('<', '>'),
+            't' => TextObject::Tag,
+            _ => return None,
+        };
+        Some(object)
+    }
+
+    /// The object at `pos`, with what is around it if `around`. A `count`
+    /// takes more words, sentences or paragraphs, or the brackets and tags
+    /// that many levels out. `None` if there is no such object or it is empty.
+    pub fn select(
+        self,
+        buffer: &Buffer,
+        words: WordChars,
+        syntax: Option<Syntax>,
+        pos: Position,
+        around: bool,
+        count: Option<usize>,
+    ) -> Option<Selection> {
+        let n = count.unwrap_or(1).max(1);
+        let line = buffer.get(pos.row);
+        let on_line = |range: Range<usize>| {
+            let start = buffer.pos_to_offset(pos.row, range.start);
+            chars(buffer, start..buffer.pos_to_offset(pos.row, range.end))
+        };
+
+        match self {
+            TextObject::Word { big } => {
+                let mut range = word(&line, pos.col, words, big, around)?;
+                for _ in 1..n {
+                    match word(&line, range.end, words, big, around) {
+                        Some(next) if next.end > range.end => range.end = next.end,
+                        _ => break,
+                    }
+                }
+                on_line(range)
+            }
+            TextObject::Sentence => sentence(buffer, words, pos, around, n),
+            TextObject::Paragraph => paragraph(buffer, pos.row, around, n),
+            TextObject::Quote(quote) => on_line(quoted(&line, pos.col, quote, around)?),
+            TextObject::Bracket { open, close } => {
+                let text = buffer.text();
+                let cursor = buffer.pos_to_offset(pos.row, pos.col.min(line.len()));
+                let literals = syntax.map_or_else(Vec::new, |syntax| syntax.literals(&text));
+                // In a string or comment, brackets in it come first.
+                let inside = literals.iter().find(|range| range.contains(&cursor));
+                let (open_at, close_at) = inside
+                    .and_then(|range| {
+                        let cursor = cursor - range.start;
+                        let (open_at, close_at) =
+                            brackets(&text[range.clone()], cursor, open, close, &[], n)?;
+                        Some((range.start + open_at, range.start + close_at))
+                    })
+                    .or_else(|| brackets(&text, cursor, open, close, &literals, n))?;
+                if around {
+                    return chars(buffer, open_at..close_at + 1);
+                }
+                inner_brackets(buffer, &text, open_at, close_at)
+            }
+            TextObject::Tag => {
+                let text = buffer.text();
+                let cursor = buffer.pos_to_offset(pos.row, pos.col.min(line.len()));
+                chars(buffer, tag(&text, cursor, around, n)?)
+            }
+        }
+    }
+}
+
+/// Characterwise selection of the bytes in `range`, or `None` if it's empty.
+fn chars(buffer: &Buffer, range: Range<usize>) -> Option<Selection> {
+    if range.is_empty() {
+        return None;
+    }
+    let (row, col) = buffer.offset_to_pos(range.start);
+    let start = Position { row, col };
+    // The end is the start of the last character; a line break ends at the
+    // end of its line.
+    let (row, col) = buffer.offset_to_pos(range.end - 1);
+    let line = buffer.get(row);
+    let col = (0..=col.min(line.len()))
+        .rev()
+        .find(|&i| line.is_char_boundary(i))
+        .unwrap_or(0);
+    let end = Position { row, col };
+    Some(Selection {
+        kind: SelectionKind::Char,
+        start,
+        end,
+    })
+}
+
+/// Byte range of the run of characters of one class at `col`, and of the
+/// blanks after it, or before it if there are none after, if `around`.
+/// Around blanks, the word after them is taken instead.
+fn word(line: &str, col: usize, words: WordChars, big: bool, around: bool) -> Option<Range<usize>> {
+    let class = |ch| words.class(ch, big);
+    let col = line.char_indices().take_while(|&(i, _)| i <= col).last()?.0;
+    let class_at = |at: usize| line[at..].chars().next().map(class);
+    let run = |at: usize| {
+        let kind = class_at(at);
+        let start = line[..at]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, ch)| Some(class(ch)) == kind)
+            .last()
+            .map_or(at, |(i, _)| i);
+        let end = line[at..]
+            .char_indices()
+            .find(|&(_, ch)| Some(class(ch)) != kind)
+            .map_or(line.len(), |(i, _)| at + i);
+        start..end
+    };
+
+    let range = run(col);
+    if !around {
+        return Some(range);
+    }
+    if class_at(col) == Some(0) || class_at(range.end) == Some(0) {
+        if range.end < line.len() {
+            return Some(range.start..run(range.end).end);
+        }
+    } else if let Some((before, ch)) = line[..range.start].char_indices().last()
+        && class(ch) == 0
+    {
+        return Some(run(before).start..range.end);
+    }
+    Some(range)
+}
+
+/// The sentence at `pos` and `n - 1` after it, with the blanks after them on
+/// their line, or before them if there are none after, if `around`.
+fn sentence(
+    buffer: &Buffer,
+    words: WordChars,
+    pos: Position,
+    around: bool,
+    n: usize,
+) -> Option<Selection> {
+    let screen = Screen { top: 0, height: 1 };
+    let next = Motion::SentenceForward.target(buffer, words, screen, pos, Some(n))?;
+    let first = Motion::SentenceForward.target(buffer, words, screen, pos, None)?;
+    let start = Motion::SentenceBackward.target(buffer, words, screen, first, None)?;
+
+    let text = buffer.text();
+    let start = buffer.pos_to_offset(start.row, start.col);
+    let end = buffer.pos_to_offset(next.row, next.col);
+    let inner_end = start + text[start..end].trim_end().len();
+    if !around {
+        return chars(buffer, start..inner_end);
+    }
+    let after = text[inner_end..end].split('\n').next().map_or(0, str::len);
+    if after == 0 {
+        let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+        let blanks = text[line_start..start].trim_end().len();
+        return chars(buffer, line_start + blanks..inner_end);
+    }
+    chars(buffer, start..inner_end + after)
+}
+
+/// Lines of the paragraph at `row`, or of the empty lines there, and of the
+/// `n - 1` after it. With `around`, the empty lines after them go with them,
+/// or those before if there are none after.
+fn paragraph(buffer: &Buffer, row: usize, around: bool, n: usize) -> Option<Selection> {
+    let last_row = buffer.len() - 1;
+    let empty = |row: usize| buffer.len_of(row) == 0;
+    let run = |row: usize| {
+        let first = (0..row)
+            .rev()
+            .take_while(|&r| empty(r) == empty(row))
+            .last()
+            .unwrap_or(row);
+        let last = (row + 1..=last_row)
+            .take_while(|&r| empty(r) == empty(row))
+            .last()
+            .unwrap_or(row);
+        (first, last)
+    };
+
+    let (mut first, mut last) = run(row);
+    let runs = if around { n.saturating_mul(2) } else { n };
+    for _ in 1..runs {
+        if last == last_row {
+            break;
+        }
+        last = run(last + 1).1;
+    }
+    // `ap` at the end of the text takes the empty lines before it instead.
+    if around && last == run(row).1 && !empty(row) && first > 0 {
+        first = run(first - 1).0;
+    }
+
+    Some(Selection {
+        kind: SelectionKind::Line,
+        start: Position { row: first, col: 0 },
+        end: Position { row: last, col: 0 },
+    })
+}
+
+/// Byte range inside the pair of `quote`s around `col` in `line`, or the next
+/// pair after it. With `around`, the quotes and the blanks after them, or
+/// before them if there are none after, are taken too.
+fn quoted(line: &str, col: usize, quote: char, around: bool) -> Option<Range<usize>> {
+    let mut quotes = Vec::new();
+    let mut escaped = false;
+    for (i, ch) in line.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if ch == quote => quotes.push(i),
+            _ => {}
+        }
+    }
+
+    let pairs = quotes.chunks_exact(2).map(|pair| (pair[0], pair[1]));
+    let (open, close) = pairs
+        .clone()
+        .find(|&(open, close)| open <= col && col <= close)
+        .or_else(|| pairs.clone().find(|&(open, _)| open > col))?;
+    let end = close + quote.len_utf8();
+    if !around {
+        return Some(open + quote.len_utf8()..close);
+    }
+
+    let after = line[end..].len() - line[end..].trim_start().len();
+    if after > 0 {
+        Some(open..end + after)
+    } else {
+        Some(line[..open].trim_end().len()..end)
+    }
+}
+
+/// Byte offsets of the `n`th pair of brackets out from `cursor`, leaving out
+/// those in `literals`.
+fn brackets(
+    text: &str,
+    cursor: usize,
+    open: char,
+    close: char,
+    literals: &[Range<usize>],
+    n: usize,
+) -> Option<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let (open, close) = (open as u8, close as u8);
+    let in_code = |i: usize| {
+        let after = literals.partition_point(|range| range.end <= i);
+        literals.get(after).is_none_or(|range| !range.contains(&i))
+    };
+    let find_open = |before: usize| {
+        let mut depth = 0;
+        for i in (0..before).rev().filter(|&i| in_code(i)) {
+            if bytes[i] == close {
+                depth += 1;
+            } else if bytes[i] == open {
+                if depth == 0 {
+                    return Some(i);
+                }
+                depth -= 1;
+            }
+        }
+        None
+    };
+    let find_close = |after: usize| {
+        let mut depth = 0;
+        for i in (after..bytes.len()).filter(|&i| in_code(i)) {
+            if bytes[i] == open {
+                depth += 1;
+            } else if bytes[i] == close {
+                if depth == 0 {
+                    return Some(i);
+                }
+                depth -= 1;
+            }
+        }
+        None
+    };
+
+    let mut open_at = if bytes.get(cursor) == Some(&open) {
+        cursor
+    } else {
+        find_open(cursor)?
+    };
+    for _ in 1..n {
+        open_at = find_open(open_at)?;
+    }
+    Some((open_at, find_close(open_at + 1)?))
+}
+
+/// Inside the brackets at `open` and `close`. When they are on lines of their
+/// own, as a block's, the lines between them are taken whole.
+fn inner_brackets(buffer: &Buffer, text: &str, open: usize, close: usize) -> Option<Selection> {
+    let (open_row, _) = buffer.offset_to_pos(open);
+    let (close_row, close_col) = buffer.offset_to_pos(close);
+    let ends_line = text[open + 1..]
+        .trim_start_matches([' ', '\t'])
+        .starts_with('\n');
+    let starts_line = buffer.get(close_row)[..close_col].trim().is_empty();
+    if ends_line && starts_line && close_row > open_row {
+        if close_row == open_row + 1 {
+            return None;
+        }
+        return Some(Selection {
+            kind: SelectionKind::Line,
+            start: Position {
+                row: open_row + 1,
+                col: 0,
+            },
+            end: Position {
+                row: close_row - 1,
+                col: 0,
+            },
+        });
+    }
+    chars(buffer, open + 1..close)
+}
+
+/// Byte range of the `n`th tag pair out from `cursor` in `text`, or of what
+/// is between its tags if not `around`.
+fn tag(text: &str, cursor: usize, around: bool, n: usize) -> Option<Range<usize>> {
+    let regex = Regex::new(r"<(/?)([A-Za-z][\w:.-]*)[^<>]*?(/?)>").unwrap();
+    let mut open_tags: Vec<(&str, Range<usize>)> = Vec::new();
+    let mut pairs = Vec::new();
+    for captures in regex.captures_iter(text) {
+        let range = captures.get(0).unwrap().range();
+        let name = captures.get(2).unwrap().as_str();
+        if !captures[3].is_empty() {
+            continue;
+        }
+        if captures[1].is_empty() {
+            open_tags.push((name, range));
+        } else if let Some(i) = open_tags.iter().rposition(|(open, _)| *open == name) {
+            // Tags left open inside, such as `<br>`, close with it.
+            let open = open_tags[i].1.clone();
+            open_tags.truncate(i);
+            pairs.push((open, range));
+        }
+    }
+
+    let mut enclosing: Vec<_> = pairs
+        .into_iter()
+        .filter(|(open, close)| open.start <= cursor && cursor < close.end)
+        .collect();
+    enclosing.sort_by_key(|(open, close)| close.end - open.start);
+    let (open, close) = enclosing.get(n - 1)?;
+    Some(if around {
+        open.start..close.end
+    } else {
+        open.end..close.start
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::test_util::buffer_of;
+
+    /// The text `keys` (`iw`, `a(`...) select at `row`, `col`, with lines
+    /// ending in `|` for linewise selections.
+    fn select(text: &str, file_name: &str, keys: &str, row: usize, col: usize) -> Option<String> {
+        let buffer = buffer_of(text);
+        let mut chars = keys.chars();
+        let around = chars.next() == Some('a');
+        let object = TextObject::parse(chars.next().unwrap()).unwrap();
+        let words = WordChars::for_file(file_name);
+        let syntax = Syntax::for_file(file_name);
+        let pos = Position { row, col };
+        let selection = object.select(&buffer, words, syntax, pos, around, None)?;
+
+        let mut selected = String::new();
+        for row in selection.rows() {
+            let line = buffer.get(row);
+            selected.push_str(&line[selection.cols(row, &line)]);
+            if selection.kind == SelectionKind::Line {
+                selected.push('|');
+            }
+            if selection.takes_line_break(row, &line) || selection.kind == SelectionKind::Line {
+                selected.push('\n');
+            }
+        }
+        Some(selected)
+    }
+
+    #[test]
+    fn selects_words_sentences_and_paragraphs() {
+        let line = "let foo_bar = x.y;";
+        assert_eq!(select(line, "a.rs", "iw", 0, 5).as_deref(), Some("foo_bar"));
+        assert_eq!(
+            select(line, "a.rs", "aw", 0, 5).as_deref(),
+            Some("foo_bar ")
+        );
+        assert_eq!(select(line, "a.rs", "aw", 0, 16).as_deref(), Some("y"));
+        assert_eq!(select(line, "a.rs", "aW", 0, 15).as_deref(), Some(" x.y;"));
+        assert_eq!(select(line, "a.rs", "iw", 0, 3).as_deref(), Some(" "));
+        assert_eq!(
+            select(line, "a.rs", "aw", 0, 3).as_deref(),
+            Some(" foo_bar")
+        );
+        assert_eq!(select("", "a.rs", "iw", 0, 0), None);
+
+        let text = "One. Two and\nthree.  Four!\n\nNext para.";
+        assert_eq!(
+            select(text, "a.txt", "is", 0, 7).as_deref(),
+            Some("Two and\nthree.")
+        );
+        assert_eq!(
+            select(text, "a.txt", "as", 0, 7).as_deref(),
+            Some("Two and\nthree.  ")
+        );
+        assert_eq!(
+            select(text, "a.txt", "as", 1, 9).as_deref(),
+            Some("  Four!")
+        );
+
+        assert_eq!(
+            select(text, "a.txt", "ip", 1, 0).as_deref(),
+            Some("One. Two and|\nthree.  Four!|\n")
+        );
+        assert_eq!(
+            select(text, "a.txt", "ap", 0, 0).as_deref(),
+            Some("One. Two and|\nthree.  Four!|\n|\n")
+        );
+        assert_eq!(
+            select(text, "a.txt", "ap", 3, 0).as_deref(),
+            Some("|\nNext para.|\n")
+        );
+    }
+
+    #[test]
+    fn selects_quotes_brackets_and_tags() {
+        let line = r#"say("a \"b\"", 'c') "#;
+        assert_eq!(
+            select(line, "a.py", "i\"", 0, 6).as_deref(),
+            Some(r#"a \"b\""#)
+        );
+        assert_eq!(
+            select(line, "a.py", "a\"", 0, 0).as_deref(),
+            Some(r#""a \"b\"""#)
+        );
+        assert_eq!(select(line, "a.py", "a'", 0, 16).as_deref(), Some(" 'c'"));
+        assert_eq!(
+            select(line, "a.py", "i(", 0, 17).as_deref(),
+            Some(r#""a \"b\"", 'c'"#)
+        );
+        assert_eq!(select(line, "a.py", "i[", 0, 5), None);
+
+        let code = "fn f(x: &'a str) {\n    if x == \"}\" { g(); } // {\n}";
+        assert_eq!(
+            select(code, "a.rs", "i{", 1, 4).as_deref(),
+            Some("    if x == \"}\" { g(); } // {|\n")
+        );
+        assert_eq!(
+            select(code, "a.rs", "a}", 1, 21).as_deref(),
+            Some("{ g(); }")
+        );
+        assert_eq!(
+            select(code, "a.rs", "ib", 0, 4).as_deref(),
+            Some("x: &'a str")
+        );
+        // Without syntax, the brace in the string closes the block.
+        assert_eq!(
+            select(code, "a.txt", "aB", 1, 4).as_deref(),
+            Some("{\n    if x == \"}")
+        );
+        assert_eq!(
+            select(code, "a.rs", "a{", 1, 27).as_deref(),
+            Some(&code[17..])
+        );
+        assert_eq!(select("// (a) b", "a.rs", "i(", 0, 4).as_deref(), Some("a"));
+
+        let html = "<ul>\n  <li>one <br> <b>two</b></li>\n</ul>";
+        assert_eq!(
+            select(html, "a.html", "it", 1, 12).as_deref(),
+            Some("one <br> <b>two</b>")
+        );
+        assert_eq!(
+            select(html, "a.html", "at", 1, 20).as_deref(),
+            Some("<b>two</b>")
+        );
+        assert_eq!(
+            select(html, "a.html", "it", 0, 1).as_deref(),
+            Some("\n  <li>one <br> <b>two</b></li>\n")
+        );
+    }
+}
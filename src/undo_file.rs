@@ -0,0 +1,132 @@
+use crate::{file_io, history::History, logger::Logger};
+use anyhow::{Context as AnyhowContext, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+const VERSION: u32 = 1;
+
+/// On-disk form of an undo history. `hash` is the hash of the text the history
+/// ends in, so it is only restored onto an identical file.
+#[derive(Deserialize)]
+struct UndoFile {
+    version: u32,
+    hash: u64,
+    history: History,
+}
+
+/// 64-bit FNV-1a hash, stable across builds unlike `DefaultHasher`.
+pub fn content_hash(chunks: impl IntoIterator<Item = impl AsRef<[u8]>>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for chunk in chunks {
+        for &byte in chunk.as_ref() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+pub fn save(file_path: &str, hash: u64, history: &History) -> Result<()> {
+    let undo_path = undo_path(file_path)?;
+    if let Some(dir) = undo_path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create undo directory. {dir:?}"))?;
+    }
+
+    let f = File::create(&undo_path)
+        .with_context(|| format!("Failed to create undo file. {undo_path:?}"))?;
+    let undo_file = UndoFileRef {
+        version: VERSION,
+        hash,
+        history,
+    };
+
+    let mut writer = BufWriter::new(f);
+    serde_json::to_writer(&mut writer, &undo_file)
+        .with_context(|| format!("Failed to write undo file. {undo_path:?}"))?;
+    writer
+        .flush()
+        .with_context(|| format!("Failed to flush undo file. {undo_path:?}"))?;
+
+    Ok(())
+}
+
+/// Loads the history saved for `file_path`. Histories of a file that changed
+/// since, and unreadable or corrupt undo files, are discarded.
+pub fn load(file_path: &str, hash: u64) -> Option<History> {
+    let undo_path = undo_path(file_path).ok()?;
+    let f = File::open(&undo_path).ok()?;
+
+    let undo_file: UndoFile = match serde_json::from_reader(BufReader::new(f)) {
+        Ok(undo_file) => undo_file,
+        Err(e) => {
+            Logger::log(format!("[undo_file] Corrupt undo file {undo_path:?}: {e}"));
+            let _ = fs::remove_file(&undo_path);
+            return None;
+        }
+    };
+
+    if undo_file.version != VERSION || undo_file.hash != hash || !undo_file.history.is_consistent()
+    {
+        Logger::log(format!("[undo_file] Stale undo file {undo_path:?}"));
+        let _ = fs::remove_file(&undo_path);
+        return None;
+    }
+
+    Some(undo_file.history)
+}
+
+/// Borrowed counterpart of `UndoFile`, so saving doesn't clone the tree.
+#[derive(Serialize)]
+struct UndoFileRef<'a> {
+    version: u32,
+    hash: u64,
+    history: &'a History,
+}
+
+fn undo_path(file_path: &str) -> Result<PathBuf> {
+    file_io::state_path("undo", file_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cursor::Position, history::Edit};
+
+    #[test]
+    fn history_round_trips_through_json() {
+        let origin = Position { row: 0, col: 0 };
+        let mut history = History::new();
+        history.record(
+            Edit::Insert {
+                offset: 0,
+                text: String::from("a\nb"),
+            },
+            origin,
+        );
+        history.commit(origin);
+
+        let json = serde_json::to_string(&UndoFileRef {
+            version: VERSION,
+            hash: content_hash(["a\n", "b"].into_iter()),
+            history: &history,
+        })
+        .unwrap();
+        let undo_file: UndoFile = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(undo_file.hash, content_hash(["a\nb"].into_iter()));
+        assert!(undo_file.history.is_consistent());
+        assert_eq!(undo_file.history.seq(), 1);
+    }
+
+    #[test]
+    fn corrupt_links_are_rejected() {
+        let json = r#"{"nodes":[{"parent":0,"cur_child":5,"children":[5],"change":null,"time":{"secs_since_epoch":0,"nanos_since_epoch":0}}],"current":0}"#;
+        let history: History = serde_json::from_str(json).unwrap();
+        assert!(!history.is_consistent());
+    }
+}
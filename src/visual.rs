@@ -0,0 +1,156 @@
+use crate::{cursor::Position, state::Mode};
+use std::ops::{Range, RangeInclusive};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionKind {
+    /// `v`: from one character to another, across line breaks.
+    Char,
+    /// `V`: whole lines.
+    Line,
+    /// `Ctrl-V`: the same columns on consecutive lines.
+    Block,
+}
+
+/// Text selected in a visual mode, between the anchor where it started and
+/// the cursor. Columns are byte columns, as the cursor's are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selection {
+    pub kind: SelectionKind,
+    /// First selected position. For blocks, the top left corner.
+    pub start: Position,
+    /// Last selected position, included. For blocks, the bottom right corner.
+    pub end: Position,
+}
+
+impl Selection {
+    /// The selection of `mode`, or `None` if it isn't a visual mode.
+    pub fn new(mode: Mode, anchor: Position, cursor: Position) -> Option<Self> {
+        let kind = match mode {
+            Mode::Visual => SelectionKind::Char,
+            Mode::VisualLine => SelectionKind::Line,
+            Mode::VisualBlock => SelectionKind::Block,
+            _ => return None,
+        };
+
+        let (start, end) = if kind == SelectionKind::Block {
+            (
+                Position {
+                    row: anchor.row.min(cursor.row),
+                    col: anchor.col.min(cursor.col),
+                },
+                Position {
+                    row: anchor.row.max(cursor.row),
+                    col: anchor.col.max(cursor.col),
+                },
+            )
+        } else if (anchor.row, anchor.col) <= (cursor.row, cursor.col) {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        };
+
+        Some(Self { kind, start, end })
+    }
+
+    pub fn rows(&self) -> RangeInclusive<usize> {
+        self.start.row..=self.end.row
+    }
+
+    /// Byte columns selected in `line`, the text of `row`. Line breaks are
+    /// left out; see `takes_line_break`.
+    pub fn cols(&self, row: usize, line: &str) -> Range<usize> {
+        if !self.rows().contains(&row) {
+            return line.len()..line.len();
+        }
+
+        match self.kind {
+            SelectionKind::Line => 0..line.len(),
+            SelectionKind::Char => {
+                let start = if row == self.start.row {
+                    floor_char(line, self.start.col)
+                } else {
+                    0
+                };
+                let end = if row == self.end.row {
+                    char_end(line, self.end.col)
+                } else {
+                    line.len()
+                };
+                start..end.max(start)
+            }
+            SelectionKind::Block => {
+                let start = floor_char(line, self.start.col);
+                start..char_end(line, self.end.col).max(start)
+            }
+        }
+    }
+
+    /// Whether the line break after `row`, whose text is `line`, is selected:
+    /// on every line but the last of a characterwise selection, and on the
+    /// last too if the selection goes past its end.
+    pub fn takes_line_break(&self, row: usize, line: &str) -> bool {
+        self.kind == SelectionKind::Char
+            && self.rows().contains(&row)
+            && (row < self.end.row || self.end.col >= line.len())
+    }
+}
+
+/// Block `I` or `A` in progress: what gets typed at `start` is repeated on
+/// the rows below it down to `last_row` when insert mode ends.
+pub struct BlockInsert {
+    pub start: Position,
+    pub last_row: usize,
+    /// Pad shorter lines to the column, as `A` does, instead of skipping them.
+    pub pad: bool,
+}
+
+/// `col`, or the start of the character it falls in.
+fn floor_char(line: &str, col: usize) -> usize {
+    let col = col.min(line.len());
+    (0..=col)
+        .rev()
+        .find(|&i| line.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+/// End of the character at `col`, or of the line past it.
+fn char_end(line: &str, col: usize) -> usize {
+    let col = floor_char(line, col);
+    line[col..]
+        .chars()
+        .next()
+        .map_or(col, |ch| col + ch.len_utf8())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(row: usize, col: usize) -> Position {
+        Position { row, col }
+    }
+
+    #[test]
+    fn selects_characters_lines_and_blocks() {
+        let chars = Selection::new(Mode::Visual, at(2, 1), at(0, 3)).unwrap();
+        assert_eq!((chars.start, chars.end), (at(0, 3), at(2, 1)));
+        assert_eq!(chars.cols(0, "abcdef"), 3..6);
+        assert_eq!(chars.cols(1, "xy"), 0..2);
+        assert_eq!(chars.cols(2, "héllo"), 0..3);
+        assert_eq!(chars.cols(3, "out"), 3..3);
+        assert!(chars.takes_line_break(1, "xy"));
+        assert!(!chars.takes_line_break(2, "héllo"));
+
+        let lines = Selection::new(Mode::VisualLine, at(1, 4), at(1, 0)).unwrap();
+        assert_eq!(lines.cols(1, "whole"), 0..5);
+        assert!(!lines.takes_line_break(1, "whole"));
+
+        let block = Selection::new(Mode::VisualBlock, at(0, 3), at(2, 1)).unwrap();
+        assert_eq!((block.start, block.end), (at(0, 1), at(2, 3)));
+        assert_eq!(block.cols(1, "abcdef"), 1..4);
+        assert_eq!(block.cols(1, "ab"), 1..2);
+        assert_eq!(block.cols(1, ""), 0..0);
+
+        assert_eq!(Selection::new(Mode::Normal, at(0, 0), at(0, 0)), None);
+    }
+}